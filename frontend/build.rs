@@ -0,0 +1,33 @@
+//! Builds the runtime that compiled skipp programs are linked against.
+//!
+//! skippc depends on `skipp-rt` as a Rust library for the JIT, but Cargo doesn't tell a crate
+//! where its dependencies' static libraries are, so the runtime is built again on its own, always
+//! optimized, into `OUT_DIR`.
+
+use std::env;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+fn main() {
+    let manifest_dir = PathBuf::from(env::var("CARGO_MANIFEST_DIR").unwrap());
+    let runtime_dir = manifest_dir.join("skipp-rt");
+    println!("cargo:rerun-if-changed={}", runtime_dir.display());
+
+    let target_dir = Path::new(&env::var("OUT_DIR").unwrap()).join("skipp-rt");
+    let cargo = env::var("CARGO").unwrap_or_else(|_| String::from("cargo"));
+    let status = Command::new(cargo)
+        .args(["build", "--release", "--lib", "--manifest-path"])
+        .arg(runtime_dir.join("Cargo.toml"))
+        .arg("--target-dir")
+        .arg(&target_dir)
+        // `cargo clippy` would lint the runtime a second time, where nobody sees it.
+        .env_remove("RUSTC_WORKSPACE_WRAPPER")
+        .status()
+        .expect("Unable to run cargo to build the runtime");
+    assert!(status.success(), "Unable to build the runtime");
+
+    let archive = target_dir.join("release").join("libskipp_rt.a");
+    println!("cargo:rustc-env=SKIPP_RUNTIME={}", archive.display());
+    // The runtime libraries to link depend on the target, which is the host unless `--target` says.
+    println!("cargo:rustc-env=SKIPP_HOST={}", env::var("TARGET").unwrap());
+}
@@ -0,0 +1,27 @@
+//! Memory for values that outlive the call that creates them.
+//!
+//! Everything is allocated on the garbage-collected heap of [`gc`](crate::gc).
+
+use crate::gc;
+use crate::panic::report;
+
+use std::convert::TryFrom;
+
+/// Allocate `size` zeroed bytes, stopping the program if there is no memory left.
+#[no_mangle]
+pub extern "C" fn alloc(size: i64) -> *mut u8 {
+    match usize::try_from(size) {
+        Ok(size) => gc::GC_malloc(size) as *mut u8,
+        Err(_) => report(&format!("unable to allocate {} bytes", size)),
+    }
+}
+
+/// Print what the heap holds on stderr.
+#[no_mangle]
+pub extern "C" fn show_umap() {
+    let stats = gc::stats();
+    eprintln!(
+        "heap: {} objects, {} bytes, {} collections",
+        stats.objects, stats.bytes, stats.collections
+    );
+}
@@ -0,0 +1,53 @@
+//! Helpers for `intarr`s, which are pointers to their first `i32`.
+//!
+//! An `intarr` doesn't know its length, so indices are only checked against zero.
+
+use crate::alloc::alloc;
+use crate::panic::report;
+
+/// A new array of `length` zeros.
+#[no_mangle]
+pub extern "C" fn intarr_new(length: i32) -> *mut i32 {
+    if length < 0 {
+        report(&format!("the array length {} is negative", length));
+    }
+    alloc(length as i64 * 4) as *mut i32
+}
+
+/// Element `i` of `arr`.
+#[no_mangle]
+pub unsafe extern "C" fn index_arr(arr: *const i32, i: i32) -> i32 {
+    *element(arr as *mut i32, i)
+}
+
+/// Set element `i` of `arr` to `value`, and return it.
+#[no_mangle]
+pub unsafe extern "C" fn set_arr(arr: *mut i32, i: i32, value: i32) -> i32 {
+    *element(arr, i) = value;
+    value
+}
+
+unsafe fn element(arr: *mut i32, i: i32) -> *mut i32 {
+    if arr.is_null() {
+        report("the array is null");
+    }
+    if i < 0 {
+        report(&format!("the index {} is negative", i));
+    }
+    arr.offset(i as isize)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{index_arr, intarr_new, set_arr};
+
+    #[test]
+    fn arrays_start_zeroed_and_can_be_written() {
+        unsafe {
+            let arr = intarr_new(3);
+            assert_eq!(index_arr(arr, 2), 0);
+            assert_eq!(set_arr(arr, 1, 7), 7);
+            assert_eq!(index_arr(arr, 1), 7);
+        }
+    }
+}
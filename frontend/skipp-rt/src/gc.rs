@@ -0,0 +1,282 @@
+//! A conservative mark-and-sweep garbage collector, with the interface of the Boehm collector.
+//!
+//! Compiled code doesn't record where it keeps pointers, so any word that looks like one keeps
+//! the object it points into alive. The roots are the stack and registers of the thread that
+//! collects; objects are scanned for pointers the same way.
+//!
+//! Each thread allocates from a heap of its own, which only its own stack keeps objects alive in,
+//! and which is freed when the thread exits. Programs are single-threaded, but the copy of the
+//! runtime in skippc may be used from several threads, e.g. by tests. An object must not be
+//! handed to another thread, whose stack its own heap doesn't scan.
+//!
+//! A collection runs when the bytes allocated since the last one reach a threshold, which is
+//! twice what survived the last collection and at least [`MIN_THRESHOLD`].
+
+use crate::panic::report;
+
+use std::alloc::Layout;
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::mem;
+use std::os::raw::c_void;
+
+/// The alignment of every object, enough for any skipp value.
+const ALIGNMENT: usize = 16;
+
+/// The fewest bytes allocated between two collections.
+const MIN_THRESHOLD: usize = 1 << 20;
+
+const WORD: usize = mem::size_of::<usize>();
+
+struct Object {
+    size: usize,
+    marked: bool,
+}
+
+/// The objects allocated by the collector, and when to collect them.
+pub struct Heap {
+    /// Live objects by address.
+    objects: BTreeMap<usize, Object>,
+    /// The size of all live objects.
+    bytes: usize,
+    /// Bytes allocated since the last collection.
+    allocated: usize,
+    threshold: usize,
+    collections: usize,
+}
+
+/// What [`show_umap`](crate::alloc::show_umap) reports about a [`Heap`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Stats {
+    pub objects: usize,
+    pub bytes: usize,
+    pub collections: usize,
+}
+
+thread_local! {
+    static HEAP: RefCell<Heap> = const { RefCell::new(Heap::new()) };
+}
+
+/// Call `f` with the heap of the current thread.
+fn with_heap<T>(f: impl FnOnce(&mut Heap) -> T) -> T {
+    HEAP.with(|heap| f(&mut heap.borrow_mut()))
+}
+
+impl Heap {
+    pub const fn new() -> Heap {
+        Heap {
+            objects: BTreeMap::new(),
+            bytes: 0,
+            allocated: 0,
+            threshold: MIN_THRESHOLD,
+            collections: 0,
+        }
+    }
+
+    /// Allocate `size` zeroed bytes, or return null if there is no memory left.
+    pub fn allocate(&mut self, size: usize) -> *mut u8 {
+        let layout = match Layout::from_size_align(size.max(1), ALIGNMENT) {
+            Ok(layout) => layout,
+            Err(_) => return std::ptr::null_mut(),
+        };
+        let pointer = unsafe { std::alloc::alloc_zeroed(layout) };
+        if !pointer.is_null() {
+            let size = layout.size();
+            self.objects.insert(
+                pointer as usize,
+                Object {
+                    size,
+                    marked: false,
+                },
+            );
+            self.bytes += size;
+            self.allocated += size;
+        }
+        pointer
+    }
+
+    /// Whether enough was allocated since the last collection to collect again.
+    fn should_collect(&self) -> bool {
+        self.allocated >= self.threshold
+    }
+
+    /// Free every object that isn't reachable from a word in `roots`.
+    ///
+    /// # Safety
+    /// Every range in `roots` must be readable.
+    pub unsafe fn collect(&mut self, roots: &[(usize, usize)]) {
+        let mut pending = Vec::new();
+        for &(start, end) in roots {
+            self.mark_range(start, end, &mut pending);
+        }
+        while let Some((start, size)) = pending.pop() {
+            self.mark_range(start, start + size, &mut pending);
+        }
+
+        let mut freed = Vec::new();
+        self.objects.retain(|&address, object| {
+            let live = object.marked;
+            object.marked = false;
+            if !live {
+                freed.push((address, object.size));
+            }
+            live
+        });
+        for (address, size) in freed {
+            std::alloc::dealloc(
+                address as *mut u8,
+                Layout::from_size_align_unchecked(size, ALIGNMENT),
+            );
+            self.bytes -= size;
+        }
+        self.allocated = 0;
+        self.threshold = (2 * self.bytes).max(MIN_THRESHOLD);
+        self.collections += 1;
+    }
+
+    /// Mark the objects the aligned words in `start..end` point into, and queue them to be
+    /// scanned in turn.
+    unsafe fn mark_range(&mut self, start: usize, end: usize, pending: &mut Vec<(usize, usize)>) {
+        let mut word = (start + WORD - 1) & !(WORD - 1);
+        while word + WORD <= end {
+            let value = std::ptr::read_volatile(word as *const usize);
+            if let Some((&address, object)) = self.objects.range_mut(..=value).next_back() {
+                if value < address + object.size && !object.marked {
+                    object.marked = true;
+                    pending.push((address, object.size));
+                }
+            }
+            word += WORD;
+        }
+    }
+
+    pub fn stats(&self) -> Stats {
+        Stats {
+            objects: self.objects.len(),
+            bytes: self.bytes,
+            collections: self.collections,
+        }
+    }
+}
+
+impl Default for Heap {
+    fn default() -> Self {
+        Heap::new()
+    }
+}
+
+/// Nothing is left to point to the objects of a heap that goes away with its thread.
+impl Drop for Heap {
+    fn drop(&mut self) {
+        for (&address, object) in &self.objects {
+            unsafe {
+                std::alloc::dealloc(
+                    address as *mut u8,
+                    Layout::from_size_align_unchecked(object.size, ALIGNMENT),
+                )
+            };
+        }
+    }
+}
+
+/// Collect `heap` with the current thread's stack and registers as the roots.
+#[inline(never)]
+fn collect_from_stack(heap: &mut Heap) {
+    unsafe {
+        // Saving the registers on the stack makes the pointers held in them roots too.
+        let mut registers: libc::ucontext_t = mem::zeroed();
+        libc::getcontext(&mut registers);
+        let top = &registers as *const libc::ucontext_t as usize;
+        heap.collect(&[(top, stack_bottom())]);
+        std::hint::black_box(&registers);
+    }
+}
+
+/// The highest address of the current thread's stack, which grows down from it.
+unsafe fn stack_bottom() -> usize {
+    let mut attributes: libc::pthread_attr_t = mem::zeroed();
+    let mut address = std::ptr::null_mut();
+    let mut size = 0;
+    if libc::pthread_getattr_np(libc::pthread_self(), &mut attributes) != 0
+        || libc::pthread_attr_getstack(&attributes, &mut address, &mut size) != 0
+    {
+        report("unable to find the stack to collect garbage");
+    }
+    libc::pthread_attr_destroy(&mut attributes);
+    address as usize + size
+}
+
+/// What the current thread's heap holds.
+pub fn stats() -> Stats {
+    with_heap(|heap| heap.stats())
+}
+
+/// Allocate `size` zeroed bytes that are freed once nothing points to them, stopping the program
+/// if there is no memory left.
+#[no_mangle]
+pub extern "C" fn GC_malloc(size: usize) -> *mut c_void {
+    let pointer = with_heap(|heap| {
+        if heap.should_collect() {
+            collect_from_stack(heap);
+        }
+        heap.allocate(size)
+    });
+    if pointer.is_null() {
+        report(&format!("out of memory allocating {} bytes", size));
+    }
+    pointer as *mut c_void
+}
+
+/// Collect garbage now.
+#[no_mangle]
+pub extern "C" fn GC_gcollect() {
+    with_heap(collect_from_stack);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{stats, GC_malloc, Heap, Stats};
+
+    fn root(pointer: &*mut u8) -> (usize, usize) {
+        let start = pointer as *const *mut u8 as usize;
+        (start, start + std::mem::size_of::<*mut u8>())
+    }
+
+    #[test]
+    fn only_reachable_objects_survive() {
+        let mut heap = Heap::new();
+        let outer = heap.allocate(16);
+        let inner = heap.allocate(8);
+        let garbage = heap.allocate(32);
+        unsafe {
+            // `outer` points to the middle of `inner`, which keeps all of it alive.
+            *(outer as *mut usize) = inner as usize + 4;
+            *(garbage as *mut usize) = outer as usize;
+            heap.collect(&[root(&outer)]);
+        }
+        assert_eq!(
+            heap.stats(),
+            Stats {
+                objects: 2,
+                bytes: 24,
+                collections: 1
+            }
+        );
+
+        unsafe { heap.collect(&[]) };
+        assert_eq!(heap.stats().objects, 0);
+        assert_eq!(heap.stats().bytes, 0);
+    }
+
+    #[test]
+    fn threads_have_heaps_of_their_own() {
+        GC_malloc(8);
+        let other = std::thread::spawn(|| {
+            GC_malloc(8);
+            GC_malloc(8);
+            stats().objects
+        });
+        assert_eq!(other.join().unwrap(), 2);
+        assert_eq!(stats().objects, 1);
+    }
+}
@@ -0,0 +1,59 @@
+//! The runtime library of skipp.
+//!
+//! Compiled programs are linked against its static library, and programs run by the JIT call
+//! into the same functions through [`symbols`]. Every function uses the C calling convention and
+//! is exported under its own name, so a program reaches it by declaring an `extern` with a
+//! matching signature, e.g. `extern println_str(s: str) -> void;`.
+//!
+//! Skipp types are passed as their C counterparts:
+//!
+//! | skipp    | C             |
+//! |----------|---------------|
+//! | `i32`    | `int32_t`     |
+//! | `i64`    | `int64_t`     |
+//! | `bool`   | `bool`        |
+//! | `str`    | `const char*`, NUL-terminated |
+//! | `intarr` | `int32_t*`    |
+//!
+//! The names and signatures exported here are the runtime's ABI: they are only ever added to.
+
+#![allow(clippy::missing_safety_doc)]
+
+pub mod alloc;
+pub mod array;
+pub mod gc;
+pub mod panic;
+pub mod print;
+pub mod string;
+
+use std::ffi::c_void;
+
+/// The name and address of every runtime function.
+pub fn symbols() -> Vec<(&'static str, *mut c_void)> {
+    vec![
+        ("println", print::println as *mut c_void),
+        ("print_i32", print::print_i32 as *mut c_void),
+        ("println_i32", print::println_i32 as *mut c_void),
+        ("print_i64", print::print_i64 as *mut c_void),
+        ("println_i64", print::println_i64 as *mut c_void),
+        ("print_bool", print::print_bool as *mut c_void),
+        ("println_bool", print::println_bool as *mut c_void),
+        ("print_str", print::print_str as *mut c_void),
+        ("println_str", print::println_str as *mut c_void),
+        ("print_intarr", print::print_intarr as *mut c_void),
+        ("println_intarr", print::println_intarr as *mut c_void),
+        ("GC_malloc", gc::GC_malloc as *mut c_void),
+        ("GC_gcollect", gc::GC_gcollect as *mut c_void),
+        ("alloc", alloc::alloc as *mut c_void),
+        ("show_umap", alloc::show_umap as *mut c_void),
+        ("str_len", string::str_len as *mut c_void),
+        ("str_eq", string::str_eq as *mut c_void),
+        ("str_concat", string::str_concat as *mut c_void),
+        ("str_from_i32", string::str_from_i32 as *mut c_void),
+        ("str_from_i64", string::str_from_i64 as *mut c_void),
+        ("intarr_new", array::intarr_new as *mut c_void),
+        ("index_arr", array::index_arr as *mut c_void),
+        ("set_arr", array::set_arr as *mut c_void),
+        ("panic", panic::panic as *mut c_void),
+    ]
+}
@@ -0,0 +1,25 @@
+//! Stopping a program that can't go on.
+
+use crate::string;
+
+use std::io::{self, Write};
+use std::os::raw::c_char;
+use std::process;
+
+/// The exit code of a program that panicked.
+pub const EXIT_CODE: i32 = 101;
+
+/// Report `message` on stderr as the reason the program stops, and exit with [`EXIT_CODE`].
+///
+/// Whatever the program printed before is flushed first, so the report comes after it.
+pub fn report(message: &str) -> ! {
+    io::stdout().flush().ok();
+    eprintln!("panic: {}", message);
+    process::exit(EXIT_CODE)
+}
+
+/// Stop the program with `message`.
+#[no_mangle]
+pub unsafe extern "C" fn panic(message: *const c_char) -> ! {
+    report(&string::text(message))
+}
@@ -0,0 +1,120 @@
+//! Printing values to stdout.
+//!
+//! Every skipp type has a `print_<type>` and a `println_<type>`, which adds a newline. Values are
+//! printed the way the interpreter prints them. Output is flushed after every call, as compiled
+//! programs exit through the C runtime, which doesn't know about Rust's buffers.
+
+use crate::string;
+
+use std::convert::TryFrom;
+use std::fmt::{self, Display};
+use std::io::{self, Write};
+use std::os::raw::c_char;
+
+fn write(args: fmt::Arguments) {
+    let mut stdout = io::stdout().lock();
+    // Like `printf`, printing to a closed stdout isn't an error the program hears about.
+    stdout.write_fmt(args).ok();
+    stdout.flush().ok();
+}
+
+/// Print `n` on its own line and return it.
+///
+/// This predates the rest of the runtime, and is kept for the programs that declare it.
+#[no_mangle]
+pub extern "C" fn println(n: i32) -> i32 {
+    write(format_args!("{}\n", n));
+    n
+}
+
+#[no_mangle]
+pub extern "C" fn print_i32(n: i32) {
+    write(format_args!("{}", n));
+}
+
+#[no_mangle]
+pub extern "C" fn println_i32(n: i32) {
+    write(format_args!("{}\n", n));
+}
+
+#[no_mangle]
+pub extern "C" fn print_i64(n: i64) {
+    write(format_args!("{}", n));
+}
+
+#[no_mangle]
+pub extern "C" fn println_i64(n: i64) {
+    write(format_args!("{}\n", n));
+}
+
+#[no_mangle]
+pub extern "C" fn print_bool(b: bool) {
+    write(format_args!("{}", b));
+}
+
+#[no_mangle]
+pub extern "C" fn println_bool(b: bool) {
+    write(format_args!("{}\n", b));
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn print_str(s: *const c_char) {
+    write(format_args!("{}", string::text(s)));
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn println_str(s: *const c_char) {
+    write(format_args!("{}\n", string::text(s)));
+}
+
+/// Print the `length` elements of `arr` as `[1, 2, 3]`.
+#[no_mangle]
+pub unsafe extern "C" fn print_intarr(arr: *const i32, length: i32) {
+    write(format_args!("{}", IntArr::new(arr, length)));
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn println_intarr(arr: *const i32, length: i32) {
+    write(format_args!("{}\n", IntArr::new(arr, length)));
+}
+
+/// An `intarr` and its length, displayed as a list.
+struct IntArr<'a>(&'a [i32]);
+
+impl<'a> IntArr<'a> {
+    unsafe fn new(arr: *const i32, length: i32) -> IntArr<'a> {
+        match usize::try_from(length) {
+            Ok(0) => IntArr(&[]),
+            Ok(length) if !arr.is_null() => IntArr(std::slice::from_raw_parts(arr, length)),
+            Ok(_) => crate::panic::report("the array is null"),
+            Err(_) => crate::panic::report(&format!("the array length {} is negative", length)),
+        }
+    }
+}
+
+impl Display for IntArr<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "[")?;
+        for (i, element) in self.0.iter().enumerate() {
+            if i > 0 {
+                write!(f, ", ")?;
+            }
+            write!(f, "{}", element)?;
+        }
+        write!(f, "]")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::IntArr;
+
+    #[test]
+    fn arrays_are_printed_like_the_interpreter_does() {
+        let elements = [1, -2, 3];
+        unsafe {
+            assert_eq!(IntArr::new(elements.as_ptr(), 3).to_string(), "[1, -2, 3]");
+            assert_eq!(IntArr::new(std::ptr::null(), 0).to_string(), "[]");
+        }
+    }
+}
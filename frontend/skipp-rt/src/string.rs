@@ -0,0 +1,100 @@
+//! Helpers for `str`s, which are NUL-terminated UTF-8.
+//!
+//! Strings made by the runtime are allocated with [`alloc`](crate::alloc::alloc).
+
+use crate::alloc::alloc;
+use crate::panic::report;
+
+use std::borrow::Cow;
+use std::ffi::CStr;
+use std::os::raw::c_char;
+use std::ptr;
+
+/// The bytes of `s`, without the terminating NUL.
+unsafe fn bytes<'a>(s: *const c_char) -> &'a [u8] {
+    if s.is_null() {
+        report("the string is null");
+    }
+    CStr::from_ptr(s).to_bytes()
+}
+
+/// The text of `s`, with invalid UTF-8 replaced.
+pub(crate) unsafe fn text<'a>(s: *const c_char) -> Cow<'a, str> {
+    String::from_utf8_lossy(bytes(s))
+}
+
+/// A new runtime string with the bytes of `text`.
+fn new_str(text: &[u8]) -> *const c_char {
+    let s = alloc(text.len() as i64 + 1);
+    // The allocation is zeroed, so it is already terminated.
+    unsafe { ptr::copy_nonoverlapping(text.as_ptr(), s, text.len()) };
+    s as *const c_char
+}
+
+/// The length of `s` in bytes.
+#[no_mangle]
+pub unsafe extern "C" fn str_len(s: *const c_char) -> i32 {
+    bytes(s).len() as i32
+}
+
+/// Whether `a` and `b` have the same bytes.
+#[no_mangle]
+pub unsafe extern "C" fn str_eq(a: *const c_char, b: *const c_char) -> bool {
+    bytes(a) == bytes(b)
+}
+
+/// A new string with `b` appended to `a`.
+#[no_mangle]
+pub unsafe extern "C" fn str_concat(a: *const c_char, b: *const c_char) -> *const c_char {
+    new_str(&[bytes(a), bytes(b)].concat())
+}
+
+#[no_mangle]
+pub extern "C" fn str_from_i32(n: i32) -> *const c_char {
+    new_str(n.to_string().as_bytes())
+}
+
+#[no_mangle]
+pub extern "C" fn str_from_i64(n: i64) -> *const c_char {
+    new_str(n.to_string().as_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{str_concat, str_eq, str_from_i32, str_from_i64, str_len, text};
+    use std::ffi::CStr;
+
+    #[test]
+    fn strings_are_built_and_compared() {
+        unsafe {
+            let hello = "hello, \0".as_ptr() as *const _;
+            let joined = str_concat(hello, str_from_i32(-42));
+            assert_eq!(text(joined), "hello, -42");
+            assert_eq!(str_len(joined), 10);
+            assert!(str_eq(joined, "hello, -42\0".as_ptr() as *const _));
+            assert!(!str_eq(joined, hello));
+            assert_eq!(text(str_from_i64(i64::MIN)), i64::MIN.to_string());
+        }
+    }
+
+    #[test]
+    fn lengths_count_bytes() {
+        unsafe {
+            assert_eq!(str_len("h\u{e9}\0".as_ptr() as *const _), 3);
+            // Not UTF-8, which would be 3 bytes once replaced.
+            assert_eq!(str_len(b"\xff\0".as_ptr() as *const _), 1);
+        }
+    }
+
+    #[test]
+    fn invalid_utf8_is_kept_as_it_is() {
+        unsafe {
+            let invalid = b"\xff\0".as_ptr() as *const _;
+            let replaced = "\u{fffd}\0".as_ptr() as *const _;
+            assert!(!str_eq(invalid, replaced));
+            assert!(str_eq(invalid, b"\xff\0".as_ptr() as *const _));
+            let joined = str_concat(invalid, b"\xfe\0".as_ptr() as *const _);
+            assert_eq!(CStr::from_ptr(joined).to_bytes(), b"\xff\xfe");
+        }
+    }
+}
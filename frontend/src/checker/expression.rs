@@ -0,0 +1,638 @@
+use crate::checker::{Checker, Type};
+use crate::diagnostic::{Diagnostic, ErrorCode};
+use crate::lexer::tokens::TokenType;
+use crate::parser::{Expr, ExprValue};
+use crate::source::Span;
+use crate::symbol_table::{ScopeKind, SymbolKind};
+use crate::Result;
+
+impl Checker {
+    pub(super) fn check(&mut self, expr: &mut Expr, expected: Option<&Type>) -> Result<Type> {
+        self.check_expression(&mut expr.value, expr.span, expected)
+    }
+
+    /// Infer the type of an expression spanning `span`.
+    ///
+    /// `expected` is the type the context wants, if it is known. It is only used to type integer
+    /// literals and `if`s; comparing the result against it is up to the caller.
+    pub fn check_expression(
+        &mut self,
+        expression: &mut ExprValue,
+        span: Span,
+        expected: Option<&Type>,
+    ) -> Result<Type> {
+        match expression {
+            ExprValue::Integer(_) => match expected {
+                Some(type_) if type_.is_integer() => Ok(type_.clone()),
+                _ => Ok(Type::I32),
+            },
+            ExprValue::Boolean(_) => Ok(Type::Bool),
+            ExprValue::Str(_) => Ok(Type::Str),
+            ExprValue::Identifier(name) => self.lookup(name, span),
+            ExprValue::Array(elements, type_) => {
+                let element_type = self.resolve(type_, span)?;
+                // `intarr` is the only array type a program can name.
+                if element_type != Type::I32 {
+                    return Err(Diagnostic::error(
+                        ErrorCode::TypeMismatch,
+                        format!("Arrays of `{}` are not supported", element_type),
+                    )
+                    .with_span(span)
+                    .with_note("only `i32` arrays are, as `intarr`"));
+                }
+                for element in elements {
+                    let found = self.check(element, Some(&element_type))?;
+                    Self::expect(&element_type, &found, element.span)?;
+                }
+                Ok(Type::Array(Box::new(element_type)))
+            }
+            ExprValue::UnOp(op, operand) => {
+                let found = self.check(operand, expected)?;
+                match **op {
+                    TokenType::Not if found == Type::Bool || found.is_integer() => Ok(found),
+                    TokenType::Plus | TokenType::Minus if found.is_integer() => Ok(found),
+                    _ => Err(Diagnostic::error(
+                        ErrorCode::TypeMismatch,
+                        format!("Cannot apply `{:?}` to `{}`", op, found),
+                    )
+                    .with_span(span)),
+                }
+            }
+            ExprValue::BinOp(lhs, op, rhs) => self.check_binop(lhs, op, rhs, span, expected),
+            ExprValue::FnCall(name, args) => self.check_call(name, args, span),
+            ExprValue::VarDecl { type_, value, .. } => {
+                let declared = self.resolve(type_, span)?;
+                if let Some(value) = value {
+                    let found = self.check(value, Some(&declared))?;
+                    Self::expect(&declared, &found, value.span)?;
+                }
+                Ok(Type::Void)
+            }
+            ExprValue::Assign { name, value } => {
+                let declared = self.lookup(name, span)?;
+                let found = self.check(value, Some(&declared))?;
+                Self::expect(&declared, &found, value.span)?;
+                Ok(Type::Void)
+            }
+            ExprValue::AugAssign { name, op, value } => {
+                let declared = self.lookup(name, span)?;
+                if !declared.is_integer() {
+                    return Err(Diagnostic::error(
+                        ErrorCode::TypeMismatch,
+                        format!("Cannot apply `{:?}` to `{}`", op, declared),
+                    )
+                    .with_span(span));
+                }
+                let found = self.check(value, Some(&declared))?;
+                Self::expect(&declared, &found, value.span)?;
+                Ok(Type::Void)
+            }
+            ExprValue::FieldAccess {
+                object,
+                field,
+                struct_,
+            } => {
+                let (name, type_) = self.check_field(object, field, span)?;
+                *struct_ = name;
+                Ok(type_)
+            }
+            ExprValue::FieldAssign {
+                object,
+                field,
+                value,
+                struct_,
+            } => {
+                let (name, declared) = self.check_field(object, field, span)?;
+                *struct_ = name;
+                let found = self.check(value, Some(&declared))?;
+                Self::expect(&declared, &found, value.span)?;
+                Ok(Type::Void)
+            }
+            ExprValue::FieldAugAssign {
+                object,
+                field,
+                op,
+                value,
+                struct_,
+            } => {
+                let (name, declared) = self.check_field(object, field, span)?;
+                *struct_ = name;
+                if !declared.is_integer() {
+                    return Err(Diagnostic::error(
+                        ErrorCode::TypeMismatch,
+                        format!("Cannot apply `{:?}` to `{}`", op, declared),
+                    )
+                    .with_span(span));
+                }
+                let found = self.check(value, Some(&declared))?;
+                Self::expect(&declared, &found, value.span)?;
+                Ok(Type::Void)
+            }
+            ExprValue::MethodCall {
+                object,
+                method,
+                args,
+                class,
+            } => {
+                let (name, type_) = self.check_method_call(object, method, args, span)?;
+                *class = name;
+                Ok(type_)
+            }
+            ExprValue::Return(value) => {
+                let return_type = self.return_type.clone();
+                let found = self.check(value, Some(&return_type))?;
+                Self::expect(&return_type, &found, value.span)?;
+                Ok(found)
+            }
+            ExprValue::IfElse {
+                cond,
+                if_,
+                else_,
+                type_,
+            } => {
+                let found = self.check(cond, Some(&Type::Bool))?;
+                Self::expect(&Type::Bool, &found, cond.span)?;
+
+                let if_type = self.check(if_, expected)?;
+                let else_type = self.check(else_, expected.or(Some(&if_type)))?;
+                // A branch that returns doesn't produce a value.
+                let branches = [(if_type, &**if_), (else_type, &**else_)];
+                let mut values = branches.iter().filter(|(_, e)| !Self::diverges(e));
+                let result = match expected {
+                    Some(expected) if *expected != Type::Void => {
+                        for (found, branch) in values {
+                            Self::expect(expected, found, branch.span)?;
+                        }
+                        expected.clone()
+                    }
+                    // Only a statement, so the branches may disagree.
+                    _ => match values.next() {
+                        Some((first, _)) if values.all(|(other, _)| other == first) => {
+                            first.clone()
+                        }
+                        _ => Type::Void,
+                    },
+                };
+                *type_ = result.to_string();
+                Ok(result)
+            }
+            ExprValue::While(cond, body) => {
+                let found = self.check(cond, Some(&Type::Bool))?;
+                Self::expect(&Type::Bool, &found, cond.span)?;
+                let start = body.span.start;
+                self.scoped(ScopeKind::Loop, start, |this| this.check(body, None))?;
+                Ok(Type::Void)
+            }
+            ExprValue::Do(expressions) => {
+                Ok(self.scoped(ScopeKind::Block, span.start, |this| this.check_block(expressions)))
+            }
+            ExprValue::Use(_) | ExprValue::Error => Ok(Type::Void),
+        }
+    }
+
+    fn check_binop(
+        &mut self,
+        lhs: &mut Expr,
+        op: &TokenType,
+        rhs: &mut Expr,
+        span: Span,
+        expected: Option<&Type>,
+    ) -> Result<Type> {
+        let arithmetic = matches!(
+            op,
+            TokenType::Plus | TokenType::Minus | TokenType::Mul | TokenType::Div
+        );
+        let hint = if arithmetic { expected } else { None };
+        // Type the operand that isn't a literal first, so `1 < x` works for any integer `x`.
+        let (l, r) = if matches!(lhs.value, ExprValue::Integer(_)) {
+            let r = self.check(rhs, hint)?;
+            (self.check(lhs, Some(&r))?, r)
+        } else {
+            let l = self.check(lhs, hint)?;
+            (l.clone(), self.check(rhs, Some(&l))?)
+        };
+        let operands_ok = match op {
+            _ if arithmetic => l.is_integer(),
+            TokenType::Equal | TokenType::NotEq => l.is_integer() || l == Type::Bool,
+            TokenType::Less | TokenType::Greater | TokenType::LessEq | TokenType::GreaterEq => {
+                l.is_integer()
+            }
+            _ => false,
+        };
+        if !operands_ok || l != r {
+            return Err(Diagnostic::error(
+                ErrorCode::TypeMismatch,
+                format!("Cannot apply `{:?}` to `{}` and `{}`", op, l, r),
+            )
+            .with_span(span));
+        }
+        Ok(if arithmetic { l } else { Type::Bool })
+    }
+
+    /// The struct `object` is an instance of, and the type of its `field`, which is at `span`.
+    fn check_field(
+        &mut self,
+        object: &mut Expr,
+        field: &str,
+        span: Span,
+    ) -> Result<(String, Type)> {
+        let struct_name = match self.check(object, None)? {
+            Type::Struct(name) => name,
+            other => {
+                return Err(Diagnostic::error(
+                    ErrorCode::TypeMismatch,
+                    format!("`{}` has no fields", other),
+                )
+                .with_span(object.span))
+            }
+        };
+        match self.structs[&struct_name]
+            .iter()
+            .find(|(name, _)| name == field)
+        {
+            Some((_, type_)) => Ok((struct_name, type_.clone())),
+            None => {
+                let kind = if self.classes.contains_key(&struct_name) {
+                    "Class"
+                } else {
+                    "Struct"
+                };
+                Err(Diagnostic::error(
+                    ErrorCode::UnresolvedName,
+                    format!("{} `{}` has no field `{}`", kind, struct_name, field),
+                )
+                .with_span(span))
+            }
+        }
+    }
+
+    /// The class of `object`, and the result of calling its `method` with `args`, at `span`.
+    fn check_method_call(
+        &mut self,
+        object: &mut Expr,
+        method: &str,
+        args: &mut [Expr],
+        span: Span,
+    ) -> Result<(String, Type)> {
+        let found = self.check(object, None)?;
+        let methods = match &found {
+            Type::Struct(name) => self.classes.get(name),
+            _ => None,
+        };
+        let signature = match methods.map(|methods| methods.get(method)) {
+            Some(Some(signature)) => signature.clone(),
+            Some(None) => {
+                return Err(Diagnostic::error(
+                    ErrorCode::UnresolvedName,
+                    format!("Class `{}` has no method `{}`", found, method),
+                )
+                .with_span(span))
+            }
+            None => {
+                return Err(Diagnostic::error(
+                    ErrorCode::TypeMismatch,
+                    format!("`{}` has no methods", found),
+                )
+                .with_span(object.span))
+            }
+        };
+        // `self` is the object the method is called on.
+        self.check_args(method, &signature.params[1..], args, span)?;
+        Ok((found.to_string(), signature.return_type))
+    }
+
+    fn check_call(&mut self, name: &str, args: &mut [Expr], span: Span) -> Result<Type> {
+        let kind = self
+            .symbols
+            .lookup(name, span.start)
+            .map(|id| self.symbols.symbol(id).kind);
+        let (params, result) = match (kind, self.structs.get(name), self.functions.get(name)) {
+            // A struct's name constructs it from its fields in order.
+            (Some(SymbolKind::Struct), Some(fields), _) => {
+                let params = fields.iter().map(|(_, type_)| type_.clone()).collect();
+                (params, Type::Struct(name.to_string()))
+            }
+            // A class's name constructs it with the arguments of `init`, if it has one.
+            (Some(SymbolKind::Class), Some(_), _) => {
+                let params = match self.classes[name].get("init") {
+                    Some(init) => init.params[1..].to_vec(),
+                    None => Vec::new(),
+                };
+                (params, Type::Struct(name.to_string()))
+            }
+            (Some(SymbolKind::Function), _, Some(signature))
+            | (Some(SymbolKind::Extern), _, Some(signature)) => {
+                (signature.params.clone(), signature.return_type.clone())
+            }
+            (Some(kind), ..) if kind == SymbolKind::Variable || kind == SymbolKind::Parameter => {
+                return Err(Diagnostic::error(
+                    ErrorCode::UnresolvedName,
+                    format!("Expected a function, found {} `{}`", kind, name),
+                )
+                .with_span(span))
+            }
+            _ => {
+                return Err(Diagnostic::error(
+                    ErrorCode::UnresolvedName,
+                    format!("Cannot find function `{}`", name),
+                )
+                .with_span(span))
+            }
+        };
+        self.check_args(name, &params, args, span)?;
+        Ok(result)
+    }
+
+    /// Check the arguments `args` of a call to `name` against its parameters `params`.
+    fn check_args(
+        &mut self,
+        name: &str,
+        params: &[Type],
+        args: &mut [Expr],
+        span: Span,
+    ) -> Result<()> {
+        if params.len() != args.len() {
+            return Err(Diagnostic::error(
+                ErrorCode::ArgumentCount,
+                format!(
+                    "`{}` takes {} argument(s) but {} were given",
+                    name,
+                    params.len(),
+                    args.len()
+                ),
+            )
+            .with_span(span));
+        }
+        for (arg, param) in args.iter_mut().zip(params) {
+            let found = self.check(arg, Some(param))?;
+            Self::expect(param, &found, arg.span)?;
+        }
+        Ok(())
+    }
+
+    /// Whether control never gets past `expr`, because it returns.
+    pub(super) fn diverges(expr: &Expr) -> bool {
+        match &expr.value {
+            ExprValue::Return(_) => true,
+            ExprValue::Do(expressions) => expressions.iter().any(Self::diverges),
+            ExprValue::IfElse { if_, else_, .. } => Self::diverges(if_) && Self::diverges(else_),
+            _ => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::checker::Checker;
+    use crate::diagnostic::ErrorCode;
+    use crate::lexer::Lexer;
+    use crate::parser::{AstNode, ExprValue, NodePosition, Parser};
+    use crate::source::SourceMap;
+
+    fn check(text: &str) -> (Vec<(AstNode, NodePosition)>, Vec<ErrorCode>) {
+        let tokens = Lexer::from_text(&SourceMap::new(), text, "<test>")
+            .map(|t| t.unwrap())
+            .collect::<Vec<_>>();
+        let mut parser = Parser::new(tokens.into_iter().peekable());
+        let (mut ast, errors) = parser.parse_program();
+        assert!(errors.is_empty(), "{:#?}", errors);
+        let errors = Checker::new(parser.symtab).check_program(&mut ast);
+        (ast, errors.into_iter().map(|e| e.code).collect())
+    }
+
+    fn errors(body: &str) -> Vec<ErrorCode> {
+        check(&format!(
+            "extern println(a: i32) -> i32;
+struct Point {{ x: i32 y: i64 }}
+def f(a: i32, b: bool) -> i32 do
+{}
+    return 0;
+end",
+            body
+        ))
+        .1
+    }
+
+    #[test]
+    fn well_typed_programs_pass() {
+        assert_eq!(
+            errors(
+                "let p: Point = Point(1, 2);
+    let y: i64 = p.y * 3;
+    p.x = 5;
+    p.y += y;
+    let n: i32 = if b: a else: return 0 - a;
+    while n > 0 do n -= 1; end;
+    if !b: println(p.x + 1);
+    return f(n, n == 0);"
+            ),
+            vec![]
+        );
+    }
+
+    #[test]
+    fn branches_that_return_give_no_value() {
+        assert_eq!(
+            errors(
+                "let c: bool = if a < 0 do true end else do
+        if a == 0 do return 0; end else do return 1; end
+    end;"
+            ),
+            vec![]
+        );
+    }
+
+    #[test]
+    fn values_are_only_returned_by_return() {
+        use ErrorCode::*;
+        let (_, errors) = check(
+            "def f(x: i32) -> i32 do x end
+def g(b: bool) -> i32 do if b do return 1; end end
+def h(b: bool) -> i32 do if b do return 1; end else do return 0; end end
+def k() -> void do end",
+        );
+        assert_eq!(errors, vec![MissingReturn; 2]);
+    }
+
+    #[test]
+    fn expressions_belong_in_functions() {
+        let (_, errors) = check(
+            "extern println(a: i32) -> i32;
+println(1);
+def main() -> i32 do return println(2); end",
+        );
+        assert_eq!(errors, vec![ErrorCode::Syntax]);
+    }
+
+    #[test]
+    fn structs_can_refer_to_themselves() {
+        let (_, errors) = check(
+            "struct Node { value: i32 next: Node }
+def f(n: Node) -> i32 do
+    n.next.next.value = 1;
+    return n.next.value;
+end",
+        );
+        assert_eq!(errors, vec![]);
+    }
+
+    #[test]
+    fn only_repr_c_structs_are_passed_to_c() {
+        let (_, errors) = check(
+            "struct Point { x: i32 y: i32 }
+struct Person repr(C) { age: i32 alive: bool }
+extern create_person(age: i32) -> Person;
+extern print_person(p: Person) -> void;
+extern make_point() -> Point;
+extern print_point(a: i32, p: Point) -> void;",
+        );
+        assert_eq!(errors, vec![ErrorCode::ForeignType; 2]);
+    }
+
+    #[test]
+    fn only_i32_arrays_are_supported() {
+        assert_eq!(errors("let v: intarr = [i32 1, a, a * 2];"), vec![]);
+        assert_eq!(errors("[i64 1, 2];"), vec![ErrorCode::TypeMismatch]);
+        assert_eq!(errors("[Point];"), vec![ErrorCode::TypeMismatch]);
+    }
+
+    #[test]
+    fn methods_are_checked_against_their_class() {
+        let (_, errors) = check(
+            "class Counter {
+    count: i32
+    def init(start: i32) -> void do self.count = start; end
+    def add(n: i32) -> Counter do self.count += n; return self; end
+    def test() -> bool do return self.count > 0; end
+}
+class Flag {
+    def test() -> i32 do return 0; end
+}
+def f() -> bool do
+    let c: Counter = Counter(1);
+    let n: i32 = c.add(2).add(3).count + Flag().test();
+    return c.test();
+end",
+        );
+        assert_eq!(errors, vec![]);
+
+        use ErrorCode::*;
+        let (_, errors) = check(
+            "class A {
+    x: i32
+    def init() -> i32 do return 0; end
+    def get(n: i32) -> i32 do return self.x + n; end
+}
+def f(a: A, n: i32) -> i32 do
+    A(1);
+    a.get(true);
+    a.set(1);
+    n.get(1);
+    return a.get();
+end",
+        );
+        assert_eq!(
+            errors,
+            vec![
+                TypeMismatch,
+                ArgumentCount,
+                TypeMismatch,
+                UnresolvedName,
+                TypeMismatch,
+                ArgumentCount
+            ]
+        );
+    }
+
+    #[test]
+    fn type_errors_are_reported() {
+        use ErrorCode::*;
+        assert_eq!(errors("println(b);"), vec![TypeMismatch]);
+        assert_eq!(errors("println(1, 2);"), vec![ArgumentCount]);
+        assert_eq!(errors("let x: u8 = 1;"), vec![UnknownType]);
+        assert_eq!(errors("x = 1; g();"), vec![UnresolvedName, UnresolvedName]);
+        assert_eq!(errors("a + b; a < b; a == b;"), vec![TypeMismatch; 3]);
+        assert_eq!(errors("return b;"), vec![TypeMismatch]);
+        assert_eq!(
+            errors("let x: i32 = if b: 1 else: true;"),
+            vec![TypeMismatch]
+        );
+        assert_eq!(errors("while a do end;"), vec![TypeMismatch]);
+        assert_eq!(
+            errors("let c: Point = Point(1, b);"),
+            vec![TypeMismatch]
+        );
+        assert_eq!(errors("a(1); let g: i32 = f;"), vec![UnresolvedName; 2]);
+        assert_eq!(
+            errors("let p: Point = Point(1, 2); p.z;"),
+            vec![UnresolvedName]
+        );
+        assert_eq!(
+            errors("let p: Point = Point(1, 2); p.x = b; p.y += b; a.x = 1; p.z = 1;"),
+            vec![TypeMismatch, TypeMismatch, TypeMismatch, UnresolvedName]
+        );
+    }
+
+    #[test]
+    fn inferred_if_types_are_written_back() {
+        let (ast, errors) = check(
+            "def f(b: bool) -> i32 do
+    if b: 1 else: 2;
+    if b: 1 else: false;
+    return 0;
+end",
+        );
+        assert!(errors.is_empty(), "{:?}", errors);
+        let types = match &ast[0].0 {
+            AstNode::FunctionDef(f) => f
+                .expressions
+                .iter()
+                .filter_map(|e| match &e.value {
+                    ExprValue::IfElse { type_, .. } => Some(type_.clone()),
+                    _ => None,
+                })
+                .collect::<Vec<_>>(),
+            other => panic!("expected a function, got {:?}", other),
+        };
+        assert_eq!(types, vec!["i32", "void"]);
+    }
+
+    #[test]
+    fn resolved_structs_are_written_back() {
+        let (ast, errors) = check(
+            "struct P { x: i32 }
+class C { p: P def get() -> P do return self.p; end }
+def f(c: C) -> i32 do
+    c.get().x = 1;
+    c.p.x += 2;
+    return c.get().x;
+end",
+        );
+        assert!(errors.is_empty(), "{:?}", errors);
+        let body = match &ast[2].0 {
+            AstNode::FunctionDef(f) => &f.expressions,
+            other => panic!("expected a function, got {:?}", other),
+        };
+        let (struct_, class) = match &body[0].value {
+            ExprValue::FieldAssign {
+                object, struct_, ..
+            } => match &object.value {
+                ExprValue::MethodCall { class, .. } => (struct_, class),
+                other => panic!("expected a method call, got {:?}", other),
+            },
+            other => panic!("expected a field assignment, got {:?}", other),
+        };
+        assert_eq!((struct_.as_str(), class.as_str()), ("P", "C"));
+        match &body[1].value {
+            ExprValue::FieldAugAssign {
+                object, struct_, ..
+            } => {
+                assert_eq!(struct_, "P");
+                assert!(
+                    matches!(&object.value, ExprValue::FieldAccess { struct_, .. } if struct_ == "C")
+                );
+            }
+            other => panic!("expected a field assignment, got {:?}", other),
+        }
+    }
+}
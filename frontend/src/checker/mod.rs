@@ -0,0 +1,119 @@
+mod expression;
+mod program;
+pub mod types;
+
+pub use types::Type;
+
+use crate::diagnostic::{Diagnostic, ErrorCode};
+use crate::source::Span;
+use crate::symbol_table::{ScopeKind, SymbolKind, SymbolTable};
+use crate::Result;
+
+use std::collections::{HashMap, HashSet};
+
+/// The parameter and return types of a function or extern.
+#[derive(Debug, Clone)]
+pub struct Signature {
+    pub params: Vec<Type>,
+    pub return_type: Type,
+}
+
+/// Checks the types of a parsed program before any code is generated.
+///
+/// Items are checked in order, so like in the generator a function can only be called after it
+/// has been declared (or from its own body).
+#[derive(Clone)]
+pub struct Checker {
+    /// Functions and externs declared so far.
+    functions: HashMap<String, Signature>,
+    /// Fields of the structs declared so far, in declaration order.
+    structs: HashMap<String, Vec<(String, Type)>>,
+    /// Methods of the classes declared so far, whose first parameter is `self`. The fields of a
+    /// class are in `structs`.
+    classes: HashMap<String, HashMap<String, Signature>>,
+    /// The structs declared `repr(C)`, which are the only ones `extern` functions may use.
+    c_structs: HashSet<String>,
+    /// The scopes built by the parser, walked again while checking.
+    pub(crate) symbols: SymbolTable,
+    /// Return type of the function being checked.
+    return_type: Type,
+    /// Errors found so far.
+    errors: Vec<Diagnostic>,
+}
+
+impl Checker {
+    pub fn new(symbols: SymbolTable) -> Self {
+        Checker {
+            functions: HashMap::new(),
+            structs: HashMap::new(),
+            classes: HashMap::new(),
+            c_structs: HashSet::new(),
+            symbols,
+            return_type: Type::Void,
+            errors: Vec::new(),
+        }
+    }
+
+    /// Resolve a type name written at `span`.
+    fn resolve(&self, name: &str, span: Span) -> Result<Type> {
+        if let Some(type_) = Type::builtin(name) {
+            return Ok(type_);
+        }
+        if self.structs.contains_key(name) {
+            return Ok(Type::Struct(name.to_string()));
+        }
+        Err(
+            Diagnostic::error(ErrorCode::UnknownType, format!("Unknown type `{}`", name))
+                .with_span(span),
+        )
+    }
+
+    /// Give back the symbol table, e.g. for the generator.
+    pub fn into_symbols(self) -> SymbolTable {
+        self.symbols
+    }
+
+    /// The type of the variable or parameter `name` used at `span`.
+    fn lookup(&self, name: &str, span: Span) -> Result<Type> {
+        let symbol = self
+            .symbols
+            .lookup(name, span.start)
+            .map(|id| self.symbols.symbol(id));
+        match symbol {
+            Some(symbol) if matches!(symbol.kind, SymbolKind::Variable | SymbolKind::Parameter) => {
+                self.resolve(&symbol.type_, symbol.span)
+            }
+            Some(symbol) => Err(Diagnostic::error(
+                ErrorCode::UnresolvedName,
+                format!("Expected a variable, found {} `{}`", symbol.kind, name),
+            )
+            .with_span(span)),
+            None => Err(Diagnostic::error(
+                ErrorCode::UnresolvedName,
+                format!("Cannot find variable `{}`", name),
+            )
+            .with_span(span)),
+        }
+    }
+
+    /// Run `f` in the scope introduced at byte offset `start`.
+    fn scoped<T>(&mut self, kind: ScopeKind, start: usize, f: impl FnOnce(&mut Self) -> T) -> T {
+        self.symbols.enter_scope(kind, start);
+        let result = f(self);
+        self.symbols.exit_scope();
+        result
+    }
+
+    /// Check that `found` is the `expected` type.
+    fn expect(expected: &Type, found: &Type, span: Span) -> Result<()> {
+        if expected == found {
+            Ok(())
+        } else {
+            Err(Diagnostic::error(
+                ErrorCode::TypeMismatch,
+                format!("Expected `{}`, found `{}`", expected, found),
+            )
+            .with_span(span))
+        }
+    }
+}
@@ -0,0 +1,238 @@
+use crate::checker::{Checker, Signature, Type};
+use crate::diagnostic::{Diagnostic, ErrorCode};
+use crate::parser::{
+    Args, AstNode, Class, Expr, ExprValue, External, Function, NodePosition, Repr, StructMembers,
+};
+use crate::source::Span;
+use crate::symbol_table::ScopeKind;
+use crate::Result;
+
+use std::collections::HashMap;
+use std::iter;
+
+impl Checker {
+    /// Check the whole program, returning every error found.
+    ///
+    /// The inferred type of each `if` is written back into the AST for the generator.
+    pub fn check_program(&mut self, program: &mut [(AstNode, NodePosition)]) -> Vec<Diagnostic> {
+        for (node, pos) in program.iter_mut() {
+            let result = match node {
+                AstNode::Extern(e) => self
+                    .check_signature(&e.args, &e.return_type, pos.span)
+                    .and_then(|signature| self.check_foreign(e, signature, pos.span))
+                    .map(|signature| self.define(&e.name, signature)),
+                AstNode::FunctionDef(f) => self.check_function(f, pos.span),
+                AstNode::Class(c) => self.check_class(c, pos.span),
+                AstNode::Struct(name, members, repr) => {
+                    self.check_struct(name, members, *repr, pos.span)
+                }
+                // Nothing would run them in a compiled program; the REPL checks them with `infer`.
+                AstNode::Expression(_) => Err(Diagnostic::error(
+                    ErrorCode::Syntax,
+                    "Expressions are only allowed inside functions",
+                )
+                .with_span(pos.span)
+                .with_note("move it into `main`, or evaluate it in the REPL")),
+                AstNode::Error => Ok(()),
+            };
+            if let Err(e) = result {
+                self.errors.push(e);
+            }
+        }
+        std::mem::take(&mut self.errors)
+    }
+
+    /// Check a top-level expression on its own and infer its type, e.g. for the REPL.
+    pub fn infer(
+        &mut self,
+        expression: &mut ExprValue,
+        span: Span,
+    ) -> std::result::Result<Type, Vec<Diagnostic>> {
+        let result = self.check_expression(expression, span, None);
+        let mut errors = std::mem::take(&mut self.errors);
+        match result {
+            Ok(type_) if errors.is_empty() => Ok(type_),
+            Ok(_) => Err(errors),
+            Err(e) => {
+                errors.push(e);
+                Err(errors)
+            }
+        }
+    }
+
+    fn check_signature(&self, args: &Args, return_type: &str, span: Span) -> Result<Signature> {
+        let params = args
+            .type_
+            .iter()
+            .zip(&args.span)
+            .map(|(type_, span)| self.resolve(type_, *span))
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Signature {
+            params,
+            return_type: self.resolve(return_type, span)?,
+        })
+    }
+
+    /// Check that C can exchange every parameter and the result of the extern `e` with skipp.
+    ///
+    /// Structs are passed by pointer, and only a `repr(C)` struct promises C the layout it
+    /// expects behind it.
+    fn check_foreign(&self, e: &External, signature: Signature, span: Span) -> Result<Signature> {
+        let types = signature
+            .params
+            .iter()
+            .chain(iter::once(&signature.return_type));
+        let spans = e.args.span.iter().chain(iter::once(&span));
+        for (type_, span) in types.zip(spans) {
+            if let Type::Struct(name) = type_ {
+                if !self.c_structs.contains(name) {
+                    return Err(Diagnostic::error(
+                        ErrorCode::ForeignType,
+                        format!("Struct `{}` can't be passed to or from C", name),
+                    )
+                    .with_span(*span)
+                    .with_note(format!(
+                        "declare it as `struct {} repr(C) {{ ... }}` to give it C's layout",
+                        name
+                    )));
+                }
+            }
+        }
+        Ok(signature)
+    }
+
+    /// Make a function or extern callable from the rest of the program.
+    ///
+    /// Redefinitions were already reported by the parser; the first definition wins.
+    fn define(&mut self, name: &str, signature: Signature) {
+        self.functions.entry(name.to_string()).or_insert(signature);
+    }
+
+    fn check_function(&mut self, function: &mut Function, span: Span) -> Result<()> {
+        let signature = self.check_signature(&function.args, &function.return_type, span)?;
+        // Defined before the body is checked so it can call itself.
+        self.define(&function.name, signature.clone());
+        self.check_body(function, signature, span);
+        Ok(())
+    }
+
+    /// Check a function body. Its parameters were already declared by the parser.
+    fn check_body(&mut self, function: &mut Function, signature: Signature, span: Span) {
+        self.return_type = signature.return_type;
+        let expressions = &mut function.expressions;
+        self.scoped(ScopeKind::Function, span.start, |this| this.check_block(expressions));
+        // The value of the last expression isn't returned, so only a `return` gives one.
+        if self.return_type != Type::Void && !expressions.iter().any(Self::diverges) {
+            self.errors.push(
+                Diagnostic::error(
+                    ErrorCode::MissingReturn,
+                    format!(
+                        "`{}` can reach its end without returning a `{}`",
+                        function.name, self.return_type
+                    ),
+                )
+                .with_span(span)
+                .with_note("end the body with a `return`"),
+            );
+        }
+        self.return_type = Type::Void;
+    }
+
+    /// Check the expressions of a block in the current scope, returning the type of the last one.
+    ///
+    /// An expression with a type error is reported and skipped, so the rest of the block is still
+    /// checked.
+    pub(super) fn check_block(&mut self, expressions: &mut [Expr]) -> Type {
+        let mut last = Type::Void;
+        for expr in expressions {
+            last = match self.check(expr, None) {
+                Ok(type_) => type_,
+                Err(e) => {
+                    self.errors.push(e);
+                    Type::Void
+                }
+            };
+        }
+        last
+    }
+
+    /// Check a class: its fields like a struct's, then its methods.
+    fn check_class(&mut self, class: &mut Class, span: Span) -> Result<()> {
+        self.check_struct(&class.name, &class.fields, Repr::default(), span)?;
+        let name = class.name.clone();
+        self.scoped(ScopeKind::Class, span.start, |this| {
+            // Every signature is known before any body is checked, so methods can call each
+            // other in any order.
+            let mut methods = HashMap::new();
+            for (method, pos) in class.fns.iter() {
+                match this.check_signature(&method.args, &method.return_type, pos.span) {
+                    Ok(signature)
+                        if method.name == "init" && signature.return_type != Type::Void =>
+                    {
+                        this.errors.push(
+                            Diagnostic::error(
+                                ErrorCode::TypeMismatch,
+                                format!(
+                                    "`init` must return `void`, not `{}`",
+                                    signature.return_type
+                                ),
+                            )
+                            .with_span(pos.span)
+                            .with_note(format!("`{}(...)` always gives the new object", name)),
+                        );
+                    }
+                    // Redefinitions were already reported by the parser; the first one wins.
+                    Ok(signature) => {
+                        methods.entry(method.name.clone()).or_insert(signature);
+                    }
+                    Err(e) => this.errors.push(e),
+                }
+            }
+            this.classes.insert(name.clone(), methods);
+            for (method, pos) in class.fns.iter_mut() {
+                if let Some(signature) = this.classes[&name].get(&method.name).cloned() {
+                    this.check_body(method, signature, pos.span);
+                }
+            }
+        });
+        Ok(())
+    }
+
+    fn check_struct(
+        &mut self,
+        name: &str,
+        members: &StructMembers,
+        repr: Repr,
+        span: Span,
+    ) -> Result<()> {
+        // Redefinitions of other structs were already reported by the parser.
+        if self.structs.contains_key(name) {
+            return Ok(());
+        }
+        if Type::builtin(name).is_some() {
+            return Err(Diagnostic::error(
+                ErrorCode::Redefinition,
+                format!("Type `{}` is already defined", name),
+            )
+            .with_span(span));
+        }
+        // Known before its fields are resolved, so a field can refer to the struct itself.
+        self.structs.insert(name.to_string(), Vec::new());
+        let fields = members
+            .iter()
+            .map(|member| Ok((member.name.clone(), self.resolve(&member.type_, member.span)?)));
+        match fields.collect::<Result<Vec<_>>>() {
+            Ok(fields) => {
+                self.structs.insert(name.to_string(), fields);
+                if repr.c {
+                    self.c_structs.insert(name.to_string());
+                }
+                Ok(())
+            }
+            Err(e) => {
+                self.structs.remove(name);
+                Err(e)
+            }
+        }
+    }
+}
@@ -0,0 +1,51 @@
+use std::fmt;
+
+/// The type of a value, resolved from a type name in the source.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Type {
+    I32,
+    I64,
+    Bool,
+    Str,
+    Void,
+    /// A struct, by name.
+    Struct(String),
+    /// An array of elements of the given type.
+    Array(Box<Type>),
+}
+
+impl Type {
+    /// The built-in type called `name`, if there is one.
+    pub fn builtin(name: &str) -> Option<Type> {
+        match name {
+            "i32" => Some(Type::I32),
+            "i64" => Some(Type::I64),
+            "bool" => Some(Type::Bool),
+            "str" => Some(Type::Str),
+            "void" => Some(Type::Void),
+            "intarr" => Some(Type::Array(Box::new(Type::I32))),
+            _ => None,
+        }
+    }
+
+    pub fn is_integer(&self) -> bool {
+        matches!(self, Type::I32 | Type::I64)
+    }
+}
+
+/// Types are displayed the way they are spelled in the source, so the result can be written back
+/// into the AST.
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Type::I32 => write!(f, "i32"),
+            Type::I64 => write!(f, "i64"),
+            Type::Bool => write!(f, "bool"),
+            Type::Str => write!(f, "str"),
+            Type::Void => write!(f, "void"),
+            Type::Struct(name) => write!(f, "{}", name),
+            Type::Array(element) if **element == Type::I32 => write!(f, "intarr"),
+            Type::Array(element) => write!(f, "[{}]", element),
+        }
+    }
+}
@@ -0,0 +1,283 @@
+use crate::source::{SourceMap, Span};
+
+use std::fmt;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use owo_colors::OwoColorize;
+
+/// Whether [`Diagnostic::render`] colors its output with ANSI escape codes.
+static COLOR: AtomicBool = AtomicBool::new(false);
+
+/// Color rendered diagnostics or not. They are plain until this is called.
+pub fn set_color(enabled: bool) {
+    COLOR.store(enabled, Ordering::Relaxed);
+}
+
+/// `text` as `style` colors it, if colors are enabled.
+fn paint(text: &str, style: impl Fn(&str) -> String) -> String {
+    if COLOR.load(Ordering::Relaxed) {
+        style(text)
+    } else {
+        text.to_string()
+    }
+}
+
+/// How serious a [`Diagnostic`] is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Error,
+    Warning,
+    Note,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Severity::Error => write!(f, "error"),
+            Severity::Warning => write!(f, "warning"),
+            Severity::Note => write!(f, "note"),
+        }
+    }
+}
+
+/// The kind of problem a [`Diagnostic`] reports.
+///
+/// Tools should match on this instead of on the message text.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorCode {
+    /// The input ended in the middle of a construct, e.g. a truncated file.
+    UnexpectedEof,
+    /// A source file couldn't be read.
+    Io,
+    /// Characters that don't form a valid token.
+    InvalidToken,
+    /// A token that can't start an expression where one was expected.
+    ExpectedExpression,
+    /// Any other violation of the grammar.
+    Syntax,
+    /// A type name that doesn't name a type.
+    UnknownType,
+    /// A variable, function or field that doesn't exist.
+    UnresolvedName,
+    /// A value of the wrong type.
+    TypeMismatch,
+    /// A call with the wrong number of arguments.
+    ArgumentCount,
+    /// A name declared twice.
+    Redefinition,
+    /// A type an `extern` function can't exchange with C, e.g. a struct without `repr(C)`.
+    ForeignType,
+    /// A function returning a value whose body can end without a `return`.
+    MissingReturn,
+    /// A well-formed program the code generator can't lower.
+    Codegen,
+    /// LLVM rejected the module or failed to emit it.
+    Llvm,
+    /// The linker failed to produce an executable.
+    Link,
+    /// The program couldn't be run in-process.
+    Jit,
+    /// The interpreter couldn't run the program, e.g. it calls an extern with no built-in.
+    Runtime,
+    /// The interpreted program stopped itself, e.g. by dividing by zero, where a compiled one
+    /// would exit through the runtime's `panic`.
+    Panic,
+}
+
+impl ErrorCode {
+    /// The stable code printed next to the severity, e.g. `E0001`.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ErrorCode::UnexpectedEof => "E0001",
+            ErrorCode::Io => "E0002",
+            ErrorCode::InvalidToken => "E0100",
+            ErrorCode::ExpectedExpression => "E0200",
+            ErrorCode::Syntax => "E0201",
+            ErrorCode::UnknownType => "E0300",
+            ErrorCode::UnresolvedName => "E0301",
+            ErrorCode::TypeMismatch => "E0302",
+            ErrorCode::ArgumentCount => "E0303",
+            ErrorCode::Redefinition => "E0304",
+            ErrorCode::ForeignType => "E0305",
+            ErrorCode::MissingReturn => "E0306",
+            ErrorCode::Codegen => "E0400",
+            ErrorCode::Llvm => "E0500",
+            ErrorCode::Link => "E0600",
+            ErrorCode::Jit => "E0700",
+            ErrorCode::Runtime => "E0800",
+            ErrorCode::Panic => "E0801",
+        }
+    }
+}
+
+impl fmt::Display for ErrorCode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+/// A secondary location attached to a [`Diagnostic`].
+#[derive(Debug, Clone)]
+pub struct Label {
+    pub span: Span,
+    pub message: String,
+}
+
+/// An error, warning or note produced by any phase of the compiler.
+#[derive(Debug, Clone)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub code: ErrorCode,
+    pub message: String,
+    /// Where the problem is, if it can be pinned to the source.
+    pub span: Option<Span>,
+    /// Other locations that help explain the problem.
+    pub labels: Vec<Label>,
+    /// Free-form remarks printed after the source excerpt.
+    pub notes: Vec<String>,
+}
+
+impl Diagnostic {
+    /// Create an error without a location.
+    pub fn error(code: ErrorCode, message: impl Into<String>) -> Self {
+        Diagnostic {
+            severity: Severity::Error,
+            code,
+            message: message.into(),
+            span: None,
+            labels: Vec::new(),
+            notes: Vec::new(),
+        }
+    }
+
+    /// Create a warning without a location.
+    pub fn warning(code: ErrorCode, message: impl Into<String>) -> Self {
+        Diagnostic {
+            severity: Severity::Warning,
+            ..Self::error(code, message)
+        }
+    }
+
+    /// The input ended while something was still expected.
+    pub fn unexpected_eof() -> Self {
+        Self::error(ErrorCode::UnexpectedEof, "Unexpected end of input")
+    }
+
+    pub fn is_unexpected_eof(&self) -> bool {
+        self.code == ErrorCode::UnexpectedEof
+    }
+
+    pub fn with_span(mut self, span: Span) -> Self {
+        self.span = Some(span);
+        self
+    }
+
+    pub fn with_label(mut self, span: Span, message: impl Into<String>) -> Self {
+        self.labels.push(Label {
+            span,
+            message: message.into(),
+        });
+        self
+    }
+
+    pub fn with_note(mut self, note: impl Into<String>) -> Self {
+        self.notes.push(note.into());
+        self
+    }
+
+    /// Render the diagnostic with excerpts of the source it points to.
+    pub fn render(&self, sources: &SourceMap) -> String {
+        let header = format!("{}[{}]", self.severity, self.code);
+        let header = match self.severity {
+            Severity::Error => paint(&header, |t| t.red().to_string()),
+            Severity::Warning => paint(&header, |t| t.yellow().to_string()),
+            Severity::Note => paint(&header, |t| t.cyan().to_string()),
+        };
+        let mut out = format!(
+            "{}: {}",
+            header,
+            paint(&self.message, |t| t.bold().to_string())
+        );
+        if let Some(span) = self.span {
+            out += &Self::render_excerpt(sources, span, "");
+        }
+        for label in &self.labels {
+            out += &Self::render_excerpt(sources, label.span, &label.message);
+        }
+        for note in &self.notes {
+            out += &format!(
+                "\n  {} {}",
+                paint("= note:", |t| t.blue().to_string()),
+                note
+            );
+        }
+        out
+    }
+
+    fn render_excerpt(sources: &SourceMap, span: Span, message: &str) -> String {
+        let file = match sources.try_get(span.file_id) {
+            Some(file) => file,
+            None => return String::new(),
+        };
+        let (line, column) = file.line_col(span.start);
+        let arrow = paint("-->", |t| t.blue().to_string());
+        let mut out = format!("\n  {} {}:{}:{}", arrow, file.name, line, column);
+        let text = match file.line(line) {
+            Some(text) => text,
+            None => return out,
+        };
+        // Underline up to the end of the first line of the span, and at least one character.
+        let (end_line, end_column) = file.line_col(span.end);
+        let width = if end_line == line {
+            end_column.saturating_sub(column)
+        } else {
+            text.chars().count() + 1 - column
+        };
+        let lead = text
+            .chars()
+            .take(column - 1)
+            .map(|c| if c == '\t' { '\t' } else { '~' })
+            .collect::<String>();
+        out += &format!(
+            "\n{text}\n{lead}{underline}",
+            text = text,
+            lead = paint(&lead, |t| t.red().to_string()),
+            underline = paint(&"^".repeat(width.max(1)), |t| t.red().to_string()),
+        );
+        if !message.is_empty() {
+            out += &format!(" {}", paint(message, |t| t.yellow().to_string()));
+        }
+        out
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}[{}]: {}", self.severity, self.code, self.message)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Diagnostic, ErrorCode};
+    use crate::source::{SourceMap, Span};
+
+    #[test]
+    fn in_memory_sources_are_rendered() {
+        let sources = SourceMap::new();
+        let id = sources.add("<snippet>", "let x: i32 = 1;\nfoo bar;\n");
+        let rendered = Diagnostic::error(ErrorCode::Syntax, "oops")
+            .with_span(Span::new(id, 20, 23))
+            .render(&sources);
+        assert!(rendered.contains("<snippet>:2:5"), "{}", rendered);
+        assert!(rendered.contains("\nfoo bar;\n"), "{}", rendered);
+        // Colors are only used once they are enabled.
+        assert!(!rendered.contains('\x1b'), "{}", rendered);
+
+        // Spans that aren't in the map are just not excerpted.
+        let rendered = Diagnostic::error(ErrorCode::Syntax, "oops")
+            .with_span(Span::new(id + 1, 0, 1))
+            .render(&sources);
+        assert!(!rendered.contains("-->"));
+    }
+}
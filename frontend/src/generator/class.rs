@@ -1,67 +1,165 @@
 use crate::c_str;
+use crate::diagnostic::{Diagnostic, ErrorCode};
 use crate::generator::Generator;
-use crate::parser::Class;
+use crate::parser::{Class, Expr, NodePosition, Repr, StructMembers};
+use crate::source::Span;
+use crate::symbol_table::ScopeKind;
 use crate::Result;
-use llvm_sys::core;
 use llvm_sys::prelude::{LLVMTypeRef, LLVMValueRef};
-use llvm_sys::LLVMIntPredicate;
-use log::{info, trace};
-use std::collections::HashMap;
+use llvm_sys::{core, target};
+use log::trace;
 
 impl Generator {
-    pub unsafe fn gen_class(&self, class: &Class) {
-        todo!()
-        // trace!("Generating class");
-        // let struct_lltype = core::LLVMStructCreateNamed(
-        //     self.context,
-        //     c_str!("$".to_owned()+&class.name)
-        // );
-        // let struct_lltype_vtable = core::LLVMStructCreateNamed(
-        //     self.context,
-        //     c_str!("$_VTable".to_owned()+&class.name)
-        // );
-        // let mut field_data = HashMap::new();
-        // field_data.insert(String::from("vtableptr"), String::from("$_VTable".to_owned()+&class.name));
-        // (*self.structs.borrow_mut()).insert(class.name.clone(), (struct_lltype.clone(), field_data));
-        // core::LLVMStructSetBody(
-        //     struct_lltype,
-        //     vec![struct_lltype_vtable].as_mut_ptr(),
-        //     2,
-        //     0,
-        // );
-        // core::LLVMAddGlobal(self.module, struct_lltype_vtable, c_str!("$_VTable".to_owned()+&class.name));
-        // self.gen_vtable(struct_lltype_vtable, class);
-        // (*self.classes.borrow_mut()).insert(class.name.clone());
+    /// Generate a class: the type of its objects and its methods.
+    ///
+    /// An object is laid out like a struct of the class's fields. There is no inheritance, so
+    /// methods are called directly by their mangled names and need no vtable.
+    pub unsafe fn gen_class(&self, class: &Class, pos: &NodePosition) -> Result<()> {
+        trace!("Generating class");
+        let llvm_name = "$class$".to_owned() + &class.name;
+        self.declare_struct(&llvm_name, &class.name, &class.fields, Repr::default());
+        self.classes.borrow_mut().insert(class.name.clone());
+
+        // Every method is declared before any is generated, so they can call each other in any
+        // order.
+        let methods = class
+            .fns
+            .iter()
+            .map(|(method, pos)| {
+                let name = Class::mangle(&class.name, &method.name);
+                let llvm_function = self.declare_function(&name, method);
+                (name, method, llvm_function, pos)
+            })
+            .collect::<Vec<_>>();
+        self.symbols
+            .borrow_mut()
+            .enter_scope(ScopeKind::Class, pos.span.start);
+        let result = methods
+            .into_iter()
+            .try_for_each(|(name, method, llvm_function, pos)| {
+                self.gen_definition(&name, method, llvm_function, pos)
+            });
+        self.symbols.borrow_mut().exit_scope();
+        result
     }
 
-    pub unsafe fn gen_vtable(&self, vtable: LLVMTypeRef, class: &Class) {
-        todo!()
-        // let vtable_name = c_str!("$_VTable".to_owned()+&class.name);
-        // let vtable = core::LLVMGetTypeByName2(self.context, vtable_name);
-        // let mut vtable_methods = vec![];
-        // let mut vtable_method_types = vec![];
-        // for (method, _) in &class.fns {
-        //     vtable_methods.push(core::LLVMGetNamedFunction(self.module, c_str!(method.name)));
-        //     vtable_method_types.push(self.str_to_type(method.return_type.clone()));
-        // }
-        // trace!("Generating vtable");
+    /// Make a new object of `class` and pass it and `args` to the class's `init`, if it has one.
+    pub(super) unsafe fn gen_construction(
+        &self,
+        class: &str,
+        args: &[Expr],
+        span: Span,
+    ) -> Result<(LLVMValueRef, LLVMTypeRef)> {
+        let struct_type = self.structs.borrow()[class].0;
+        // Like a struct's fields, the arguments are evaluated before the object exists.
+        let mut values = vec![];
+        for arg in args {
+            values.push(self.gen_expression(&arg.value, arg.span)?.0);
+        }
+        // The heap hands out zeroed memory, so fields `init` doesn't set start at zero.
+        let object = self.build_gc_alloc(struct_type);
+        let init = core::LLVMGetNamedFunction(self.module, c_str!(Class::mangle(class, "init")));
+        if !init.is_null() {
+            values.insert(0, object);
+            self.build_call(init, &mut values);
+        } else if !values.is_empty() {
+            return Err(Diagnostic::error(
+                ErrorCode::Codegen,
+                format!(
+                    "`{}` has no `init` to take {} argument(s)",
+                    class,
+                    values.len()
+                ),
+            )
+            .with_span(span));
+        }
+        Ok((object, self.pointer_type(struct_type)))
     }
 
-    pub unsafe fn gen_struct(&self, name: &String, struct_: &HashMap<String, (String, i32)>) {
-        let struct_lltype =
-            core::LLVMStructCreateNamed(self.context, c_str!("$struct$".to_owned() + &name));
-        let mut types = vec![];
-        for (key, (value, index)) in struct_.into_iter() {
-            types.push(self.str_to_type(value.to_string()));
-            println!("{} : {} ({})", key, value, index);
+    /// Call `method` on `object`, an instance of `class`, with `args`.
+    pub(super) unsafe fn gen_method_call(
+        &self,
+        object: &Expr,
+        class: &str,
+        method: &str,
+        args: &[Expr],
+        span: Span,
+    ) -> Result<(LLVMValueRef, LLVMTypeRef)> {
+        let (object, _) = self.gen_expression(&object.value, object.span)?;
+        let function =
+            core::LLVMGetNamedFunction(self.module, c_str!(Class::mangle(class, method)));
+        if function.is_null() {
+            return Err(Diagnostic::error(
+                ErrorCode::Codegen,
+                format!("Method `{}` doesn't exist", method),
+            )
+            .with_span(span));
         }
-        core::LLVMStructSetBody(struct_lltype, types.as_mut_ptr(), types.len() as u32, 0);
+        let mut values = vec![object];
+        for arg in args {
+            values.push(self.gen_expression(&arg.value, arg.span)?.0);
+        }
+        let result = self.build_call(function, &mut values);
+        Ok((
+            result,
+            core::LLVMGetReturnType(core::LLVMGlobalGetValueType(function)),
+        ))
+    }
+
+    /// Declare the struct `name`, laid out as `repr` asks.
+    pub unsafe fn gen_struct(&self, name: &str, struct_: &StructMembers, repr: Repr) {
+        let struct_lltype =
+            self.declare_struct(&("$struct$".to_owned() + name), name, struct_, repr);
         core::LLVMAddGlobal(
             self.module,
             struct_lltype,
-            c_str!("$struct_ty$".to_owned() + &name),
+            c_str!("$struct_ty$".to_owned() + name),
         );
-        (*self.structs.borrow_mut()).insert(name.clone(), (struct_lltype, struct_.clone()));
         trace!("Generating struct");
     }
+
+    /// Create the LLVM type `llvm_name` of the struct or class `name`, and make it known.
+    unsafe fn declare_struct(
+        &self,
+        llvm_name: &str,
+        name: &str,
+        struct_: &StructMembers,
+        repr: Repr,
+    ) -> LLVMTypeRef {
+        let struct_lltype = core::LLVMStructCreateNamed(self.context, c_str!(llvm_name));
+        // Known before its body, so a field can point to a struct of the same type.
+        (*self.structs.borrow_mut()).insert(name.to_string(), (struct_lltype, struct_.clone()));
+        // Fields are laid out in declaration order, which is also the order C lays them out in.
+        // LLVM pads them the way the target's C ABI does, so `repr(C)` needs nothing more.
+        let mut types = struct_
+            .iter()
+            .map(|member| self.str_to_type(member.type_.to_string()))
+            .collect::<Vec<_>>();
+        // LLVM types have no alignment of their own, but only the size that follows from it
+        // matters to a struct that is always behind a pointer. It is made up with padding at
+        // the end, after every field.
+        if let Some(align) = repr.align {
+            let natural = core::LLVMStructTypeInContext(
+                self.context,
+                types.as_mut_ptr(),
+                types.len() as u32,
+                false as i32,
+            );
+            let data_layout = target::LLVMGetModuleDataLayout(self.module);
+            let size = target::LLVMABISizeOfType(data_layout, natural);
+            let padding = (align as u64 - size % align as u64) % align as u64;
+            if padding > 0 {
+                types.push(
+                    self.array_type(padding as u32, core::LLVMInt8TypeInContext(self.context)),
+                );
+            }
+        }
+        core::LLVMStructSetBody(
+            struct_lltype,
+            types.as_mut_ptr(),
+            types.len() as u32,
+            repr.packed as i32,
+        );
+        struct_lltype
+    }
 }
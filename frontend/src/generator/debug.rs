@@ -0,0 +1,367 @@
+use crate::generator::Generator;
+use crate::parser::Function;
+use crate::source::{FileId, Span};
+use libc::c_char;
+use llvm_sys::debuginfo::{
+    self as di, LLVMDIFlagPrototyped, LLVMDIFlagZero, LLVMDWARFEmissionKind,
+    LLVMDWARFSourceLanguage,
+};
+use llvm_sys::prelude::{LLVMDIBuilderRef, LLVMMetadataRef, LLVMValueRef};
+use llvm_sys::{core, target, LLVMModuleFlagBehavior};
+use log::debug;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::path::Path;
+use std::{env, ptr};
+
+/// `DW_ATE_boolean`
+const DW_ATE_BOOLEAN: u32 = 0x02;
+/// `DW_ATE_signed`
+const DW_ATE_SIGNED: u32 = 0x05;
+/// `DW_ATE_unsigned_char`
+const DW_ATE_UNSIGNED_CHAR: u32 = 0x08;
+
+/// The DWARF debug information of a module, built while its IR is generated.
+pub(super) struct DebugInfo {
+    builder: LLVMDIBuilderRef,
+    /// The debug info file of every source file seen so far.
+    files: RefCell<HashMap<FileId, LLVMMetadataRef>>,
+    /// The function and the blocks the builder is in, innermost last.
+    scopes: RefCell<Vec<LLVMMetadataRef>>,
+}
+
+impl DebugInfo {
+    pub(super) unsafe fn dispose(&self) {
+        di::LLVMDisposeDIBuilder(self.builder);
+    }
+}
+
+impl Generator {
+    /// Emit DWARF debug information for everything generated from now on.
+    ///
+    /// # Arguments
+    /// * `optimized` - Whether the module will be optimized, which debuggers are told about.
+    pub unsafe fn init_debug_info(&mut self, optimized: bool) {
+        let builder = di::LLVMCreateDIBuilder(self.module);
+        let debug = DebugInfo {
+            builder,
+            files: RefCell::new(HashMap::new()),
+            scopes: RefCell::new(Vec::new()),
+        };
+        let file = match self.program.first() {
+            Some((_, pos)) => self.di_file(&debug, pos.span.file_id),
+            None => {
+                let name = "<unknown>";
+                di::LLVMDIBuilderCreateFile(
+                    builder,
+                    name.as_ptr() as *const c_char,
+                    name.len(),
+                    ptr::null(),
+                    0,
+                )
+            }
+        };
+        let producer = format!("skippc {}", env!("CARGO_PKG_VERSION"));
+        di::LLVMDIBuilderCreateCompileUnit(
+            builder,
+            // DWARF has no code for skipp, and C is what debuggers understand best.
+            LLVMDWARFSourceLanguage::LLVMDWARFSourceLanguageC,
+            file,
+            producer.as_ptr() as *const c_char,
+            producer.len(),
+            optimized as i32,
+            ptr::null(),
+            0,
+            0,
+            ptr::null(),
+            0,
+            LLVMDWARFEmissionKind::LLVMDWARFEmissionKindFull,
+            0,
+            0,
+            0,
+            ptr::null(),
+            0,
+            ptr::null(),
+            0,
+        );
+
+        for (flag, value) in [
+            ("Debug Info Version", di::LLVMDebugMetadataVersion()),
+            ("Dwarf Version", 4),
+        ] {
+            let value = core::LLVMConstInt(self.i32_type(), value as u64, false as i32);
+            core::LLVMAddModuleFlag(
+                self.module,
+                LLVMModuleFlagBehavior::LLVMModuleFlagBehaviorWarning,
+                flag.as_ptr() as *const c_char,
+                flag.len(),
+                core::LLVMValueAsMetadata(value),
+            );
+        }
+
+        self.debug = Some(debug);
+        debug!("Emitting debug information");
+    }
+
+    /// Resolve the debug information that is still incomplete. Must be called before the module
+    /// is verified or emitted.
+    pub(super) unsafe fn finalize_debug_info(&self) {
+        if let Some(debug) = &self.debug {
+            di::LLVMDIBuilderFinalize(debug.builder);
+        }
+    }
+
+    /// Describe `function` and make it the scope of the locations that follow.
+    pub(super) unsafe fn debug_enter_function(
+        &self,
+        function: &Function,
+        llvm_function: LLVMValueRef,
+        span: Span,
+    ) {
+        let debug = match &self.debug {
+            Some(debug) => debug,
+            None => return,
+        };
+        let file = self.di_file(debug, span.file_id);
+        let (line, _) = self.line_col(span);
+
+        // The return type comes first, and null stands for `void`.
+        let mut types = std::iter::once(&function.return_type)
+            .chain(&function.args.type_)
+            .map(|type_| self.di_type(debug, type_))
+            .collect::<Vec<_>>();
+        let subroutine_type = di::LLVMDIBuilderCreateSubroutineType(
+            debug.builder,
+            file,
+            types.as_mut_ptr(),
+            types.len() as u32,
+            LLVMDIFlagZero,
+        );
+        // Methods are linked under their mangled names.
+        let mut linkage_name_len = 0;
+        let linkage_name = core::LLVMGetValueName2(llvm_function, &mut linkage_name_len);
+        let subprogram = di::LLVMDIBuilderCreateFunction(
+            debug.builder,
+            file,
+            function.name.as_ptr() as *const c_char,
+            function.name.len(),
+            linkage_name,
+            linkage_name_len,
+            file,
+            line,
+            subroutine_type,
+            false as i32,
+            true as i32,
+            line,
+            LLVMDIFlagPrototyped,
+            false as i32,
+        );
+        di::LLVMSetSubprogram(llvm_function, subprogram);
+        debug.scopes.borrow_mut().push(subprogram);
+        self.set_debug_location(span);
+    }
+
+    /// Open a lexical block, such as a `do` block, starting at `span`.
+    pub(super) unsafe fn debug_enter_block(&self, span: Span) {
+        let debug = match &self.debug {
+            Some(debug) => debug,
+            None => return,
+        };
+        let parent = match debug.scopes.borrow().last() {
+            Some(&parent) => parent,
+            None => return,
+        };
+        let (line, column) = self.line_col(span);
+        let block = di::LLVMDIBuilderCreateLexicalBlock(
+            debug.builder,
+            parent,
+            self.di_file(debug, span.file_id),
+            line,
+            column,
+        );
+        debug.scopes.borrow_mut().push(block);
+    }
+
+    /// Close the innermost function or block opened with `debug_enter_*`.
+    pub(super) unsafe fn debug_exit_scope(&self) {
+        if let Some(debug) = &self.debug {
+            debug.scopes.borrow_mut().pop();
+            if debug.scopes.borrow().is_empty() {
+                core::LLVMSetCurrentDebugLocation2(self.builder, ptr::null_mut());
+            }
+        }
+    }
+
+    /// Describe the variable or parameter `name` of type `type_`, stored in `storage`.
+    ///
+    /// # Arguments
+    /// * `argument` - The 1-based position of a parameter, or `None` for a local variable.
+    pub(super) unsafe fn debug_declare(
+        &self,
+        name: &str,
+        argument: Option<u32>,
+        storage: LLVMValueRef,
+        type_: &str,
+        span: Span,
+    ) {
+        let debug = match &self.debug {
+            Some(debug) => debug,
+            None => return,
+        };
+        let scope = match debug.scopes.borrow().last() {
+            Some(&scope) => scope,
+            None => return,
+        };
+        let file = self.di_file(debug, span.file_id);
+        let (line, column) = self.line_col(span);
+        let di_type = self.di_type(debug, type_);
+        let variable = match argument {
+            Some(argument) => di::LLVMDIBuilderCreateParameterVariable(
+                debug.builder,
+                scope,
+                name.as_ptr() as *const c_char,
+                name.len(),
+                argument,
+                file,
+                line,
+                di_type,
+                true as i32,
+                LLVMDIFlagZero,
+            ),
+            None => di::LLVMDIBuilderCreateAutoVariable(
+                debug.builder,
+                scope,
+                name.as_ptr() as *const c_char,
+                name.len(),
+                file,
+                line,
+                di_type,
+                true as i32,
+                LLVMDIFlagZero,
+                0,
+            ),
+        };
+        let location = di::LLVMDIBuilderCreateDebugLocation(
+            self.context,
+            line,
+            column,
+            scope,
+            ptr::null_mut(),
+        );
+        di::LLVMDIBuilderInsertDeclareAtEnd(
+            debug.builder,
+            storage,
+            variable,
+            di::LLVMDIBuilderCreateExpression(debug.builder, ptr::null_mut(), 0),
+            location,
+            core::LLVMGetInsertBlock(self.builder),
+        );
+    }
+
+    /// Attribute the instructions built from now on to `span`, and return the location they
+    /// were attributed to before.
+    pub(super) unsafe fn set_debug_location(&self, span: Span) -> LLVMMetadataRef {
+        let debug = match &self.debug {
+            Some(debug) => debug,
+            None => return ptr::null_mut(),
+        };
+        let previous = core::LLVMGetCurrentDebugLocation2(self.builder);
+        // Code outside of functions has no scope to be located in.
+        if let Some(&scope) = debug.scopes.borrow().last() {
+            let (line, column) = self.line_col(span);
+            let location = di::LLVMDIBuilderCreateDebugLocation(
+                self.context,
+                line,
+                column,
+                scope,
+                ptr::null_mut(),
+            );
+            core::LLVMSetCurrentDebugLocation2(self.builder, location);
+        }
+        previous
+    }
+
+    /// Go back to a location returned by [`Generator::set_debug_location`].
+    pub(super) unsafe fn restore_debug_location(&self, location: LLVMMetadataRef) {
+        if self.debug.is_some() {
+            core::LLVMSetCurrentDebugLocation2(self.builder, location);
+        }
+    }
+
+    /// The debug info file for the source file `id`, with an absolute directory so debuggers
+    /// find it from anywhere.
+    unsafe fn di_file(&self, debug: &DebugInfo, id: FileId) -> LLVMMetadataRef {
+        if let Some(&file) = debug.files.borrow().get(&id) {
+            return file;
+        }
+        let name = self
+            .sources
+            .try_get(id)
+            .map_or_else(|| String::from("<unknown>"), |file| file.name.clone());
+        let path = env::current_dir()
+            .map_or_else(|_| Path::new(&name).to_path_buf(), |cwd| cwd.join(&name));
+        let file_name = path
+            .file_name()
+            .map_or(name.clone(), |n| n.to_string_lossy().into_owned());
+        let directory = path
+            .parent()
+            .map_or(String::new(), |d| d.to_string_lossy().into_owned());
+        let file = di::LLVMDIBuilderCreateFile(
+            debug.builder,
+            file_name.as_ptr() as *const c_char,
+            file_name.len(),
+            directory.as_ptr() as *const c_char,
+            directory.len(),
+        );
+        debug.files.borrow_mut().insert(id, file);
+        file
+    }
+
+    /// The debug info type of the skipp type `type_`, or null for `void`.
+    unsafe fn di_type(&self, debug: &DebugInfo, type_: &str) -> LLVMMetadataRef {
+        let basic = |name: &str, bits: u64, encoding: u32| {
+            di::LLVMDIBuilderCreateBasicType(
+                debug.builder,
+                name.as_ptr() as *const c_char,
+                name.len(),
+                bits,
+                encoding,
+                LLVMDIFlagZero,
+            )
+        };
+        let pointer = |pointee: LLVMMetadataRef, name: &str| {
+            let bits = target::LLVMPointerSize(target::LLVMGetModuleDataLayout(self.module)) * 8;
+            di::LLVMDIBuilderCreatePointerType(
+                debug.builder,
+                pointee,
+                bits as u64,
+                0,
+                0,
+                name.as_ptr() as *const c_char,
+                name.len(),
+            )
+        };
+        match type_ {
+            "void" => ptr::null_mut(),
+            "i32" => basic("i32", 32, DW_ATE_SIGNED),
+            "i64" => basic("i64", 64, DW_ATE_SIGNED),
+            "bool" => basic("bool", 8, DW_ATE_BOOLEAN),
+            "str" => pointer(basic("u8", 8, DW_ATE_UNSIGNED_CHAR), "str"),
+            "intarr" => pointer(basic("i32", 32, DW_ATE_SIGNED), "intarr"),
+            // Structs are only named for now; their layout isn't described.
+            name => di::LLVMDIBuilderCreateUnspecifiedType(
+                debug.builder,
+                name.as_ptr() as *const c_char,
+                name.len(),
+            ),
+        }
+    }
+
+    /// The 1-based line and column where `span` starts.
+    fn line_col(&self, span: Span) -> (u32, u32) {
+        self.sources.try_get(span.file_id).map_or((0, 0), |file| {
+            let (line, column) = file.line_col(span.start);
+            (line as u32, column as u32)
+        })
+    }
+}
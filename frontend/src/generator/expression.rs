@@ -1,74 +1,93 @@
 use crate::c_str;
+use crate::diagnostic::{Diagnostic, ErrorCode};
 use crate::generator::Generator;
-use crate::parser::ExprValue;
+use crate::parser::{Expr, ExprValue};
 use crate::lexer::tokens::TokenType;
+use crate::source::Span;
+use crate::symbol_table::ScopeKind;
 use crate::Result;
 use llvm_sys::core;
 use llvm_sys::prelude::{LLVMValueRef, LLVMBasicBlockRef, LLVMTypeRef};
-use llvm_sys::LLVMIntPredicate;
+use llvm_sys::{LLVMIntPredicate, LLVMTypeKind};
 use log::{trace, info};
 
-use std::collections::HashMap;
-
 impl Generator {
-    pub unsafe fn gen_expression(&self, expression: &ExprValue) -> Result<(LLVMValueRef, LLVMTypeRef)> {
+    pub unsafe fn gen_expression(&self, expression: &ExprValue, span: Span) -> Result<(LLVMValueRef, LLVMTypeRef)> {
+        // Code built after a subexpression still belongs to the enclosing expression.
+        let location = self.set_debug_location(span);
+        let result = self.gen_expression_value(expression, span);
+        self.restore_debug_location(location);
+        result
+    }
+
+    unsafe fn gen_expression_value(&self, expression: &ExprValue, span: Span) -> Result<(LLVMValueRef, LLVMTypeRef)> {
         trace!("Generating expression");
         match expression {
             ExprValue::Integer(i) => {
-                return Ok((
+                Ok((
                     core::LLVMConstInt(self.i32_type(), *i as u64, false as i32),
                     self.i32_type()
-                ));
+                ))
             }
             ExprValue::Do(expressions) => {
-                let mut ret_val = Ok((core::LLVMConstInt(self.i32_type(), 0 as u64, false as i32), self.i32_type()));
+                self.symbols.borrow_mut().enter_scope(ScopeKind::Block, span.start);
+                self.debug_enter_block(span);
+                let mut ret_val = Ok((core::LLVMConstInt(self.i32_type(), 0, false as i32), self.i32_type()));
                 for expression in expressions {
-                    ret_val = self.gen_expression(expression);
+                    // Nothing after a `return` is reached.
+                    if !self.no_terminator() {
+                        break;
+                    }
+                    ret_val = self.gen_expression(&expression.value, expression.span);
+                    if ret_val.is_err() {
+                        break;
+                    }
                 }
-                return ret_val
+                self.debug_exit_scope();
+                self.symbols.borrow_mut().exit_scope();
+                ret_val
             }
             ExprValue::Str(s) => {
-                return Ok((core::LLVMConstString(
+                // A `str` is a pointer to NUL-terminated bytes, as the runtime expects.
+                Ok((core::LLVMBuildGlobalStringPtr(
+                    self.builder,
                     c_str!(s),
-                    s.len() as u32,
-                    false as i32,
-                ), self.str_type(s.len() as u32)));
+                    c_str!("str"),
+                ), self.pstr_type()))
             }
             ExprValue::Boolean(b)=>{
                 trace!("Boolean literal: {}", *b as u64);
                 Ok((core::LLVMConstInt(self.bool_type(), *b as u64, false as i32), self.bool_type()))
             }
-            ExprValue::Array(v, t)=>{
-               let mut vals=  v.iter().map(|x| self.gen_expression(x).expect("oops").0).collect::<Vec<_>>();
-               return Ok((core::LLVMConstArray(
-                    match t.as_str() {
-                        "i32" => self.i32_type(),
-                        "bool"=>self.bool_type(),
-                        "void"=>self.void_type(),
-                        "str"=>self.pstr_type(),               
-                        s if &s[0..3] == "str" => {
-                            self.str_type(s[3..].parse::<u32>().unwrap())
-                        },
-                        _=>todo!()
-                    },
-                    vals.as_mut_ptr(),
-                    vals.len() as u32
-                ), self.array_type(vals.len() as u32, match t.as_str() {
-                        "i32" => self.i32_type(),
-                        "bool"=>self.bool_type(),
-                        "void"=>self.void_type(),
-                        "str"=>self.pstr_type(),               
-                        s if &s[0..3] == "str" => {
-                            self.str_type(s[3..].parse::<u32>().unwrap())
-                        },
-                        _=>todo!()
-                    },)))
+            ExprValue::Array(elements, _) => {
+                // The checker only lets through arrays of `i32`, which live on the garbage-collected
+                // heap like those `intarr_new` makes. The elements are evaluated in order first.
+                let mut values = vec![];
+                for element in elements {
+                    values.push(self.gen_expression(&element.value, element.span)?.0);
+                }
+                let array_type = self.array_type(values.len() as u32, self.i32_type());
+                let array = self.build_gc_alloc(array_type);
+                let array = core::LLVMBuildPointerCast(self.builder, array, self.parr_type(), c_str!(""));
+                for (index, value) in values.into_iter().enumerate() {
+                    let element = core::LLVMBuildGEP2(
+                        self.builder,
+                        self.i32_type(),
+                        array,
+                        [core::LLVMConstInt(self.i32_type(), index as u64, false as i32)].as_mut_ptr(),
+                        1,
+                        c_str!(""),
+                    );
+                    core::LLVMBuildStore(self.builder, value, element);
+                }
+                Ok((array, self.parr_type()))
             }
             ExprValue::UnOp ( op, expression ) => {
                 trace!("Generating unary expression");
                 match **op {
+                    TokenType::Plus => self.gen_expression(&expression.value, expression.span),
                     TokenType::Minus => {
-                        let (expr, type_) = self.gen_expression(expression)?;
+                        let (expr, type_) = self.gen_expression(&expression.value, expression.span)?;
                         Ok((core::LLVMBuildNeg(
                             self.builder,
                             expr,
@@ -76,255 +95,191 @@ impl Generator {
                         ), type_))
                     }
                     TokenType::Not => {
-                        let (expr, type_) = self.gen_expression(expression)?;
+                        let (expr, type_) = self.gen_expression(&expression.value, expression.span)?;
                         Ok((core::LLVMBuildNot(
                             self.builder,
                             expr,
                             c_str!(""),
                         ), type_))
                     }
-                    _ => Err("Unidentified unary expression".to_string()),
+                    _ => Err(Diagnostic::error(ErrorCode::Codegen, "Unidentified unary expression")),
                 }
             }
             ExprValue::Identifier(name) => {
                 
-                if let Some((var, lltype)) = self.local_vars.borrow().get(name) {
+                if let Some((var, lltype)) = self.variable(name, span) {
                     trace!("Local variable: {}", name);
                     Ok((core::LLVMBuildLoad2(
                         self.builder,
-                        lltype.clone(),
-                        *var,
+                        lltype,
+                        var,
                         c_str!(""),
-                    ), lltype.clone()))
+                    ), lltype))
                 } else {
                     
-                    Err(format!("Unresolved variable reference `{}`", name))
+                    Err(Diagnostic::error(
+                        ErrorCode::Codegen,
+                        format!("Unresolved variable reference `{}`", name),
+                    ))
                 }
             }
             ExprValue::FnCall(name, args) => {
 
-                // if self.classes.borrow().contains(name) {
-                //     println!("Class pointer {:?}", core::LLVMGetTypeByName2(self.context, c_str!("$".to_owned()+name)));
-                //     let class = core::LLVMGetTypeByName2(self.context, c_str!("$".to_owned()+name));
-                //     let dummy_ptr = core::LLVMBuildStructGEP2(
-                //         self.builder,
-                //         class,
-                //         core::LLVMConstNull(class),
-                //         0,
-                //         c_str!("objptr"),
-                //     );
-                //     // println!("obj size {:?}", object_size);
-                //     println!("GC_malloc {:?}", core::LLVMGetNamedFunction(self.module, c_str!("GC_malloc")));
-                //     // NOT LINKED! 
-                //     let obj_void_ptr = core::LLVMBuildCall2(
-                //         self.builder,
-                //         class,
-                //         core::LLVMGetNamedFunction(self.module, c_str!("GC_malloc")),
-                //         [core::LLVMConstInt(self.i32_type(), 4, false as i32)].as_mut_ptr(),
-                //         1,
-                //         c_str!("")
-                //     );
-                //     println!("object {:?}", obj_void_ptr);
-                //     let obj = core::LLVMBuildPointerCast(
-                //         self.builder,
-                //         obj_void_ptr,
-                //         class,
-                //         c_str!(""),
-                //     );
-                //     let vtable_name = "$_VTable".to_owned()+name;
-                //     let vtable_field = core::LLVMBuildStructGEP2(
-                //         self.builder,
-                //         class,
-                //         obj,
-                //         0,
-                //         c_str!("obj"),
-                //     );
-                //     println!("vtable_field (obj pointer) {:?}", vtable_field);
-                //     let vtable = core::LLVMGetNamedGlobal(self.module, c_str!(vtable_name));
-                //     // println!("VTable {:?}", vtable);
-                //     if vtable==std::ptr::null_mut() {
-                //         panic!("No Vtable found for {:}", vtable_name);
-                //     }
-                //     core::LLVMBuildStore(self.builder, vtable, vtable_field);
-                //     println!("Classes todo!");
-                //     return Ok(vtable)
-                // }
+                if self.classes.borrow().contains(name) {
+                    return self.gen_construction(name, args, span);
+                }
 
-                match self.structs.borrow().get(name) {
-                    Some((t,v))=>{
-                        if args.len()!=v.len() {
-                            panic!("NO! INCORRECT NUMBER OF STRUCT PARAMS");
-                        }
-                        let mut vals = vec![];
-                        for arg in args {
-                            vals.push(self.gen_expression(arg)?.0);
-                        }
-                        let var = core::LLVMBuildAlloca(
-                            self.builder, 
-                            core::LLVMGetTypeByName2(self.context, c_str!("$struct$".to_owned()+name)),
-                            c_str!("")
-                        );
-                        let struct_init = core::LLVMBuildStore(self.builder, 
-                            var, 
-                            core::LLVMConstNamedStruct(t.clone(), vals.as_mut_ptr(), vals.len() as u32)
+                if let Some((struct_type, members)) = self.structs.borrow().get(name) {
+                    if args.len() != members.len() {
+                        return Err(Diagnostic::error(
+                            ErrorCode::Codegen,
+                            format!("`{}` takes {} fields, found {}", name, members.len(), args.len()),
+                        ).with_span(span));
+                    }
+                    // The fields are evaluated in order before the object exists.
+                    let mut values = vec![];
+                    for arg in args {
+                        values.push(self.gen_expression(&arg.value, arg.span)?.0);
+                    }
+                    let object = self.build_gc_alloc(*struct_type);
+                    for (index, (member, value)) in members.iter().zip(values).enumerate() {
+                        let field = core::LLVMBuildStructGEP2(
+                            self.builder,
+                            *struct_type,
+                            object,
+                            index as u32,
+                            c_str!(""),
                         );
-                        println!("Struct type pointer {:?}", core::LLVMGetTypeByName2(self.context, c_str!("$struct$".to_owned()+name)));
-                        return Ok((struct_init, t.clone()));
-                        // panic!("aaaaaaaaaaa");
+                        let lltype = self.str_to_type(member.type_.clone());
+                        let value = self.coerce(value, lltype);
+                        let store = core::LLVMBuildStore(self.builder, value, field);
+                        self.align_field_access(store, *struct_type);
                     }
-                    None=>{}
-                    // return Ok(vtable)
-                }
-
-                // if name.as_str()=="init_struct" {
-                //     self.init();
-                //     println!("INIT!");
-                //     return Ok(core::LLVMConstInt(self.i32_type(), 0, false as i32));
-                // }
-                
-                let mut llvm_args: Vec<LLVMValueRef> = Vec::new();
-                for arg in args {
-                    llvm_args.push(self.gen_expression(arg)?.0);
+                    return Ok((object, self.pointer_type(*struct_type)));
                 }
 
                 let function = core::LLVMGetNamedFunction(self.module, c_str!(name));
                 if function.is_null() {
-                    return Err(format!("Function `{}` doesn't exist", name));
+                    return Err(Diagnostic::error(
+                        ErrorCode::Codegen,
+                        format!("Function `{}` doesn't exist", name),
+                    ));
                 }
-                Ok((core::LLVMBuildCall2(
-                    self.builder,
-                    llvm_sys::core::LLVMGlobalGetValueType(function),
-                    function,
-                    llvm_args.as_mut_ptr(),
-                    args.len() as u32,
-                    c_str!(""),
-                ), llvm_sys::core::LLVMGlobalGetValueType(function)))
+                let mut llvm_args: Vec<LLVMValueRef> = Vec::new();
+                for arg in args {
+                    llvm_args.push(self.gen_expression(&arg.value, arg.span)?.0);
+                }
+                Ok((self.build_call(function, &mut llvm_args), core::LLVMGetReturnType(core::LLVMGlobalGetValueType(function))))
+            }
+            ExprValue::MethodCall { object, method, args, class } => {
+                self.gen_method_call(object, class, method, args, span)
             }
             ExprValue::Return (expr) => {
                 
-                let (val, type_) = self.gen_expression(expr)?;
+                let (val, type_) = self.gen_expression(&expr.value, expr.span)?;
+                let val = match *self.current_fn.borrow() {
+                    Some(f) => self.coerce(val, core::LLVMGetReturnType(core::LLVMGlobalGetValueType(f))),
+                    None => val,
+                };
                 core::LLVMBuildRet(self.builder, val);
                 Ok((val, type_))
             }
             ExprValue::VarDecl { name, type_, value } => {
                 trace!("Generating variable declaration {}", name);
-                let mut local_vars_mut = self.local_vars.borrow_mut();
-
-                if local_vars_mut.contains_key(name) {
-                    return Err(format!("Variable `{}` already exists", name));
-                }
+                let id = match self.symbols.borrow().declared_at(name, span) {
+                    Some(id) => id,
+                    None => return Err(Diagnostic::error(
+                        ErrorCode::Codegen,
+                        format!("Variable `{}` was not declared by the parser", name),
+                    ).with_span(span)),
+                };
 
                 let lltype = self.str_to_type(type_.to_string());
 
-                let var = core::LLVMBuildAlloca(
-                    self.builder, 
-                    lltype,
-                    c_str!("")
-                );
+                let var = self.build_entry_alloca(lltype);
                 info!("Adding `{}` to local vars", name);
-                local_vars_mut.insert(String::from(name), (var, lltype));
-                self.scope_var_names
-                    .borrow_mut()
-                    .last_mut()
-                    .unwrap()
-                    .push(String::from(name));
-
-                drop(local_vars_mut);
+                self.debug_declare(name, None, var, type_, span);
 
                 if let Some(v) = value {
-                    
-                    let (val, type_) = self.gen_expression(v)?;
-                    assert_eq!(type_, lltype);
-                    return Ok((core::LLVMBuildStore(self.builder, 
-                        val, 
-                        match self.local_vars.borrow().get(name) {
-                            Some((v, _))=>*v,
-                            None=>panic!("No such variable")
-                        }
-                    ), type_))
+                    // Generated before the variable exists, so `let x: i32 = x` reads an outer `x`.
+                    let (val, type_) = self.gen_expression(&v.value, v.span)?;
+                    let val = self.coerce(val, lltype);
+                    if type_ != lltype && core::LLVMTypeOf(val) != lltype {
+                        return Err(Diagnostic::error(
+                            ErrorCode::Codegen,
+                            format!("Initializer of `{}` has the wrong type", name),
+                        ));
+                    }
+                    self.values.borrow_mut().insert(id, (var, lltype));
+                    return Ok((core::LLVMBuildStore(self.builder, val, var), type_))
                 }
-                Ok((var, lltype))
+                self.values.borrow_mut().insert(id, (var, lltype));
+                Ok((core::LLVMBuildStore(self.builder, self.zero_value(type_, lltype), var), lltype))
             }
             ExprValue::Assign {name, value} =>{
-                let (expr, type_) = self.gen_expression(value)?;
-                Ok((core::LLVMBuildStore(self.builder, 
-                    expr, 
-                    match self.local_vars.borrow().get(name) {
-                        Some((v, _))=>*v,
-                        None=>panic!("No such variable")
-                    }
-                ), type_))
+                let (expr, type_) = self.gen_expression(&value.value, value.span)?;
+                let (var, lltype) = match self.variable(name, span) {
+                    Some(variable) => variable,
+                    None => panic!("No such variable"),
+                };
+                Ok((core::LLVMBuildStore(self.builder, self.coerce(expr, lltype), var), type_))
+            }
+            ExprValue::AugAssign { name, op, value } => {
+                let (value, _) = self.gen_expression(&value.value, value.span)?;
+                let (var, lltype) = match self.variable(name, span) {
+                    Some(variable) => variable,
+                    None => return Err(Diagnostic::error(
+                        ErrorCode::Codegen,
+                        format!("Unresolved variable reference `{}`", name),
+                    ).with_span(span)),
+                };
+                let current = core::LLVMBuildLoad2(self.builder, lltype, var, c_str!(""));
+                let result = self.build_aug_op(op, current, self.coerce(value, lltype), span)?;
+                Ok((core::LLVMBuildStore(self.builder, result, var), lltype))
+            }
+            ExprValue::FieldAssign { object, field, value, struct_ } => {
+                let (address, lltype, struct_type) = self.gen_field_address(object, struct_, field, span)?;
+                let (value, type_) = self.gen_expression(&value.value, value.span)?;
+                let store = core::LLVMBuildStore(self.builder, self.coerce(value, lltype), address);
+                Ok((self.align_field_access(store, struct_type), type_))
+            }
+            ExprValue::FieldAugAssign { object, field, op, value, struct_ } => {
+                // The object first and the value next, like everything else left to right.
+                let (address, lltype, struct_type) = self.gen_field_address(object, struct_, field, span)?;
+                let (value, _) = self.gen_expression(&value.value, value.span)?;
+                let current = core::LLVMBuildLoad2(self.builder, lltype, address, c_str!(field));
+                self.align_field_access(current, struct_type);
+                let result = self.build_aug_op(op, current, self.coerce(value, lltype), span)?;
+                let store = core::LLVMBuildStore(self.builder, result, address);
+                Ok((self.align_field_access(store, struct_type), lltype))
+            }
+            ExprValue::FieldAccess { object, field, struct_ } => {
+                let (address, lltype, struct_type) = self.gen_field_address(object, struct_, field, span)?;
+                let load = core::LLVMBuildLoad2(self.builder, lltype, address, c_str!(field));
+                Ok((self.align_field_access(load, struct_type), lltype))
             }
             ExprValue::BinOp(lhs, op, rhs) =>{
 
-                let (l, type_) = self.gen_expression(lhs)?;
+                let (l, type_) = self.gen_expression(&lhs.value, lhs.span)?;
 
-                if let TokenType::Dot=**op{
-                    let mut struct_: HashMap<String, (String, i32)> = HashMap::new();
-                    let mut elem_type = " ".to_string();
-
-                    for (name, (ty, struct__)) in self.structs.borrow().iter() {
-                        if *ty==type_ {
-                            struct_ = struct__.clone();
-                            break;
-                        }
-                    }
-                    // todo!();
-                    match **rhs {
-                        ExprValue::Integer(i)=>{
-                            // Ok(core::LLVMBuildGEP2(
-                            //     self.builder, 
-                            //     type_,
-                            //     l,
-                            //     [core::LLVMConstInt(self.i32_type(), 0, 0)].as_mut_ptr(),
-                            //     i,
-                            //     c_str!("struct_gep")
-                            // ), type_);
-                            todo!()
-                        }, //(i as usize).into().unwrap(),
-                        ExprValue::Identifier(ref i)=>{
-                            println!("GEP");
-                            match struct_.get(i) {
-                                Some((type__, index)) => {
-                                    // *index as u32
-                                    println!("GEP 2 {} {}({})", type__, i, index);
-                                    let x =  (core::LLVMBuildStructGEP2(
-                                        self.builder, 
-                                        type_,
-                                        l,
-                                        // [
-                                        //     core::LLVMConstInt(self.i32_type(), 0, 0),
-                                        // ].as_mut_ptr(),
-                                        *index as u32,
-                                        c_str!("struct_gep")
-                                    ), self.str_to_type(type__.to_string()));
-                                    println!("{:?} {:?}", x.0, x.1);
-                                    return Ok(x)
-                                },
-                                None => {
-                                    panic!("invalid param accessed");
-                                },
-                            }
-                        },
-                        _=>{
-                            println!("{:?}", rhs);
-                            panic!("aa");
-                        },
-                    }
-                    // return Ok((core::LLVMConstInt(self.i32_type(), 21, 0), self.i32_type()))
-                }
-
-                let (l, type_l) = self.gen_expression(lhs)?;
-                let (r, _type_r) = self.gen_expression(rhs)?;
+                let (r, type_r) = self.gen_expression(&rhs.value, rhs.span)?;
+                // A literal operand is an `i32`, and is widened to the other operand's type.
+                let wider = |a: LLVMTypeRef, b: LLVMTypeRef| {
+                    core::LLVMGetTypeKind(a) == LLVMTypeKind::LLVMIntegerTypeKind
+                        && core::LLVMGetTypeKind(b) == LLVMTypeKind::LLVMIntegerTypeKind
+                        && core::LLVMGetIntTypeWidth(a) > core::LLVMGetIntTypeWidth(b)
+                };
+                let type_l = if wider(type_r, type_) { type_r } else { type_ };
+                let (l, r) = (self.coerce(l, type_l), self.coerce(r, type_l));
 
                 // todo: handle if type_l and type_r are different
                 // for now, type of the entire expression is type_l
 
-                if let ExprValue::Str(_) = **lhs {
+                if let ExprValue::Str(_) = lhs.value {
                     todo!()
                 }
 
-                if let ExprValue::Str(_) = **rhs {
+                if let ExprValue::Str(_) = rhs.value {
                     todo!()
                 }
 
@@ -332,7 +287,7 @@ impl Generator {
                     TokenType::Plus => Ok((core::LLVMBuildAdd(self.builder, l, r, c_str!("")), type_l)),
                     TokenType::Minus => Ok((core::LLVMBuildSub(self.builder, l, r, c_str!("")), type_l)),
                     TokenType::Mul => Ok((core::LLVMBuildMul(self.builder, l, r, c_str!("")), type_l)),
-                    TokenType::Div => Ok((core::LLVMBuildSDiv(self.builder, l, r, c_str!("")), type_l)),
+                    TokenType::Div => Ok((self.build_div(l, r), type_l)),
                     TokenType::Equal | TokenType::NotEq | TokenType::Less | TokenType::Greater | TokenType::LessEq | TokenType::GreaterEq => {
                         let cmp = {
                             core::LLVMBuildICmp(
@@ -345,7 +300,8 @@ impl Generator {
                                     TokenType::LessEq => LLVMIntPredicate::LLVMIntSLE,
                                     TokenType::GreaterEq => LLVMIntPredicate::LLVMIntSGE,
                                     _ => {
-                                        return Err(format!(
+                                        return Err(Diagnostic::error(
+                                            ErrorCode::Codegen,
                                             "Unhandled comparison binary operation",
                                         ))
                                     }
@@ -370,109 +326,163 @@ impl Generator {
                     Some(s)=>s,
                     _=>unreachable!()
                 };
-                let entry = core::LLVMGetLastBasicBlock(current_fn);
-                if *self.if_count.borrow()>=1 {
-                    return Err("Cannot have two if statements in one function".to_string())
+                let (cond_llvm, _) = self.gen_expression(&cond.value, cond.span)?;
+
+                let then_bb =
+                    core::LLVMAppendBasicBlockInContext(self.context, current_fn, c_str!("then"));
+                let else_bb =
+                    core::LLVMAppendBasicBlockInContext(self.context, current_fn, c_str!("else"));
+                let merge_bb =
+                    core::LLVMAppendBasicBlockInContext(self.context, current_fn, c_str!("ifcont"));
+                core::LLVMBuildCondBr(self.builder, cond_llvm, then_bb, else_bb);
+
+                // Each branch may contain further conditionals and loops, so the block it ends in
+                // (the phi's real predecessor) is only known after generating it. A branch that
+                // returned doesn't flow into the merge block at all.
+                let expected = match type_.as_str() {
+                    "void" => None,
+                    s => Some(self.str_to_type(s.to_string())),
+                };
+                let mut incoming: Vec<(LLVMValueRef, LLVMTypeRef, LLVMBasicBlockRef)> = vec![];
+                for (branch, bb) in [(if_, then_bb), (else_, else_bb)] {
+                    core::LLVMPositionBuilderAtEnd(self.builder, bb);
+                    let (value, mut lltype) = self.gen_expression(&branch.value, branch.span)?;
+                    if self.no_terminator() {
+                        let value = match expected {
+                            Some(expected) if lltype != expected => {
+                                let value = self.coerce(value, expected);
+                                lltype = core::LLVMTypeOf(value);
+                                value
+                            }
+                            _ => value,
+                        };
+                        incoming.push((value, lltype, core::LLVMGetInsertBlock(self.builder)));
+                        core::LLVMBuildBr(self.builder, merge_bb);
+                    }
                 }
-                *self.if_count.borrow_mut()+=1;
-
-                let end = core::LLVMAppendBasicBlock(current_fn, 
-                        c_str!(format!("end.{}", *self.if_count.borrow()))
-                    ); 
-                let if_bb = core::LLVMAppendBasicBlock(current_fn, 
-                        c_str!(format!("then.{}", *self.if_count.borrow()))
-                    );
-                core::LLVMPositionBuilderAtEnd(self.builder, if_bb);
-                
-                let (if_expr, if_type) = self.gen_expression(if_).unwrap();
-
-                core::LLVMBuildBr(self.builder, end);
-                
-                let else_bb = core::LLVMAppendBasicBlock(current_fn, 
-                    c_str!(format!("else.{}", *self.if_count.borrow()))
-                );
-                core::LLVMPositionBuilderAtEnd(self.builder, else_bb);
-
-                let (else_expr, else_type) = self.gen_expression(else_).unwrap();
-
-                core::LLVMBuildBr(self.builder, end);
-
-                core::LLVMPositionBuilderAtEnd(self.builder, entry);
-                let (cond_llvm, _) = self.gen_expression(cond)?;
-                core::LLVMBuildCondBr(self.builder, cond_llvm, if_bb, else_bb);
-                core::LLVMPositionBuilderAtEnd(self.builder, end);
-
-                // let if_val = if if_exprs.len() == 0{
-                //     cond_llvm
-                // }else{
-                //     if_expr
-                // };
-
-                // let else_val = if else_exprs.len() == 0{
-                //     cond_llvm
-                // }else{
-                //     match else_exprs.last(){
-                //         Some(v)=>v.clone(),
-                //         _=>unreachable!()
-                //     }
-                // };
-
-                let phi = core::LLVMBuildPhi(
-                    self.builder, 
-                    match type_.as_str(){
-                        "i32" => self.i32_type(),
-                        "bool"=>self.bool_type(),
-                        "void"=>self.void_type(),                    
-                        s if &s[0..3] == "str" => {
-                            
-                            self.str_type(s[3..].parse::<u32>().unwrap())
-                        },
-                        x=>{
-                            print!("{:?}",x);
-                            todo!()
-                        }
-                    },
-                    c_str!("fie")
-                );
+                // If neither branch gets to the merge block, it is dropped. The builder stays at
+                // the end of the last branch, so an enclosing `if` sees that this one returned.
+                if incoming.is_empty() {
+                    core::LLVMDeleteBasicBlock(merge_bb);
+                    return Ok((core::LLVMConstInt(self.i32_type(), 0, false as i32), self.i32_type()));
+                }
+                core::LLVMPositionBuilderAtEnd(self.builder, merge_bb);
 
-                let (mut values, mut basic_blocks): (Vec<LLVMValueRef>, Vec<LLVMBasicBlockRef>) = 
-                    (vec![if_expr, else_expr],
-                    vec![end, end]);
+                let none = (core::LLVMConstInt(self.i32_type(), 0, false as i32), self.i32_type());
+                let lltype = match expected {
+                    Some(lltype) => lltype,
+                    None => return Ok(none),
+                };
+                if incoming.iter().any(|(_, t, _)| *t != lltype) {
+                    return Err(Diagnostic::error(
+                        ErrorCode::Codegen,
+                        format!("Both branches of an `if` of type `{}` must produce `{}`", type_, type_),
+                    ));
+                }
 
+                let phi = core::LLVMBuildPhi(self.builder, lltype, c_str!("iftmp"));
+                let (mut values, mut basic_blocks): (Vec<LLVMValueRef>, Vec<LLVMBasicBlockRef>) =
+                    incoming.iter().map(|(v, _, bb)| (*v, *bb)).unzip();
                 core::LLVMAddIncoming(
                     phi,
                     values.as_mut_ptr(),
                     basic_blocks.as_mut_ptr(),
-                    2,
+                    values.len() as u32,
                 );
 
-                Ok((if_expr, if_type))
+                Ok((phi, lltype))
             }
-            ExprValue::While(cond, exprs)=>{
+            ExprValue::While(cond, body) => {
+                trace!("Generating while loop");
                 let current_fn = match *self.current_fn.borrow(){
                     Some(s)=>s,
                     _=>unreachable!()
                 };
-                let entry = core::LLVMGetLastBasicBlock(current_fn);
-                let end = core::LLVMAppendBasicBlock(current_fn, 
-                        c_str!("while.end")
-                    ); 
-                let body = core::LLVMAppendBasicBlock(current_fn, 
-                        c_str!("while.body")
-                    );
-                let (cond_llvm, type_) = self.gen_expression(cond)?;
-
-                core::LLVMBuildCondBr(self.builder, cond_llvm, body, end);
-                core::LLVMPositionBuilderAtEnd(self.builder, body);
+                let cond_bb =
+                    core::LLVMAppendBasicBlockInContext(self.context, current_fn, c_str!("while.cond"));
+                let body_bb =
+                    core::LLVMAppendBasicBlockInContext(self.context, current_fn, c_str!("while.body"));
+                let end_bb =
+                    core::LLVMAppendBasicBlockInContext(self.context, current_fn, c_str!("while.end"));
+                core::LLVMBuildBr(self.builder, cond_bb);
+
+                // The condition gets its own block so it is re-evaluated before every iteration.
+                core::LLVMPositionBuilderAtEnd(self.builder, cond_bb);
+                let (cond_llvm, _) = self.gen_expression(&cond.value, cond.span)?;
+                core::LLVMBuildCondBr(self.builder, cond_llvm, body_bb, end_bb);
+
+                core::LLVMPositionBuilderAtEnd(self.builder, body_bb);
+                self.symbols.borrow_mut().enter_scope(ScopeKind::Loop, body.span.start);
+                let body_result = self.gen_expression(&body.value, body.span);
+                self.symbols.borrow_mut().exit_scope();
+                body_result?;
+                // The body may have returned already.
+                if self.no_terminator() {
+                    core::LLVMBuildBr(self.builder, cond_bb);
+                }
 
-                self.gen_expression(&*exprs);
-                
-                todo!()
+                core::LLVMPositionBuilderAtEnd(self.builder, end_bb);
+                Ok((core::LLVMConstInt(self.i32_type(), 0, false as i32), self.i32_type()))
             }
-            x=>{
+            ExprValue::Error => Err(Diagnostic::error(
+                ErrorCode::Codegen,
+                "Cannot generate code for an expression that failed to parse",
+            )),
+            _=>{
                 
                 todo!()
             }
         }
     }
+
+    /// The address and type of `field` of `object`, an instance of the struct `struct_name`,
+    /// accessed at `span`, and the type of the struct.
+    unsafe fn gen_field_address(&self, object: &Expr, struct_name: &str, field: &str, span: Span) -> Result<(LLVMValueRef, LLVMTypeRef, LLVMTypeRef)> {
+        let (pointer, _) = self.gen_expression(&object.value, object.span)?;
+        let found = self.structs.borrow().get(struct_name).and_then(|(struct_type, members)| {
+            let index = members.iter().position(|member| member.name == field)?;
+            Some((*struct_type, index, members[index].clone()))
+        });
+        let (struct_type, index, member) = match found {
+            Some(found) => found,
+            None => return Err(Diagnostic::error(
+                ErrorCode::Codegen,
+                format!("Struct `{}` has no field `{}`", struct_name, field),
+            ).with_span(span)),
+        };
+        // A struct variable declared without a value holds null.
+        let message = format!("Access to field `{}` of a null `{}`", field, struct_name);
+        self.build_panic_if_null(pointer, &message);
+        let address = core::LLVMBuildStructGEP2(
+            self.builder,
+            struct_type,
+            pointer,
+            index as u32,
+            c_str!(""),
+        );
+        Ok((address, self.str_to_type(member.type_), struct_type))
+    }
+
+    /// Make the load or store `access` of a field of `struct_type` expect no more alignment than
+    /// the field has, which in a packed struct is none.
+    unsafe fn align_field_access(&self, access: LLVMValueRef, struct_type: LLVMTypeRef) -> LLVMValueRef {
+        if core::LLVMIsPackedStruct(struct_type) != 0 {
+            core::LLVMSetAlignment(access, 1);
+        }
+        access
+    }
+
+    /// Apply the arithmetic of the compound assignment `op`, e.g. `+` for `+=`, to `l` and `r`.
+    unsafe fn build_aug_op(&self, op: &TokenType, l: LLVMValueRef, r: LLVMValueRef, span: Span) -> Result<LLVMValueRef> {
+        match op {
+            TokenType::PlusEq => Ok(core::LLVMBuildAdd(self.builder, l, r, c_str!(""))),
+            TokenType::MinusEq => Ok(core::LLVMBuildSub(self.builder, l, r, c_str!(""))),
+            TokenType::MulEq => Ok(core::LLVMBuildMul(self.builder, l, r, c_str!(""))),
+            TokenType::DivEq => Ok(self.build_div(l, r)),
+            other => Err(Diagnostic::error(
+                ErrorCode::Codegen,
+                format!("Unknown operator `{:?}`", other),
+            ).with_span(span)),
+        }
+    }
 }
\ No newline at end of file
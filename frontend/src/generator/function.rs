@@ -1,15 +1,22 @@
 use crate::c_str;
 use crate::generator::Generator;
-use crate::parser::{ExprValue, External, Function};
+use crate::parser::{ExprValue, External, Function, NodePosition};
+use crate::source::Span;
+use crate::symbol_table::ScopeKind;
 use crate::Result;
-use llvm_sys::core;
-use llvm_sys::prelude::LLVMTypeRef;
-use log::{info, trace};
+use llvm_sys::{core, LLVMTypeKind};
+use llvm_sys::prelude::{LLVMTypeRef, LLVMValueRef};
+use log::trace;
+use std::ptr;
 
 impl Generator {
-    pub unsafe fn gen_function(&self, function: &Function) -> Result<()> {
-        trace!("Generating function");
+    pub unsafe fn gen_function(&self, function: &Function, pos: &NodePosition) -> Result<()> {
+        let llvm_function = self.declare_function(&function.name, function);
+        self.gen_definition(&function.name, function, llvm_function, pos)
+    }
 
+    /// Add `function` to the module as `name`, without a body yet.
+    pub(super) unsafe fn declare_function(&self, name: &str, function: &Function) -> LLVMValueRef {
         let args = &function.args;
         let mut arg_types: Vec<LLVMTypeRef> = Vec::new();
 
@@ -25,19 +32,33 @@ impl Generator {
         // let mut arg_types = vec![self.i32_type(); args.name.len()];
 
         // Create function
-        let llvm_function = core::LLVMAddFunction(
+        core::LLVMAddFunction(
             self.module,
-            c_str!(function.name),
+            c_str!(name),
             core::LLVMFunctionType(
                 return_type,
                 arg_types.as_mut_ptr(),
                 args.name.len() as u32,
                 0,
             ),
-        );
+        )
+    }
+
+    /// Generate the body of `function`, which was declared as `llvm_function` named `name`.
+    pub(super) unsafe fn gen_definition(
+        &self,
+        name: &str,
+        function: &Function,
+        llvm_function: LLVMValueRef,
+        pos: &NodePosition,
+    ) -> Result<()> {
+        trace!("Generating function");
+        let return_type = core::LLVMGetReturnType(core::LLVMGlobalGetValueType(llvm_function));
 
         *self.current_fn.borrow_mut() = Some(llvm_function);
-        *self.if_count.borrow_mut() = 0;
+        self.function_spans
+            .borrow_mut()
+            .insert(name.to_string(), pos.span);
 
         let entry =
             core::LLVMAppendBasicBlockInContext(self.context, llvm_function, c_str!("entry"));
@@ -48,44 +69,106 @@ impl Generator {
         //     self.init();
         // }
 
+        self.debug_enter_function(function, llvm_function, pos.span);
+        self.symbols.borrow_mut().enter_scope(ScopeKind::Function, pos.span.start);
+        let result = self.gen_body(function, llvm_function, return_type);
+        self.symbols.borrow_mut().exit_scope();
+        self.debug_exit_scope();
+        result
+    }
+
+    unsafe fn gen_body(
+        &self,
+        function: &Function,
+        llvm_function: LLVMValueRef,
+        return_type: LLVMTypeRef,
+    ) -> Result<()> {
+        let args = &function.args;
         for (i, arg_name) in args.name.iter().enumerate() {
             // Set arg name in function prototype
             let arg = core::LLVMGetParam(llvm_function, i as u32);
             core::LLVMSetValueName2(arg, c_str!(arg_name), arg_name.len());
 
-            let mut local_vars_mut = self.local_vars.borrow_mut();
             let t = &args.type_[i];
             let lltype = self.str_to_type(t.clone());
             let var = core::LLVMBuildAlloca(self.builder, lltype, c_str!(""));
 
-            if arg_name != "_" {
-                local_vars_mut.insert(arg_name.to_string(), (var, lltype));
+            if let Some(id) = self.symbols.borrow().declared_at(arg_name, args.span[i]) {
+                self.values.borrow_mut().insert(id, (var, lltype));
             }
 
             core::LLVMBuildStore(self.builder, arg, var);
+            self.debug_declare(arg_name, Some(i as u32 + 1), var, t, args.span[i]);
         }
 
-        self.scope_var_names.borrow_mut().push(Vec::new());
-
         for expr in &function.expressions {
-            self.gen_expression(&expr)?;
+            // Nothing after a `return` is reached.
+            if !self.no_terminator() {
+                break;
+            }
+            self.gen_expression(&expr.value, expr.span)?;
         }
 
-        if let Some(ExprValue::Return(_)) = &function.expressions.last() {
-        } else {
-            let zero = core::LLVMConstInt(self.i32_type(), 0 as u64, false as i32);
-            core::LLVMBuildRet(self.builder, zero);
+        // Falling off the end returns nothing, or a zero value.
+        if self.no_terminator() {
+            if function.return_type == "void" {
+                core::LLVMBuildRetVoid(self.builder);
+            } else {
+                core::LLVMBuildRet(self.builder, core::LLVMConstNull(return_type));
+            }
         }
+        Ok(())
+    }
 
-        let mut local_vars_mut = self.local_vars.borrow_mut();
-        for var in self.scope_var_names.borrow().last().unwrap() {
-            local_vars_mut.remove(var);
-        }
+    /// Generate a function `name` without parameters that evaluates a top-level expression.
+    ///
+    /// The function returns the expression's value, or nothing if `return_type` is `void`. Unlike
+    /// [`Generator::gen_function`] no scope is entered, so the expression sees the scopes the
+    /// parser opened for it.
+    pub unsafe fn gen_thunk(
+        &self,
+        name: &str,
+        expression: &ExprValue,
+        span: Span,
+        return_type: &str,
+    ) -> Result<()> {
+        trace!("Generating thunk {}", name);
+        let return_type = self.str_to_type(return_type.to_string());
+        let llvm_function = core::LLVMAddFunction(
+            self.module,
+            c_str!(name),
+            core::LLVMFunctionType(return_type, ptr::null_mut(), 0, 0),
+        );
+        *self.current_fn.borrow_mut() = Some(llvm_function);
+        self.function_spans.borrow_mut().insert(name.to_string(), span);
+        let entry =
+            core::LLVMAppendBasicBlockInContext(self.context, llvm_function, c_str!("entry"));
+        core::LLVMPositionBuilderAtEnd(self.builder, entry);
 
-        self.scope_var_names.borrow_mut().pop();
+        let result = self.gen_expression(expression, span);
+        if let Err(e) = result {
+            core::LLVMDeleteFunction(llvm_function);
+            return Err(e);
+        }
+        if self.no_terminator() {
+            if core::LLVMGetTypeKind(return_type) == LLVMTypeKind::LLVMVoidTypeKind {
+                core::LLVMBuildRetVoid(self.builder);
+            } else {
+                core::LLVMBuildRet(self.builder, result?.0);
+            }
+        }
         Ok(())
     }
 
+    /// Delete the function `name` from the module, if it exists.
+    pub unsafe fn remove_function(&self, name: &str) {
+        let function = core::LLVMGetNamedFunction(self.module, c_str!(name));
+        if !function.is_null() {
+            core::LLVMDeleteFunction(function);
+        }
+        self.function_spans.borrow_mut().remove(name);
+    }
+
     pub unsafe fn gen_extern(&self, function: &External) -> Result<()> {
         trace!("Generating extern");
 
@@ -104,7 +187,7 @@ impl Generator {
         // self.functions.borrow_mut().insert(function.name.clone(), return_type);
 
         // Create function
-        let llvm_fn = core::LLVMAddFunction(
+        let llvm_function = core::LLVMAddFunction(
             self.module,
             c_str!(name),
             core::LLVMFunctionType(
@@ -114,6 +197,17 @@ impl Generator {
                 0,
             ),
         );
+
+        // C passes a `bool` in a whole byte, and expects the bits above the `i1` to be zero.
+        let kind = core::LLVMGetEnumAttributeKindForName(c_str!("zeroext"), "zeroext".len());
+        let zeroext = core::LLVMCreateEnumAttribute(self.context, kind, 0);
+        let types = std::iter::once(return_type).chain(arg_types.iter().copied());
+        for (index, type_) in types.enumerate() {
+            // Index 0 is the return value, and the parameters follow from 1.
+            if type_ == self.bool_type() {
+                core::LLVMAddAttributeAtIndex(llvm_function, index as u32, zeroext);
+            }
+        }
         Ok(())
     }
 }
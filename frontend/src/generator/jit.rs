@@ -0,0 +1,205 @@
+use crate::c_str;
+use crate::diagnostic::{Diagnostic, ErrorCode};
+use crate::generator::Generator;
+use crate::runtime;
+use crate::Result;
+use libc::c_char;
+use llvm_sys::execution_engine::{self, LLVMExecutionEngineRef, LLVMMCJITCompilerOptions};
+use llvm_sys::{core, target, LLVMTypeKind};
+use log::{debug, trace};
+use std::ffi::CStr;
+use std::{mem, ptr};
+
+impl Generator {
+    /// Compile the module in-process with MCJIT and run its `main`.
+    ///
+    /// # Arguments
+    /// * `optimization` - Optimization level (0-3).
+    ///
+    /// Returns the value `main` returned.
+    pub unsafe fn run_main(&self, optimization: u32) -> Result<i32> {
+        let main = core::LLVMGetNamedFunction(self.module, c_str!("main"));
+        if main.is_null() {
+            return Err(Diagnostic::error(
+                ErrorCode::Jit,
+                "There is no `main` function to run",
+            ));
+        }
+        let main_type = core::LLVMGlobalGetValueType(main);
+        let return_type = core::LLVMGetReturnType(main_type);
+        if core::LLVMCountParamTypes(main_type) != 0
+            || core::LLVMGetTypeKind(return_type) != LLVMTypeKind::LLVMIntegerTypeKind
+            || core::LLVMGetIntTypeWidth(return_type) != 32
+        {
+            return Err(Diagnostic::error(
+                ErrorCode::Jit,
+                "`main` must take no arguments and return `i32`",
+            ));
+        }
+
+        let status = self.with_jit("main", optimization, |address| {
+            let main: extern "C" fn() -> i32 = mem::transmute(address as usize);
+            main()
+        })?;
+        Ok(status)
+    }
+
+    /// Run the function `name`, which takes no arguments, with MCJIT, and call `f` with its result.
+    ///
+    /// The result is widened to `i64`, or `None` if the function returns nothing. A pointer is
+    /// passed as its address, which `f` may still follow into constants of the compiled module.
+    pub unsafe fn run_function<T>(
+        &self,
+        name: &str,
+        f: impl FnOnce(Option<i64>) -> T,
+    ) -> Result<T> {
+        let function = core::LLVMGetNamedFunction(self.module, c_str!(name));
+        if function.is_null() {
+            return Err(Diagnostic::error(
+                ErrorCode::Jit,
+                format!("Function `{}` doesn't exist", name),
+            ));
+        }
+        let return_type = core::LLVMGetReturnType(core::LLVMGlobalGetValueType(function));
+        let width = match core::LLVMGetTypeKind(return_type) {
+            LLVMTypeKind::LLVMVoidTypeKind => 0,
+            LLVMTypeKind::LLVMIntegerTypeKind => core::LLVMGetIntTypeWidth(return_type),
+            LLVMTypeKind::LLVMPointerTypeKind => usize::BITS,
+            _ => u32::MAX,
+        };
+        self.with_jit(name, 0, |address| match width {
+            0 => {
+                let function: extern "C" fn() = mem::transmute(address as usize);
+                function();
+                Ok(f(None))
+            }
+            // Only the lowest bit of an `i1` is defined.
+            1..=8 => {
+                let function: extern "C" fn() -> u8 = mem::transmute(address as usize);
+                Ok(f(Some((function() & 1) as i64)))
+            }
+            32 => {
+                let function: extern "C" fn() -> i32 = mem::transmute(address as usize);
+                Ok(f(Some(function() as i64)))
+            }
+            64 => {
+                let function: extern "C" fn() -> i64 = mem::transmute(address as usize);
+                Ok(f(Some(function())))
+            }
+            _ => Err(Diagnostic::error(
+                ErrorCode::Jit,
+                format!("Cannot run `{}`: unsupported return type", name),
+            )),
+        })?
+    }
+
+    /// Compile a clone of the module in-process with MCJIT and call `f` with the address of
+    /// `function`.
+    ///
+    /// The generator keeps its module, so it can still be emitted or extended afterwards. Calls to
+    /// the runtime go to the copy of `skipp-rt` linked into skippc, through [`runtime`].
+    unsafe fn with_jit<T>(
+        &self,
+        function: &str,
+        optimization: u32,
+        f: impl FnOnce(u64) -> T,
+    ) -> Result<T> {
+        execution_engine::LLVMLinkInMCJIT();
+        if target::LLVM_InitializeNativeTarget() != 0
+            || target::LLVM_InitializeNativeAsmPrinter() != 0
+        {
+            return Err(Diagnostic::error(
+                ErrorCode::Jit,
+                "Unable to initialize the native target",
+            ));
+        }
+        trace!("Successfully initialized the native target");
+
+        let mut options = mem::zeroed::<LLVMMCJITCompilerOptions>();
+        execution_engine::LLVMInitializeMCJITCompilerOptions(
+            &mut options,
+            mem::size_of::<LLVMMCJITCompilerOptions>(),
+        );
+        options.OptLevel = optimization.min(3);
+
+        // The engine takes ownership of the module it runs.
+        let module = core::LLVMCloneModule(self.module);
+        let mut engine = ptr::null_mut() as LLVMExecutionEngineRef;
+        let mut error = ptr::null_mut::<c_char>();
+        if execution_engine::LLVMCreateMCJITCompilerForModule(
+            &mut engine,
+            module,
+            &mut options,
+            mem::size_of::<LLVMMCJITCompilerOptions>(),
+            &mut error,
+        ) != 0
+        {
+            let message = CStr::from_ptr(error).to_string_lossy().into_owned();
+            core::LLVMDisposeMessage(error);
+            return Err(Diagnostic::error(ErrorCode::Llvm, message));
+        }
+
+        for (name, address) in runtime::symbols() {
+            let function = core::LLVMGetNamedFunction(module, c_str!(name));
+            if !function.is_null() {
+                execution_engine::LLVMAddGlobalMapping(engine, function, address);
+            }
+        }
+
+        let address = execution_engine::LLVMGetFunctionAddress(engine, c_str!(function));
+        if address == 0 {
+            execution_engine::LLVMDisposeExecutionEngine(engine);
+            return Err(Diagnostic::error(
+                ErrorCode::Jit,
+                format!("Unable to compile `{}`", function),
+            ));
+        }
+        debug!("Running {} at {:#x}", function, address);
+        let result = f(address);
+
+        execution_engine::LLVMDisposeExecutionEngine(engine);
+        Ok(result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::checker::Checker;
+    use crate::generator::Generator;
+    use crate::lexer::Lexer;
+    use crate::parser::Parser;
+    use crate::source::SourceMap;
+    use std::rc::Rc;
+
+    fn run(text: &str) -> crate::Result<i32> {
+        let sources = Rc::new(SourceMap::new());
+        let tokens = Lexer::from_text(&sources, text, "<test>")
+            .map(|t| t.unwrap())
+            .collect::<Vec<_>>();
+        let mut parser = Parser::new(tokens.into_iter().peekable());
+        let (mut program, errors) = parser.parse_program();
+        assert!(errors.is_empty(), "{:#?}", errors);
+        let mut checker = Checker::new(parser.symtab);
+        let errors = checker.check_program(&mut program);
+        assert!(errors.is_empty(), "{:#?}", errors);
+        unsafe {
+            let generator = Generator::new(program, "test", sources, checker.into_symbols());
+            generator.init();
+            generator.generate()?;
+            generator.run_main(0)
+        }
+    }
+
+    #[test]
+    fn main_runs_in_process() {
+        let status = run("def fib(n: i32) -> i32 do
+    if n < 2: return n;
+    return fib(n - 1) + fib(n - 2);
+end
+def main() -> i32 do
+    return fib(10);
+end");
+        assert_eq!(status.unwrap(), 55);
+        assert!(run("def f() -> i32 do return 0; end").is_err());
+    }
+}
@@ -1,29 +1,40 @@
 mod class;
+mod debug;
 mod expression;
 mod function;
+mod jit;
 mod program;
+mod target;
+
+pub use target::{CodeModel, RelocationModel, TargetOptions};
 
 use crate::c_str;
-use crate::parser::{AstNode, NodePosition};
+use crate::diagnostic::{Diagnostic, ErrorCode};
+use crate::parser::{AstNode, NodePosition, StructMembers};
+use crate::source::{SourceMap, Span};
+use crate::symbol_table::{SymbolId, SymbolTable};
 use crate::Result;
 use libc::c_char;
 use llvm_sys::analysis::LLVMVerifierFailureAction;
-use llvm_sys::prelude::{LLVMBuilderRef, LLVMContextRef, LLVMModuleRef, LLVMTypeRef, LLVMValueRef};
-use llvm_sys::target_machine::{
-    LLVMCodeGenFileType, LLVMCodeGenOptLevel, LLVMCodeModel, LLVMRelocMode, LLVMTarget,
+use llvm_sys::prelude::{
+    LLVMBool, LLVMBuilderRef, LLVMContextRef, LLVMModuleRef, LLVMTypeRef, LLVMValueRef,
 };
-use llvm_sys::{analysis, core, target, target_machine};
-use log::{debug, error, info, trace, warn};
+use llvm_sys::target_machine::LLVMCodeGenFileType;
+use llvm_sys::transforms::pass_builder;
+use llvm_sys::{analysis, bit_writer, core, error, target_machine, LLVMIntPredicate, LLVMTypeKind};
+use log::{debug, info, trace, warn};
 use std::cell::RefCell;
 use std::collections::{HashMap, HashSet};
 use std::ffi::CStr;
-use std::process::Command;
-use std::ptr;
+use std::rc::Rc;
+use std::{ptr, slice};
 
 /// Generates LLVM IR based on the AST.
 pub struct Generator {
     /// The root of the AST.
     program: Vec<(AstNode, NodePosition)>,
+    /// The sources the AST was parsed from.
+    sources: Rc<SourceMap>,
 
     /// LLVM Context.
     context: LLVMContextRef,
@@ -32,16 +43,21 @@ pub struct Generator {
     /// LLVM Builder.
     builder: LLVMBuilderRef,
 
-    /// LLVM variable map.
-    local_vars: RefCell<HashMap<String, (LLVMValueRef, LLVMTypeRef)>>,
-    /// Variables in the current scope
-    scope_var_names: RefCell<Vec<Vec<String>>>,
+    /// The scopes of the program, walked again while generating code.
+    pub(crate) symbols: RefCell<SymbolTable>,
+    /// Stack slot and type of each variable and parameter generated so far.
+    values: RefCell<HashMap<SymbolId, (LLVMValueRef, LLVMTypeRef)>>,
     /// current function
     current_fn: RefCell<Option<LLVMValueRef>>,
-    /// number of if statements in the current function
-    if_count: RefCell<i32>,
+    /// Where each generated function was defined, to point verifier failures at the source.
+    function_spans: RefCell<HashMap<String, Span>>,
+    /// DWARF debug information, if it was asked for with [`Generator::init_debug_info`].
+    debug: Option<debug::DebugInfo>,
+    /// The machine code is generated for.
+    target: TargetOptions,
     /// struct name-type mapping
-    structs: RefCell<HashMap<String, (LLVMTypeRef, HashMap<String, (String, i32)>)>>,
+    structs: RefCell<HashMap<String, (LLVMTypeRef, StructMembers)>>,
+    /// Names of the classes, whose types are also in `structs`.
     classes: RefCell<HashSet<String>>,
     /*
     {
@@ -61,23 +77,51 @@ impl Generator {
     /// # Arguments
     /// * `program` - The root of the AST.
     /// * `name` - The name of the module to be created.
-    pub unsafe fn new(program: Vec<(AstNode, NodePosition)>, name: &str) -> Self {
+    /// * `sources` - The sources the AST was parsed from.
+    /// * `symbols` - The scopes built by the parser.
+    pub unsafe fn new(
+        program: Vec<(AstNode, NodePosition)>,
+        name: &str,
+        sources: Rc<SourceMap>,
+        symbols: SymbolTable,
+    ) -> Self {
         let context = core::LLVMContextCreate();
+        // Pointers are spelled `ptr`, as LLVM does by default from version 15 on. Nothing
+        // generated depends on what a pointer points to, so the IR reads the same whichever LLVM
+        // is linked.
+        core::LLVMContextSetOpaquePointers(context, true as LLVMBool);
         Generator {
             program,
+            sources,
             context,
             module: core::LLVMModuleCreateWithNameInContext(c_str!(name), context),
             builder: core::LLVMCreateBuilderInContext(context),
-            local_vars: RefCell::new(HashMap::new()),
-            scope_var_names: RefCell::new(Vec::new()),
+            symbols: RefCell::new(symbols),
+            values: RefCell::new(HashMap::new()),
             current_fn: RefCell::new(None),
-            if_count: RefCell::new(0),
+            function_spans: RefCell::new(HashMap::new()),
+            debug: None,
+            target: TargetOptions::default(),
             structs: RefCell::new(HashMap::new()),
             classes: RefCell::new(HashSet::new()),
         }
     }
 
     pub unsafe fn init(&self) {
+        if let Some(file) = self
+            .program
+            .first()
+            .and_then(|(_, pos)| self.sources.try_get(pos.span.file_id))
+        {
+            core::LLVMSetSourceFileName(
+                self.module,
+                file.name.as_ptr() as *const c_char,
+                file.name.len(),
+            );
+        }
+        if let Err(e) = self.apply_target() {
+            warn!("Unable to set the target of the module: {}", e.message);
+        }
         // let struct_lltype = core::LLVMStructCreateNamed(
         //     self.context,
         //     c_str!("Person")
@@ -92,13 +136,25 @@ impl Generator {
         // field_data.insert(String::from("age"), String::from("i32"));
         // field_data.insert(String::from("alive"), String::from("bool"));
         // (*self.structs.borrow_mut()).insert(String::from("Person"), (struct_lltype.clone(), field_data));
-        let llvm_fn = core::LLVMAddFunction(
+        // `void *GC_malloc(size_t)`, from the runtime's collector.
+        core::LLVMAddFunction(
             self.module,
             c_str!("GC_malloc"),
             core::LLVMFunctionType(
-                self.void_type(),
-                [self.i64_type()].as_mut_ptr(),
-                1, // name.len() == type_.len()
+                self.pointer_type(core::LLVMInt8TypeInContext(self.context)),
+                [self.size_type()].as_mut_ptr(),
+                1,
+                0,
+            ),
+        );
+        // `void panic(const char *)`, which stops the program.
+        core::LLVMAddFunction(
+            self.module,
+            c_str!("panic"),
+            core::LLVMFunctionType(
+                core::LLVMVoidTypeInContext(self.context),
+                [self.pointer_type(core::LLVMInt8TypeInContext(self.context))].as_mut_ptr(),
+                1,
                 0,
             ),
         );
@@ -143,175 +199,377 @@ impl Generator {
     /// Generate the LLVM IR from the module.
     pub unsafe fn generate(&self) -> Result<()> {
         self.gen_program(&self.program)?;
+        self.finalize_debug_info();
         debug!("Successfully generated program");
         debug!("{:?}", self.structs.borrow());
         Ok(())
     }
 
-    pub unsafe fn optimize(&self) {
-        use llvm_sys::core::*;
-        use llvm_sys::transforms::instcombine::LLVMAddInstructionCombiningPass;
-        use llvm_sys::transforms::pass_manager_builder::*;
-        use llvm_sys::transforms::scalar::*;
-        use llvm_sys::transforms::vectorize::LLVMAddLoopVectorizePass;
-
-        // Per clang and rustc, we want to use both kinds.
-        let fpm = LLVMCreateFunctionPassManagerForModule(self.module);
-        let mpm = LLVMCreatePassManager();
-
-        // Populate the pass managers with passes
-        let pmb = LLVMPassManagerBuilderCreate();
-        LLVMPassManagerBuilderSetOptLevel(pmb, 2);
-        LLVMAddInstructionCombiningPass(fpm);
-        LLVMAddLoopVectorizePass(fpm);
-        LLVMAddLoopUnrollPass(fpm);
-        LLVMAddLoopRotatePass(fpm);
-        // Magic threshold from Clang for -O2
-        LLVMPassManagerBuilderUseInlinerWithThreshold(pmb, 225);
-        LLVMPassManagerBuilderPopulateModulePassManager(pmb, mpm);
-        LLVMPassManagerBuilderPopulateFunctionPassManager(pmb, fpm);
-        LLVMPassManagerBuilderDispose(pmb);
-
-        // Iterate over functions, running the FPM over each
-        LLVMInitializeFunctionPassManager(fpm);
-        let mut func = LLVMGetFirstFunction(self.module);
-        while func != ptr::null_mut() {
-            LLVMRunFunctionPassManager(fpm, func);
-            func = LLVMGetNextFunction(func);
-        }
-        LLVMFinalizeFunctionPassManager(fpm);
-
-        // Run the MPM over the module
-        LLVMRunPassManager(mpm, self.module);
-
-        // Clean up managers
-        LLVMDisposePassManager(fpm);
-        LLVMDisposePassManager(mpm);
-    }
-
-    /// Verify LLVM IR.
-    pub unsafe fn verify(&self) -> Result<()> {
-        let mut error = ptr::null_mut::<c_char>();
-        analysis::LLVMVerifyModule(
+    /// Run LLVM's optimization passes over the module with the new pass manager.
+    ///
+    /// # Arguments
+    /// * `optimization` - Optimization level (0-3), which selects the `default<On>` pipeline.
+    /// * `passes` - A pipeline in the syntax of `opt -passes=...`, run instead of the default one.
+    pub unsafe fn optimize(&self, optimization: u32, passes: Option<&str>) -> Result<()> {
+        let pipeline = match passes {
+            Some(passes) => passes.to_string(),
+            None => format!("default<O{}>", optimization.min(3)),
+        };
+        info!("Running pass pipeline `{}`", pipeline);
+
+        let target_machine = self.target_machine(optimization)?;
+        // Like clang, only vectorize and unroll from -O2 up.
+        let options = pass_builder::LLVMCreatePassBuilderOptions();
+        let aggressive = (optimization > 1) as LLVMBool;
+        pass_builder::LLVMPassBuilderOptionsSetLoopVectorization(options, aggressive);
+        pass_builder::LLVMPassBuilderOptionsSetSLPVectorization(options, aggressive);
+        pass_builder::LLVMPassBuilderOptionsSetLoopInterleaving(options, aggressive);
+        pass_builder::LLVMPassBuilderOptionsSetLoopUnrolling(options, aggressive);
+
+        let failure = pass_builder::LLVMRunPasses(
             self.module,
-            LLVMVerifierFailureAction::LLVMReturnStatusAction,
-            &mut error,
+            c_str!(pipeline),
+            target_machine,
+            options,
         );
-        if !error.is_null() {
-            let error = CStr::from_ptr(error).to_str().unwrap().to_string();
-            if !error.is_empty() {
-                return Err(error);
+        pass_builder::LLVMDisposePassBuilderOptions(options);
+        target_machine::LLVMDisposeTargetMachine(target_machine);
+        if !failure.is_null() {
+            let message = error::LLVMGetErrorMessage(failure);
+            let diagnostic = Diagnostic::error(
+                ErrorCode::Llvm,
+                format!(
+                    "Unable to run pass pipeline `{}`: {}",
+                    pipeline,
+                    CStr::from_ptr(message).to_string_lossy()
+                ),
+            );
+            error::LLVMDisposeErrorMessage(message);
+            return Err(diagnostic);
+        }
+        debug!("Successfully optimized module");
+        Ok(())
+    }
+
+    /// Verify every function with a body, then the module as a whole.
+    ///
+    /// Malformed IR is a bug in the generator, so it is reported as an internal compiler error
+    /// that points at the skipp function it was generated for and shows the offending IR.
+    pub unsafe fn verify(&self) -> Result<()> {
+        let mut function = core::LLVMGetFirstFunction(self.module);
+        while !function.is_null() {
+            if core::LLVMCountBasicBlocks(function) > 0
+                && analysis::LLVMVerifyFunction(
+                    function,
+                    LLVMVerifierFailureAction::LLVMReturnStatusAction,
+                ) != 0
+            {
+                return Err(self.verifier_error(function));
             }
+            function = core::LLVMGetNextFunction(function);
+        }
+
+        if let Some(message) = self.verifier_message() {
+            return Err(Diagnostic::error(
+                ErrorCode::Llvm,
+                "Internal compiler error: generated an invalid LLVM module",
+            )
+            .with_note(format!("LLVM reported: {}", message))
+            .with_note("this is a bug in skippc"));
         }
         debug!("Successfully verified module");
         Ok(())
     }
 
-    /// Dump LLVM IR to stdout.
+    /// The message of the module verifier, if the module is broken.
+    unsafe fn verifier_message(&self) -> Option<String> {
+        let mut error = ptr::null_mut::<c_char>();
+        let broken = analysis::LLVMVerifyModule(
+            self.module,
+            LLVMVerifierFailureAction::LLVMReturnStatusAction,
+            &mut error,
+        ) != 0;
+        let message = if error.is_null() {
+            String::new()
+        } else {
+            let message = CStr::from_ptr(error).to_string_lossy().trim().to_string();
+            core::LLVMDisposeMessage(error);
+            message
+        };
+        match (broken, message.is_empty()) {
+            (false, _) => None,
+            (true, true) => Some(String::from("the module is broken")),
+            (true, false) => Some(message),
+        }
+    }
+
+    /// Report `function`, which failed to verify.
+    unsafe fn verifier_error(&self, function: LLVMValueRef) -> Diagnostic {
+        let mut length = 0;
+        let name = core::LLVMGetValueName2(function, &mut length);
+        let name = String::from_utf8_lossy(slice::from_raw_parts(name as *const u8, length));
+        let message = self
+            .verifier_message()
+            .unwrap_or_else(|| String::from("the function is broken"));
+
+        let ir = core::LLVMPrintValueToString(function);
+        let function_ir = CStr::from_ptr(ir).to_string_lossy().into_owned();
+        core::LLVMDisposeMessage(ir);
+
+        let diagnostic = Diagnostic::error(
+            ErrorCode::Llvm,
+            format!(
+                "Internal compiler error: generated invalid LLVM IR for `{}`",
+                name
+            ),
+        );
+        let diagnostic = match self.function_spans.borrow().get(name.as_ref()) {
+            Some(&span) => diagnostic.with_span(span),
+            None => diagnostic,
+        };
+        diagnostic
+            .with_note(format!("LLVM reported: {}", message.replace('\n', "\n    ")))
+            .with_note(format!("in this IR:\n{}", ir_snippet(&function_ir, &message)))
+            .with_note("this is a bug in skippc")
+    }
+
+    /// Write the textual LLVM IR to a file.
     pub unsafe fn generate_ir(&self, output: &str) -> Result<()> {
         let mut error = ptr::null_mut::<c_char>();
         core::LLVMPrintModuleToFile(self.module, c_str!(output), &mut error);
         if !error.is_null() {
             let error = CStr::from_ptr(error).to_str().unwrap().to_string();
             if !error.is_empty() {
-                return Err(error);
+                return Err(Diagnostic::error(ErrorCode::Llvm, error));
             }
         }
         Ok(())
     }
 
+    /// The textual LLVM IR of the module.
+    pub unsafe fn ir(&self) -> String {
+        let ir = core::LLVMPrintModuleToString(self.module);
+        let text = CStr::from_ptr(ir).to_string_lossy().into_owned();
+        core::LLVMDisposeMessage(ir);
+        text
+    }
+
+    /// Write the module as LLVM bitcode.
+    pub unsafe fn generate_bitcode(&self, output: &str) -> Result<()> {
+        if bit_writer::LLVMWriteBitcodeToFile(self.module, c_str!(output)) != 0 {
+            return Err(Diagnostic::error(
+                ErrorCode::Llvm,
+                format!("Unable to write bitcode to `{}`", output),
+            ));
+        }
+        Ok(())
+    }
+
     /// Generate an object file from the LLVM IR.
     ///
     /// # Arguments
     /// * `optimization` - Optimization level (0-3).
     /// * `output` - Output file path.
     pub unsafe fn generate_object_file(&self, optimization: u32, output: &str) -> Result<()> {
-        let target_triple = target_machine::LLVMGetDefaultTargetTriple();
-
-        info!(
-            "Target: {}",
-            CStr::from_ptr(target_triple).to_str().unwrap()
-        );
+        self.emit_to_file(optimization, output, LLVMCodeGenFileType::LLVMObjectFile)
+    }
 
-        target::LLVM_InitializeAllTargetInfos();
-        target::LLVM_InitializeAllTargets();
-        target::LLVM_InitializeAllTargetMCs();
-        target::LLVM_InitializeAllAsmParsers();
-        target::LLVM_InitializeAllAsmPrinters();
-        trace!("Successfully initialized all LLVM targets");
+    /// Generate target assembly from the LLVM IR.
+    ///
+    /// # Arguments
+    /// * `optimization` - Optimization level (0-3).
+    /// * `output` - Output file path.
+    pub unsafe fn generate_assembly(&self, optimization: u32, output: &str) -> Result<()> {
+        self.emit_to_file(optimization, output, LLVMCodeGenFileType::LLVMAssemblyFile)
+    }
 
-        let mut target = ptr::null_mut::<LLVMTarget>();
+    unsafe fn emit_to_file(
+        &self,
+        optimization: u32,
+        output: &str,
+        file_type: LLVMCodeGenFileType,
+    ) -> Result<()> {
+        let target_machine = self.target_machine(optimization)?;
         let mut error = ptr::null_mut::<c_char>();
-        target_machine::LLVMGetTargetFromTriple(target_triple, &mut target, &mut error);
-        if !error.is_null() {
-            let error = CStr::from_ptr(error).to_str().unwrap().to_string();
-            if !error.is_empty() {
-                return Err(error);
-            }
+        let failed = target_machine::LLVMTargetMachineEmitToFile(
+            target_machine,
+            self.module,
+            c_str!(output) as *mut _,
+            file_type,
+            &mut error,
+        );
+        target_machine::LLVMDisposeTargetMachine(target_machine);
+        if failed != 0 {
+            let message = if error.is_null() {
+                String::from("Unable to emit code")
+            } else {
+                CStr::from_ptr(error).to_string_lossy().into_owned()
+            };
+            return Err(Diagnostic::error(
+                ErrorCode::Llvm,
+                format!("Unable to write `{}`: {}", output, message),
+            ));
         }
+        trace!("Successfully emitted to file");
+        Ok(())
+    }
 
-        let optimization_level = match optimization {
-            0 => LLVMCodeGenOptLevel::LLVMCodeGenLevelNone,
-            1 => LLVMCodeGenOptLevel::LLVMCodeGenLevelLess,
-            2 => LLVMCodeGenOptLevel::LLVMCodeGenLevelDefault,
-            3 => LLVMCodeGenOptLevel::LLVMCodeGenLevelAggressive,
-            _ => {
-                warn!("Invalid optimization level, defaulting to 2");
-                LLVMCodeGenOptLevel::LLVMCodeGenLevelDefault
-            }
+    fn no_terminator(&self) -> bool {
+        let block = unsafe { core::LLVMGetInsertBlock(self.builder) };
+        let terminator = unsafe { core::LLVMGetBasicBlockTerminator(block) };
+        terminator.is_null()
+    }
+
+    /// Convert the integer `value` to the integer type `lltype`, like the interpreter's
+    /// `Value::coerce`.
+    ///
+    /// Integer literals are generated as `i32` whatever their context, so they are widened where
+    /// an `i64` is expected. Other values are returned as they are.
+    unsafe fn coerce(&self, value: LLVMValueRef, lltype: LLVMTypeRef) -> LLVMValueRef {
+        let from = core::LLVMTypeOf(value);
+        let is_integer = |t| {
+            core::LLVMGetTypeKind(t) == LLVMTypeKind::LLVMIntegerTypeKind
+                && core::LLVMGetIntTypeWidth(t) > 1
         };
-        info!("Optimization level: {}", optimization);
+        if from == lltype || !is_integer(from) || !is_integer(lltype) {
+            return value;
+        }
+        if core::LLVMGetIntTypeWidth(from) < core::LLVMGetIntTypeWidth(lltype) {
+            core::LLVMBuildSExt(self.builder, value, lltype, c_str!(""))
+        } else {
+            core::LLVMBuildTrunc(self.builder, value, lltype, c_str!(""))
+        }
+    }
 
-        let target_machine = target_machine::LLVMCreateTargetMachine(
-            target,
-            target_triple,
-            c_str!("generic"),
+    /// Allocate a zeroed `lltype` on the garbage-collected heap, and return a pointer to it.
+    unsafe fn build_gc_alloc(&self, lltype: LLVMTypeRef) -> LLVMValueRef {
+        let gc_malloc = core::LLVMGetNamedFunction(self.module, c_str!("GC_malloc"));
+        let size = core::LLVMBuildIntCast2(
+            self.builder,
+            core::LLVMSizeOf(lltype),
+            self.size_type(),
+            false as i32,
             c_str!(""),
-            optimization_level,
-            LLVMRelocMode::LLVMRelocDefault, // TODO is this right?
-            LLVMCodeModel::LLVMCodeModelDefault, // TODO is this right?
         );
-        trace!("Successfully created target machine");
-
-        let mut target = ptr::null_mut::<c_char>();
-        target_machine::LLVMTargetMachineEmitToFile(
-            target_machine,
-            self.module,
-            c_str!(output) as *mut _,
-            LLVMCodeGenFileType::LLVMObjectFile,
-            &mut target,
+        let memory = core::LLVMBuildCall2(
+            self.builder,
+            core::LLVMGlobalGetValueType(gc_malloc),
+            gc_malloc,
+            [size].as_mut_ptr(),
+            1,
+            c_str!(""),
         );
-        if !target.is_null() {
-            let error = CStr::from_ptr(error).to_str().unwrap();
-            error!("{}", error);
-        };
-        trace!("Successfully emitted to file");
-        Ok(())
+        core::LLVMBuildPointerCast(self.builder, memory, self.pointer_type(lltype), c_str!(""))
     }
 
-    /// Generates an executable from the object file by calling gcc.
+    /// The value a variable of type `type_` starts with, as in the interpreter.
     ///
-    /// # Arguments
-    /// * `object_file` - Path to the object file.
-    /// * `output` - Path to the executable.
-    pub fn generate_executable(&self, object_file: &str, output: &str) -> Result<()> {
-        // TODO is there a better way to do this?
-        match Command::new("g++")
-            .args(&[object_file, "std.cc", "-o", output])
-            .spawn()
-        {
-            Ok(_) => {
-                debug!("Successfully generated executable: {}", output);
-                Ok(())
+    /// That is zero, except for a `str`, which is empty rather than null.
+    unsafe fn zero_value(&self, type_: &str, lltype: LLVMTypeRef) -> LLVMValueRef {
+        match type_ {
+            "str" => core::LLVMBuildGlobalStringPtr(self.builder, c_str!(""), c_str!("str")),
+            _ => core::LLVMConstNull(lltype),
+        }
+    }
+
+    /// Divide `l` by `r`, stopping the program with the runtime's `panic` if `r` is zero.
+    ///
+    /// LLVM leaves division by zero undefined, while the interpreter reports it. Dividing the
+    /// smallest integer by -1 overflows, which LLVM leaves undefined too and x86 traps on, so that
+    /// wraps around to the smallest integer again, as it does in the interpreter.
+    unsafe fn build_div(&self, l: LLVMValueRef, r: LLVMValueRef) -> LLVMValueRef {
+        if !core::LLVMIsAConstantInt(r).is_null() {
+            match core::LLVMConstIntGetSExtValue(r) {
+                0 => {}
+                -1 => return core::LLVMBuildNeg(self.builder, l, c_str!("")),
+                _ => return core::LLVMBuildSDiv(self.builder, l, r, c_str!("")),
             }
-            Err(e) => Err(format!("Unable to link object file:\n{}", e)),
         }
+        self.build_panic_if_null(r, "Attempt to divide by zero");
+        // Divide by 1 instead of -1, and negate instead, which wraps.
+        let type_ = core::LLVMTypeOf(r);
+        let minus_one = core::LLVMConstAllOnes(type_);
+        let is_minus_one = core::LLVMBuildICmp(
+            self.builder,
+            LLVMIntPredicate::LLVMIntEQ,
+            r,
+            minus_one,
+            c_str!(""),
+        );
+        let one = core::LLVMConstInt(type_, 1, false as i32);
+        let divisor = core::LLVMBuildSelect(self.builder, is_minus_one, one, r, c_str!(""));
+        let quotient = core::LLVMBuildSDiv(self.builder, l, divisor, c_str!(""));
+        let negated = core::LLVMBuildNeg(self.builder, l, c_str!(""));
+        core::LLVMBuildSelect(self.builder, is_minus_one, negated, quotient, c_str!(""))
     }
 
-    fn no_terminator(&self) -> bool {
-        let block = unsafe { core::LLVMGetInsertBlock(self.builder) };
-        let terminator = unsafe { core::LLVMGetBasicBlockTerminator(block) };
-        return terminator.is_null();
+    /// Stop the program with the runtime's `panic` and `message` if `value` is zero or null.
+    unsafe fn build_panic_if_null(&self, value: LLVMValueRef, message: &str) {
+        let current_fn = core::LLVMGetBasicBlockParent(core::LLVMGetInsertBlock(self.builder));
+        let panic_bb = core::LLVMAppendBasicBlockInContext(self.context, current_fn, c_str!("panic"));
+        let ok_bb = core::LLVMAppendBasicBlockInContext(self.context, current_fn, c_str!("ok"));
+        let is_null = core::LLVMBuildIsNull(self.builder, value, c_str!(""));
+        core::LLVMBuildCondBr(self.builder, is_null, panic_bb, ok_bb);
+
+        core::LLVMPositionBuilderAtEnd(self.builder, panic_bb);
+        let panic = core::LLVMGetNamedFunction(self.module, c_str!("panic"));
+        let message = core::LLVMBuildGlobalStringPtr(self.builder, c_str!(message), c_str!("str"));
+        core::LLVMBuildCall2(
+            self.builder,
+            core::LLVMGlobalGetValueType(panic),
+            panic,
+            [message].as_mut_ptr(),
+            1,
+            c_str!(""),
+        );
+        core::LLVMBuildUnreachable(self.builder);
+
+        core::LLVMPositionBuilderAtEnd(self.builder, ok_bb);
+    }
+
+    /// Call `function` with `args`, each converted to the type of its parameter.
+    unsafe fn build_call(&self, function: LLVMValueRef, args: &mut [LLVMValueRef]) -> LLVMValueRef {
+        let function_type = core::LLVMGlobalGetValueType(function);
+        let mut param_types =
+            vec![ptr::null_mut(); core::LLVMCountParamTypes(function_type) as usize];
+        core::LLVMGetParamTypes(function_type, param_types.as_mut_ptr());
+        for (arg, param_type) in args.iter_mut().zip(param_types) {
+            *arg = self.coerce(*arg, param_type);
+        }
+        core::LLVMBuildCall2(
+            self.builder,
+            function_type,
+            function,
+            args.as_mut_ptr(),
+            args.len() as u32,
+            c_str!(""),
+        )
+    }
+
+    /// Allocate a local variable at the start of the current function's entry block.
+    ///
+    /// Allocas emitted where a declaration appears would grow the stack on every iteration of an
+    /// enclosing loop.
+    unsafe fn build_entry_alloca(&self, lltype: LLVMTypeRef) -> LLVMValueRef {
+        let current_fn = match *self.current_fn.borrow() {
+            Some(f) => f,
+            None => return core::LLVMBuildAlloca(self.builder, lltype, c_str!("")),
+        };
+        let entry = core::LLVMGetEntryBasicBlock(current_fn);
+        let builder = core::LLVMCreateBuilderInContext(self.context);
+        match core::LLVMGetFirstInstruction(entry) {
+            first if first.is_null() => core::LLVMPositionBuilderAtEnd(builder, entry),
+            first => core::LLVMPositionBuilderBefore(builder, first),
+        }
+        let var = core::LLVMBuildAlloca(builder, lltype, c_str!(""));
+        core::LLVMDisposeBuilder(builder);
+        var
+    }
+
+    /// The stack slot and type of the variable or parameter `name` used at `span`.
+    fn variable(&self, name: &str, span: Span) -> Option<(LLVMValueRef, LLVMTypeRef)> {
+        let id = self.symbols.borrow().lookup(name, span.start)?;
+        self.values.borrow().get(&id).copied()
     }
 
     /// Get LLVM i32 type in context.
@@ -338,48 +596,50 @@ impl Generator {
         unsafe { core::LLVMVoidTypeInContext(self.context) }
     }
 
-    /// Get LLVM string type in context
     #[inline]
-    fn str_type(&self, length: u32) -> LLVMTypeRef {
-        unsafe { core::LLVMArrayType(core::LLVMInt8TypeInContext(self.context), length) }
+    fn pstr_type(&self) -> LLVMTypeRef {
+        unsafe { core::LLVMPointerType(core::LLVMInt8TypeInContext(self.context), 0) }
     }
 
     #[inline]
-    fn pstr_type(&self) -> LLVMTypeRef {
-        unsafe { core::LLVMPointerType(core::LLVMInt8TypeInContext(self.context), 8) }
+    fn pointer_type(&self, pointee: LLVMTypeRef) -> LLVMTypeRef {
+        unsafe { core::LLVMPointerType(pointee, 0) }
     }
 
+    /// The integer type of `size_t` on the target.
     #[inline]
-    fn parr_type(&self) -> LLVMTypeRef {
-        unsafe { core::LLVMPointerType(core::LLVMInt32TypeInContext(self.context), 8) }
+    fn size_type(&self) -> LLVMTypeRef {
+        unsafe {
+            llvm_sys::target::LLVMIntPtrTypeInContext(
+                self.context,
+                llvm_sys::target::LLVMGetModuleDataLayout(self.module),
+            )
+        }
     }
 
     #[inline]
-    fn array_type(&self, length: u32, type_: LLVMTypeRef) -> LLVMTypeRef {
-        unsafe { core::LLVMArrayType(type_, length) }
+    fn parr_type(&self) -> LLVMTypeRef {
+        unsafe { core::LLVMPointerType(core::LLVMInt32TypeInContext(self.context), 0) }
     }
 
     #[inline]
-    fn struct_type(&self, name: String) -> LLVMTypeRef {
-        match self.structs.borrow().get(&name) {
-            Some((type_, _)) => type_.clone(),
-            None => panic!("no such struct"),
-        }
+    fn array_type(&self, length: u32, type_: LLVMTypeRef) -> LLVMTypeRef {
+        unsafe { core::LLVMArrayType(type_, length) }
     }
 
     fn str_to_type(&self, ty: String) -> LLVMTypeRef {
         match ty.as_str() {
             "i32" => self.i32_type(),
-            "i64" => self.i32_type(),
+            "i64" => self.i64_type(),
             "bool" => self.bool_type(),
             "void" => self.void_type(),
             // "string"=>
             "str" => self.pstr_type(),
             "intarr" => self.parr_type(),
             x => {
-                print!("{:?}", x);
+                // Structs live on the garbage-collected heap, and are passed around by pointer.
                 match (self.structs.borrow()).get(x) {
-                    Some((ty, type_map)) => ty.clone(),
+                    Some((ty, _)) => self.pointer_type(*ty),
                     None => panic!("No such struct {} found!", x),
                 }
             }
@@ -391,6 +651,9 @@ impl Drop for Generator {
     fn drop(&mut self) {
         debug!("Cleaning up generator");
         unsafe {
+            if let Some(debug) = &self.debug {
+                debug.dispose();
+            }
             core::LLVMDisposeBuilder(self.builder);
             core::LLVMDisposeModule(self.module);
             core::LLVMContextDispose(self.context);
@@ -398,6 +661,45 @@ impl Drop for Generator {
     }
 }
 
+/// The lines of `function_ir` around the first value or block the verifier's `message` names,
+/// or its beginning if none is found.
+fn ir_snippet(function_ir: &str, message: &str) -> String {
+    const CONTEXT: usize = 3;
+    const MAX_LINES: usize = 2 * CONTEXT + 1;
+
+    let lines = function_ir.lines().collect::<Vec<_>>();
+    // The lines after the first one quote the offending values, e.g. `label %then` for a block
+    // without a terminator.
+    let culprit = message.lines().skip(1).find_map(|quoted| {
+        let quoted = quoted.trim();
+        let wanted = match quoted.strip_prefix("label %") {
+            Some(block) => format!("{}:", block),
+            None => quoted.to_string(),
+        };
+        if wanted.is_empty() {
+            return None;
+        }
+        lines
+            .iter()
+            .position(|line| line.trim_start().starts_with(&wanted) || line.trim() == quoted)
+    });
+    let (start, end) = match culprit {
+        Some(i) => (i.saturating_sub(CONTEXT), (i + CONTEXT + 1).min(lines.len())),
+        None => (0, MAX_LINES.min(lines.len())),
+    };
+    let mut snippet = lines[start..end]
+        .iter()
+        .map(|line| format!("    {}", line))
+        .collect::<Vec<_>>();
+    if start > 0 {
+        snippet.insert(0, String::from("    ..."));
+    }
+    if end < lines.len() {
+        snippet.push(String::from("    ..."));
+    }
+    snippet.join("\n")
+}
+
 /// Convert a `&str` into `*const libc::c_char`
 #[macro_export]
 macro_rules! c_str {
@@ -405,3 +707,294 @@ macro_rules! c_str {
         format!("{}\0", $s).as_ptr() as *const libc::c_char
     };
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::checker::Checker;
+    use crate::diagnostic::ErrorCode;
+    use crate::generator::{Generator, TargetOptions};
+    use crate::lexer::Lexer;
+    use crate::parser::Parser;
+    use crate::source::SourceMap;
+    use llvm_sys::core;
+    use std::rc::Rc;
+
+    unsafe fn generate(text: &str) -> Generator {
+        generate_with(text, false)
+    }
+
+    unsafe fn generate_with(text: &str, debug_info: bool) -> Generator {
+        let sources = Rc::new(SourceMap::new());
+        let tokens = Lexer::from_text(&sources, text, "<test>")
+            .map(|t| t.unwrap())
+            .collect::<Vec<_>>();
+        let mut parser = Parser::new(tokens.into_iter().peekable());
+        let (mut program, errors) = parser.parse_program();
+        assert!(errors.is_empty(), "{:#?}", errors);
+        let mut checker = Checker::new(parser.symtab);
+        let errors = checker.check_program(&mut program);
+        assert!(errors.is_empty(), "{:#?}", errors);
+        let mut generator = Generator::new(program, "test", sources, checker.into_symbols());
+        if debug_info {
+            generator.init_debug_info(false);
+        }
+        generator.init();
+        generator.generate().unwrap();
+        generator
+    }
+
+    /// Whether a line of `ir` has all of `parts`, in order.
+    ///
+    /// Leaving pointer types out of `parts` keeps an assertion true whether LLVM spells them
+    /// `ptr` or `T*`.
+    fn has_line(ir: &str, parts: &[&str]) -> bool {
+        ir.lines().any(|line| {
+            let mut rest = line;
+            parts.iter().all(|part| match rest.find(part) {
+                Some(i) => {
+                    rest = &rest[i + part.len()..];
+                    true
+                }
+                None => false,
+            })
+        })
+    }
+
+    const COUNT: &str = "extern println(a: i32) -> i32;
+def count(curr: i32, endval: i32) -> i32 do
+    if curr <= endval do
+        println(curr);
+        return count(curr + 1, endval);
+    end else do
+        return endval;
+    end;
+end";
+
+    #[test]
+    fn tail_recursion_becomes_a_loop() {
+        unsafe {
+            let generator = generate(COUNT);
+            generator.optimize(0, None).unwrap();
+            assert!(generator.ir().contains("call i32 @count"));
+            generator.optimize(3, None).unwrap();
+            assert!(!generator.ir().contains("call i32 @count"));
+        }
+    }
+
+    #[test]
+    fn custom_pipelines_are_run() {
+        unsafe {
+            let generator = generate(COUNT);
+            generator.optimize(0, Some("function(mem2reg)")).unwrap();
+            assert!(!generator.ir().contains("alloca"));
+            assert!(generator.optimize(0, Some("no-such-pass")).is_err());
+        }
+    }
+
+    #[test]
+    fn branches_that_return_leave_no_merge_block() {
+        unsafe {
+            let generator = generate(
+                "extern println(a: i32) -> i32;
+def sign(n: i32) -> i32 do
+    if n < 0: return 0 - 1
+    else if n == 0: return 0
+    else: return 1;
+    println(n);
+end",
+            );
+            generator.verify().unwrap();
+            let ir = generator.ir();
+            assert!(!ir.contains("phi"), "{}", ir);
+            assert!(!ir.contains("ifcont"), "{}", ir);
+            assert!(!ir.contains("call i32 @println"), "{}", ir);
+        }
+    }
+
+    #[test]
+    fn i64_values_are_64_bits_wide() {
+        unsafe {
+            let generator = generate(
+                "def widen(n: i64) -> i64 do
+    let total: i64 = 1;
+    total = total + n * 2;
+    return total;
+end",
+            );
+            generator.verify().unwrap();
+            let ir = generator.ir();
+            assert!(ir.contains("define i64 @widen(i64 %n)"), "{}", ir);
+            assert!(ir.contains("store i64 1"), "{}", ir);
+            assert!(ir.contains("mul i64"), "{}", ir);
+        }
+    }
+
+    #[test]
+    fn unary_plus_leaves_its_operand_alone() {
+        unsafe {
+            let plus = generate("def same(n: i64) -> i64 do return +n; end");
+            plus.verify().unwrap();
+            let plain = generate("def same(n: i64) -> i64 do return n; end");
+            assert_eq!(plus.ir(), plain.ir());
+        }
+    }
+
+    #[test]
+    fn string_literals_are_passed_as_pointers() {
+        unsafe {
+            let generator = generate(
+                "extern puts(s: str) -> i32;
+def greet() -> i32 do
+    return puts(\"hi\");
+end",
+            );
+            generator.verify().unwrap();
+            let ir = generator.ir();
+            assert!(ir.contains("private unnamed_addr constant [3 x i8] c\"hi\\00\""), "{}", ir);
+            assert!(!ir.contains("addrspace"), "{}", ir);
+        }
+    }
+
+    #[test]
+    fn structs_are_allocated_on_the_gc_heap() {
+        unsafe {
+            let generator = generate(
+                "struct Pair { a: i64 b: i64 }
+def pair(n: i64) -> Pair do
+    return Pair(n, 2);
+end",
+            );
+            generator.verify().unwrap();
+            let ir = generator.ir();
+            assert!(has_line(&ir, &["declare ", " @GC_malloc(i64)"]), "{}", ir);
+            assert!(ir.contains("@GC_malloc(i64 ptrtoint"), "{}", ir);
+            assert!(has_line(&ir, &["define ", " @pair(i64 %n)"]), "{}", ir);
+        }
+    }
+
+    #[test]
+    fn struct_layouts_follow_their_repr() {
+        unsafe {
+            let generator = generate(
+                "struct Plain { a: bool b: i64 c: i32 }
+struct Packed repr(C, packed) { a: bool b: i64 c: i32 }
+struct Aligned repr(C, align(16)) { a: bool c: i32 }
+extern show(p: Packed, verbose: bool) -> bool;
+def bump(p: Packed) -> bool do
+    p.c += 1;
+    return show(p, true);
+end",
+            );
+            generator.verify().unwrap();
+            let ir = generator.ir();
+            assert!(ir.contains("%\"$struct$Plain\" = type { i1, i64, i32 }"), "{}", ir);
+            assert!(ir.contains("%\"$struct$Packed\" = type <{ i1, i64, i32 }>"), "{}", ir);
+            // 5 bytes of fields and padding, and 8 more to make it 16.
+            assert!(ir.contains("%\"$struct$Aligned\" = type { i1, i32, [8 x i8] }"), "{}", ir);
+            assert!(has_line(&ir, &["load i32, ", ", align 1"]), "{}", ir);
+            assert!(has_line(&ir, &["declare zeroext i1 @show(", ", i1 zeroext)"]), "{}", ir);
+        }
+    }
+
+    #[test]
+    fn methods_are_mangled_per_class() {
+        unsafe {
+            let generator = generate(
+                "class A {
+    x: i32
+    def init(x: i32) -> void do self.x = x; end
+    def test() -> i32 do return self.x; end
+}
+class B {
+    def test() -> i32 do return 2; end
+}
+def sum() -> i32 do
+    return A(1).test() + B().test();
+end",
+            );
+            generator.verify().unwrap();
+            let ir = generator.ir();
+            assert!(ir.contains("%\"$class$A\" = type { i32 }"), "{}", ir);
+            assert!(has_line(&ir, &["define void @A.init(", " %self, i32 %x)"]), "{}", ir);
+            assert!(has_line(&ir, &["define i32 @A.test(", " %self)"]), "{}", ir);
+            assert!(has_line(&ir, &["define i32 @B.test(", " %self)"]), "{}", ir);
+            assert!(ir.contains("call void @A.init("), "{}", ir);
+        }
+    }
+
+    #[test]
+    fn debug_info_describes_functions_and_variables() {
+        unsafe {
+            let generator = generate_with(
+                "def twice(n: i32) -> i32 do
+    let m: i32 = n * 2;
+    return m;
+end",
+                true,
+            );
+            generator.verify().unwrap();
+            let ir = generator.ir();
+            assert!(ir.contains("!DISubprogram(name: \"twice\""), "{}", ir);
+            assert!(ir.contains("!DILocalVariable(name: \"n\", arg: 1"), "{}", ir);
+            assert!(ir.contains("!DILocalVariable(name: \"m\""), "{}", ir);
+            assert!(ir.contains("!DILocation(line: 3, column: 5"), "{}", ir);
+        }
+    }
+
+    #[test]
+    fn objects_are_generated_for_other_targets() {
+        let dir = std::env::temp_dir().join(format!("skipp-targets-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        // `e_machine` of the ELF header.
+        for (triple, machine) in [
+            ("aarch64-unknown-linux-gnu", 183),
+            ("riscv64-unknown-linux-gnu", 243),
+        ] {
+            unsafe {
+                let mut generator = generate(COUNT);
+                generator
+                    .set_target(TargetOptions {
+                        triple: Some(triple.to_string()),
+                        ..TargetOptions::default()
+                    })
+                    .unwrap();
+                assert!(generator.ir().contains(&format!("target triple = \"{}\"", triple)));
+                let object = dir.join(format!("{}.o", triple));
+                generator
+                    .generate_object_file(2, object.to_str().unwrap())
+                    .unwrap();
+                let header = std::fs::read(&object).unwrap();
+                assert_eq!(&header[..4], b"\x7fELF");
+                assert_eq!(u16::from_le_bytes([header[18], header[19]]), machine);
+            }
+        }
+        std::fs::remove_dir_all(&dir).ok();
+
+        let mut generator = unsafe { generate(COUNT) };
+        let unknown = TargetOptions {
+            triple: Some(String::from("no-such-target")),
+            ..TargetOptions::default()
+        };
+        assert!(unsafe { generator.set_target(unknown) }.is_err());
+    }
+
+    #[test]
+    fn broken_functions_are_reported_at_their_definition() {
+        unsafe {
+            let generator = generate(COUNT);
+            generator.verify().unwrap();
+
+            let count = core::LLVMGetNamedFunction(generator.module, c_str!("count"));
+            core::LLVMAppendBasicBlockInContext(generator.context, count, c_str!("dangling"));
+            let error = generator.verify().unwrap_err();
+            assert_eq!(error.code, ErrorCode::Llvm);
+            assert!(error.message.contains("`count`"), "{}", error.message);
+            assert_eq!(
+                error.span,
+                generator.function_spans.borrow().get("count").copied()
+            );
+            assert!(error.span.is_some());
+            assert!(error.notes.iter().any(|note| note.contains("dangling:")));
+        }
+    }
+}
@@ -1,28 +1,34 @@
+use crate::diagnostic::{Diagnostic, ErrorCode};
 use crate::generator::Generator;
-use crate::parser::{AstNode, Function, NodePosition};
+use crate::parser::{AstNode, NodePosition};
 use crate::Result;
 use log::trace;
 
 impl Generator {
-    pub unsafe fn gen_program(&self, program: &Vec<(AstNode, NodePosition)>) -> Result<()> {
+    pub unsafe fn gen_program(&self, program: &[(AstNode, NodePosition)]) -> Result<()> {
         trace!("Generating program");
         for (node, pos) in program {
-            self.local_vars.borrow_mut().clear();
             match node {
                 AstNode::FunctionDef(f) => {
-                    self.gen_function(f)?;
+                    self.gen_function(f, pos)?;
                 }
                 AstNode::Class(c) => {
-                    self.gen_class(c);
+                    self.gen_class(c, pos)?;
                 }
                 AstNode::Expression(e) => {
-                    self.gen_expression(e)?;
+                    self.gen_expression(e, pos.span)?;
                 }
                 AstNode::Extern(e) => {
                     self.gen_extern(e)?;
                 }
-                AstNode::Struct(n, s) => {
-                    self.gen_struct(n, s);
+                AstNode::Struct(n, s, repr) => {
+                    self.gen_struct(n, s, *repr);
+                }
+                AstNode::Error => {
+                    return Err(Diagnostic::error(
+                        ErrorCode::Codegen,
+                        "Cannot generate code for an item that failed to parse",
+                    ))
                 }
             }
             // self.gen_function(&function)?;
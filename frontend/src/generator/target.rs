@@ -0,0 +1,178 @@
+use crate::c_str;
+use crate::diagnostic::{Diagnostic, ErrorCode};
+use crate::generator::Generator;
+use crate::Result;
+use libc::c_char;
+use llvm_sys::target_machine::{
+    self, LLVMCodeGenOptLevel, LLVMCodeModel, LLVMRelocMode, LLVMTarget, LLVMTargetMachineRef,
+};
+use llvm_sys::{core, target};
+use log::{info, trace, warn};
+use std::ffi::CStr;
+use std::ptr;
+
+/// How code refers to addresses, which decides whether it can be loaded anywhere.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RelocationModel {
+    /// Whatever the target does by default.
+    Default,
+    /// Absolute addresses, fixed at link time.
+    Static,
+    /// Position independent code.
+    Pic,
+}
+
+/// How far apart code and data may be, which decides the instructions used to reach them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CodeModel {
+    /// Whatever the target does by default.
+    Default,
+    Small,
+    Kernel,
+    Medium,
+    Large,
+}
+
+/// The machine code is generated for.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TargetOptions {
+    /// Target triple, or `None` for the host.
+    pub triple: Option<String>,
+    /// CPU to tune and select instructions for. Empty for the target's generic CPU, which isn't
+    /// called `generic` on every target.
+    pub cpu: String,
+    /// Comma-separated features to enable or disable, e.g. `+neon,-fp-armv8`.
+    pub features: String,
+    pub relocation_model: RelocationModel,
+    pub code_model: CodeModel,
+}
+
+impl Default for TargetOptions {
+    fn default() -> Self {
+        TargetOptions {
+            triple: None,
+            cpu: String::new(),
+            features: String::new(),
+            // Linkers such as `cc` make position independent executables unless told otherwise.
+            relocation_model: RelocationModel::Pic,
+            code_model: CodeModel::Default,
+        }
+    }
+}
+
+impl Generator {
+    /// Generate code for `options` instead of the host.
+    ///
+    /// The module gets the target's triple and data layout, so an unknown triple is reported
+    /// here rather than when emitting code.
+    pub unsafe fn set_target(&mut self, options: TargetOptions) -> Result<()> {
+        self.target = options;
+        self.apply_target()
+    }
+
+    /// Set the module's triple and data layout to those of the target.
+    pub(super) unsafe fn apply_target(&self) -> Result<()> {
+        let target_machine = self.target_machine(2)?;
+        let triple = target_machine::LLVMGetTargetMachineTriple(target_machine);
+        core::LLVMSetTarget(self.module, triple);
+        core::LLVMDisposeMessage(triple);
+        let data_layout = target_machine::LLVMCreateTargetDataLayout(target_machine);
+        target::LLVMSetModuleDataLayout(self.module, data_layout);
+        target::LLVMDisposeTargetData(data_layout);
+        target_machine::LLVMDisposeTargetMachine(target_machine);
+        Ok(())
+    }
+
+    /// Create a machine for the target. The caller disposes of it.
+    ///
+    /// # Arguments
+    /// * `optimization` - Optimization level (0-3) of code generation.
+    pub(super) unsafe fn target_machine(&self, optimization: u32) -> Result<LLVMTargetMachineRef> {
+        let target_triple = match &self.target.triple {
+            Some(triple) => {
+                let triple = target_machine::LLVMNormalizeTargetTriple(c_str!(triple));
+                let normalized = CStr::from_ptr(triple).to_string_lossy().into_owned();
+                core::LLVMDisposeMessage(triple);
+                normalized
+            }
+            None => {
+                let triple = target_machine::LLVMGetDefaultTargetTriple();
+                let default = CStr::from_ptr(triple).to_string_lossy().into_owned();
+                core::LLVMDisposeMessage(triple);
+                default
+            }
+        };
+        info!("Target: {}", target_triple);
+
+        target::LLVM_InitializeAllTargetInfos();
+        target::LLVM_InitializeAllTargets();
+        target::LLVM_InitializeAllTargetMCs();
+        target::LLVM_InitializeAllAsmParsers();
+        target::LLVM_InitializeAllAsmPrinters();
+        trace!("Successfully initialized all LLVM targets");
+
+        let mut target = ptr::null_mut::<LLVMTarget>();
+        let mut error = ptr::null_mut::<c_char>();
+        if target_machine::LLVMGetTargetFromTriple(c_str!(target_triple), &mut target, &mut error)
+            != 0
+        {
+            let message = if error.is_null() {
+                String::from("no such target")
+            } else {
+                let message = CStr::from_ptr(error).to_string_lossy().into_owned();
+                core::LLVMDisposeMessage(error);
+                message
+            };
+            return Err(Diagnostic::error(
+                ErrorCode::Llvm,
+                format!(
+                    "Unable to generate code for `{}`: {}",
+                    target_triple, message
+                ),
+            ));
+        }
+
+        let optimization_level = match optimization {
+            0 => LLVMCodeGenOptLevel::LLVMCodeGenLevelNone,
+            1 => LLVMCodeGenOptLevel::LLVMCodeGenLevelLess,
+            2 => LLVMCodeGenOptLevel::LLVMCodeGenLevelDefault,
+            3 => LLVMCodeGenOptLevel::LLVMCodeGenLevelAggressive,
+            _ => {
+                warn!("Invalid optimization level, defaulting to 2");
+                LLVMCodeGenOptLevel::LLVMCodeGenLevelDefault
+            }
+        };
+        info!("Optimization level: {}", optimization);
+
+        let relocation_model = match self.target.relocation_model {
+            RelocationModel::Default => LLVMRelocMode::LLVMRelocDefault,
+            RelocationModel::Static => LLVMRelocMode::LLVMRelocStatic,
+            RelocationModel::Pic => LLVMRelocMode::LLVMRelocPIC,
+        };
+        let code_model = match self.target.code_model {
+            CodeModel::Default => LLVMCodeModel::LLVMCodeModelDefault,
+            CodeModel::Small => LLVMCodeModel::LLVMCodeModelSmall,
+            CodeModel::Kernel => LLVMCodeModel::LLVMCodeModelKernel,
+            CodeModel::Medium => LLVMCodeModel::LLVMCodeModelMedium,
+            CodeModel::Large => LLVMCodeModel::LLVMCodeModelLarge,
+        };
+
+        let target_machine = target_machine::LLVMCreateTargetMachine(
+            target,
+            c_str!(target_triple),
+            c_str!(self.target.cpu),
+            c_str!(self.target.features),
+            optimization_level,
+            relocation_model,
+            code_model,
+        );
+        if target_machine.is_null() {
+            return Err(Diagnostic::error(
+                ErrorCode::Llvm,
+                format!("Unable to create a target machine for `{}`", target_triple),
+            ));
+        }
+        trace!("Successfully created target machine");
+        Ok(target_machine)
+    }
+}
@@ -0,0 +1,410 @@
+use crate::diagnostic::{Diagnostic, ErrorCode};
+use crate::interpreter::{Eval, Fields, Interpreter, Unwind, Value};
+use crate::lexer::tokens::TokenType;
+use crate::parser::{Class, Expr, ExprValue};
+use crate::source::Span;
+use crate::symbol_table::{ScopeKind, SymbolId};
+
+use std::cell::RefCell;
+use std::io::Write;
+use std::rc::Rc;
+
+impl<W: Write> Interpreter<W> {
+    pub(super) fn eval(&mut self, expr: &Expr) -> Eval<Value> {
+        self.eval_expression(&expr.value, expr.span)
+    }
+
+    /// Evaluate an expression spanning `span`.
+    pub(super) fn eval_expression(&mut self, expression: &ExprValue, span: Span) -> Eval<Value> {
+        match expression {
+            ExprValue::Integer(i) => Ok(Value::I32(*i)),
+            ExprValue::Boolean(b) => Ok(Value::Bool(*b)),
+            ExprValue::Str(s) => Ok(Value::Str(Rc::from(s.as_str()))),
+            ExprValue::Identifier(name) => {
+                let id = self.variable(name, span)?;
+                Ok(self.frame()[&id].clone())
+            }
+            ExprValue::Array(elements, type_) => {
+                let elements = elements
+                    .iter()
+                    .map(|e| Ok(self.eval(e)?.coerce(type_)))
+                    .collect::<Eval<Vec<_>>>()?;
+                Ok(Value::Array(Rc::new(RefCell::new(elements))))
+            }
+            ExprValue::UnOp(op, operand) => match (&**op, self.eval(operand)?) {
+                (TokenType::Minus, Value::I32(i)) => Ok(Value::I32(i.wrapping_neg())),
+                (TokenType::Minus, Value::I64(i)) => Ok(Value::I64(i.wrapping_neg())),
+                (TokenType::Plus, value @ Value::I32(_))
+                | (TokenType::Plus, value @ Value::I64(_)) => Ok(value),
+                (TokenType::Not, Value::Bool(b)) => Ok(Value::Bool(!b)),
+                (TokenType::Not, Value::I32(i)) => Ok(Value::I32(!i)),
+                (TokenType::Not, Value::I64(i)) => Ok(Value::I64(!i)),
+                (op, value) => Err(Self::error(
+                    format!("Cannot apply `{:?}` to `{}`", op, value),
+                    span,
+                )),
+            },
+            ExprValue::FieldAccess { object, field, .. } => {
+                let (fields, index, _) = self.field(object, field, span)?;
+                let value = fields.borrow()[index].clone();
+                Ok(value)
+            }
+            ExprValue::BinOp(lhs, op, rhs) => {
+                let l = self.eval(lhs)?;
+                let r = self.eval(rhs)?;
+                Ok(Self::binop(l, op, r, span)?)
+            }
+            ExprValue::FnCall(name, args) => {
+                let args = args
+                    .iter()
+                    .map(|arg| self.eval(arg))
+                    .collect::<Eval<Vec<_>>>()?;
+                // A class's name makes an object with zeroed fields and passes it to `init`.
+                if self.classes.contains(name) {
+                    let fields = self.structs[name]
+                        .iter()
+                        .map(|(_, type_)| Value::zero(type_))
+                        .collect();
+                    let object = Value::Struct(name.clone(), Rc::new(RefCell::new(fields)));
+                    let init = Class::mangle(name, "init");
+                    if self.functions.contains_key(&init) {
+                        let args = std::iter::once(object.clone()).chain(args).collect();
+                        self.call(&init, args, Some(span))?;
+                    }
+                    return Ok(object);
+                }
+                // A struct's name constructs it from its fields in order.
+                if let Some(fields) = self.structs.get(name) {
+                    let fields = args
+                        .into_iter()
+                        .zip(fields)
+                        .map(|(value, (_, type_))| value.coerce(type_))
+                        .collect();
+                    return Ok(Value::Struct(name.clone(), Rc::new(RefCell::new(fields))));
+                }
+                Ok(self.call(name, args, Some(span))?)
+            }
+            ExprValue::MethodCall {
+                object,
+                method,
+                args,
+                ..
+            } => {
+                let object = self.eval(object)?;
+                let function = match &object {
+                    Value::Struct(class, _) | Value::Null(class) => Class::mangle(class, method),
+                    other => return Err(Self::error(format!("`{}` has no methods", other), span)),
+                };
+                let args = std::iter::once(Ok(object))
+                    .chain(args.iter().map(|arg| self.eval(arg)))
+                    .collect::<Eval<Vec<_>>>()?;
+                Ok(self.call(&function, args, Some(span))?)
+            }
+            ExprValue::VarDecl { name, type_, value } => {
+                // Evaluated before the variable exists, so `let x: i32 = x` reads an outer `x`.
+                let value = match value {
+                    Some(value) => self.eval(value)?.coerce(type_),
+                    None => Value::zero(type_),
+                };
+                let id = self.symbols.declared_at(name, span).ok_or_else(|| {
+                    Self::error(
+                        format!("Variable `{}` was not declared by the parser", name),
+                        span,
+                    )
+                })?;
+                self.frame().insert(id, value);
+                Ok(Value::Void)
+            }
+            ExprValue::Assign { name, value } => {
+                let value = self.eval(value)?;
+                let id = self.variable(name, span)?;
+                let type_ = self.symbols.symbol(id).type_.clone();
+                self.frame().insert(id, value.coerce(&type_));
+                Ok(Value::Void)
+            }
+            ExprValue::AugAssign { name, op, value } => {
+                let value = self.eval(value)?;
+                let id = self.variable(name, span)?;
+                let op = Self::aug_op(op, span)?;
+                let current = self.frame()[&id].clone();
+                let result = Self::binop(current, &op, value, span)?;
+                self.frame().insert(id, result);
+                Ok(Value::Void)
+            }
+            ExprValue::FieldAssign {
+                object,
+                field,
+                value,
+                ..
+            } => {
+                let (fields, index, type_) = self.field(object, field, span)?;
+                let value = self.eval(value)?.coerce(&type_);
+                fields.borrow_mut()[index] = value;
+                Ok(Value::Void)
+            }
+            ExprValue::FieldAugAssign {
+                object,
+                field,
+                op,
+                value,
+                ..
+            } => {
+                let (fields, index, _) = self.field(object, field, span)?;
+                let value = self.eval(value)?;
+                let current = fields.borrow()[index].clone();
+                let result = Self::binop(current, &Self::aug_op(op, span)?, value, span)?;
+                fields.borrow_mut()[index] = result;
+                Ok(Value::Void)
+            }
+            ExprValue::Return(value) => {
+                let value = self.eval(value)?;
+                Err(Unwind::Return(value))
+            }
+            ExprValue::IfElse {
+                cond,
+                if_,
+                else_,
+                type_,
+            } => {
+                let branch = match self.eval(cond)? {
+                    Value::Bool(true) => if_,
+                    Value::Bool(false) => else_,
+                    other => {
+                        return Err(Self::error(
+                            format!("Expected a `bool` condition, found `{}`", other),
+                            cond.span,
+                        ))
+                    }
+                };
+                let value = self.eval(branch)?;
+                Ok(if type_ == "void" {
+                    Value::Void
+                } else {
+                    value.coerce(type_)
+                })
+            }
+            ExprValue::While(cond, body) => {
+                self.symbols.enter_scope(ScopeKind::Loop, body.span.start);
+                let result = self.eval_while(cond, body);
+                self.symbols.exit_scope();
+                result
+            }
+            ExprValue::Do(expressions) => {
+                self.symbols.enter_scope(ScopeKind::Block, span.start);
+                let result = expressions
+                    .iter()
+                    .try_fold(Value::Void, |_, expr| self.eval(expr));
+                self.symbols.exit_scope();
+                result
+            }
+            ExprValue::Use(_) => Ok(Value::Void),
+            ExprValue::Error => Err(Self::error(
+                "Cannot run an expression that failed to parse",
+                span,
+            )),
+        }
+    }
+
+    /// Run a loop whose body scope has been entered.
+    fn eval_while(&mut self, cond: &Expr, body: &Expr) -> Eval<Value> {
+        loop {
+            // The condition is outside of the body's scope.
+            let scope = self.symbols.current_scope();
+            self.symbols.exit_scope();
+            let cond_value = self.eval(cond);
+            self.symbols.restore(scope);
+            match cond_value? {
+                Value::Bool(true) => {}
+                Value::Bool(false) => return Ok(Value::Void),
+                other => {
+                    return Err(Self::error(
+                        format!("Expected a `bool` condition, found `{}`", other),
+                        cond.span,
+                    ))
+                }
+            }
+            self.eval(body)?;
+        }
+    }
+
+    fn binop(l: Value, op: &TokenType, r: Value, span: Span) -> Eval<Value> {
+        // A literal operand is an `i32` even next to an `i64`.
+        let (l, r) = match (l, r) {
+            (Value::I32(l), Value::I64(r)) => (Value::I64(l as i64), Value::I64(r)),
+            (Value::I64(l), Value::I32(r)) => (Value::I64(l), Value::I64(r as i64)),
+            operands => operands,
+        };
+        let divide_by_zero = || {
+            Unwind::Error(
+                Diagnostic::error(ErrorCode::Panic, "Attempt to divide by zero").with_span(span),
+            )
+        };
+        match (l, r) {
+            (Value::I32(l), Value::I32(r)) => match op {
+                TokenType::Plus => Ok(Value::I32(l.wrapping_add(r))),
+                TokenType::Minus => Ok(Value::I32(l.wrapping_sub(r))),
+                TokenType::Mul => Ok(Value::I32(l.wrapping_mul(r))),
+                TokenType::Div if r == 0 => Err(divide_by_zero()),
+                TokenType::Div => Ok(Value::I32(l.wrapping_div(r))),
+                op => Self::compare(l, op, r, span),
+            },
+            (Value::I64(l), Value::I64(r)) => match op {
+                TokenType::Plus => Ok(Value::I64(l.wrapping_add(r))),
+                TokenType::Minus => Ok(Value::I64(l.wrapping_sub(r))),
+                TokenType::Mul => Ok(Value::I64(l.wrapping_mul(r))),
+                TokenType::Div if r == 0 => Err(divide_by_zero()),
+                TokenType::Div => Ok(Value::I64(l.wrapping_div(r))),
+                op => Self::compare(l, op, r, span),
+            },
+            (Value::Bool(l), Value::Bool(r)) => match op {
+                TokenType::Equal => Ok(Value::Bool(l == r)),
+                TokenType::NotEq => Ok(Value::Bool(l != r)),
+                op => Err(Self::error(
+                    format!("Cannot apply `{:?}` to `bool`s", op),
+                    span,
+                )),
+            },
+            (l, r) => Err(Self::error(
+                format!("Cannot apply `{:?}` to `{}` and `{}`", op, l, r),
+                span,
+            )),
+        }
+    }
+
+    fn compare<T: Ord>(l: T, op: &TokenType, r: T, span: Span) -> Eval<Value> {
+        match op {
+            TokenType::Equal => Ok(Value::Bool(l == r)),
+            TokenType::NotEq => Ok(Value::Bool(l != r)),
+            TokenType::Less => Ok(Value::Bool(l < r)),
+            TokenType::Greater => Ok(Value::Bool(l > r)),
+            TokenType::LessEq => Ok(Value::Bool(l <= r)),
+            TokenType::GreaterEq => Ok(Value::Bool(l >= r)),
+            op => Err(Self::error(format!("Unknown operator `{:?}`", op), span)),
+        }
+    }
+
+    /// The arithmetic operator of the compound assignment `op`, e.g. `+` for `+=`.
+    fn aug_op(op: &TokenType, span: Span) -> Eval<TokenType> {
+        match op {
+            TokenType::PlusEq => Ok(TokenType::Plus),
+            TokenType::MinusEq => Ok(TokenType::Minus),
+            TokenType::MulEq => Ok(TokenType::Mul),
+            TokenType::DivEq => Ok(TokenType::Div),
+            other => Err(Self::error(format!("Unknown operator `{:?}`", other), span)),
+        }
+    }
+
+    /// The fields of the struct `object`, and the index and type of its `field` at `span`.
+    fn field(&mut self, object: &Expr, field: &str, span: Span) -> Eval<(Fields, usize, String)> {
+        let (name, fields) = match self.eval(object)? {
+            Value::Struct(name, fields) => (name, fields),
+            Value::Null(name) => {
+                return Err(Unwind::Error(
+                    Diagnostic::error(
+                        ErrorCode::Panic,
+                        format!("Access to field `{}` of a null `{}`", field, name),
+                    )
+                    .with_span(span),
+                ))
+            }
+            other => {
+                return Err(Self::error(
+                    format!("`{}` has no fields", other),
+                    object.span,
+                ))
+            }
+        };
+        let index = self.structs[&name].iter().position(|(f, _)| f == field);
+        match index.filter(|&i| i < fields.borrow().len()) {
+            Some(i) => Ok((fields, i, self.structs[&name][i].1.clone())),
+            None => Err(Self::error(
+                format!("Struct `{}` has no field `{}`", name, field),
+                span,
+            )),
+        }
+    }
+
+    /// The variable `name` used at `span`.
+    fn variable(&self, name: &str, span: Span) -> Eval<SymbolId> {
+        let id = self.symbols.lookup(name, span.start);
+        match id {
+            Some(id) if self.frames.last().unwrap().contains_key(&id) => Ok(id),
+            _ => Err(Self::error(
+                format!("Cannot find variable `{}`", name),
+                span,
+            )),
+        }
+    }
+
+    fn error(message: impl Into<String>, span: Span) -> Unwind {
+        Unwind::Error(Diagnostic::error(ErrorCode::Runtime, message).with_span(span))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::checker::Checker;
+    use crate::interpreter::Interpreter;
+    use crate::lexer::Lexer;
+    use crate::parser::Parser;
+    use crate::source::SourceMap;
+
+    /// Run `main` and return its result and everything it printed.
+    fn run(text: &str) -> (i32, String) {
+        let tokens = Lexer::from_text(&SourceMap::new(), text, "<test>")
+            .map(|t| t.unwrap())
+            .collect::<Vec<_>>();
+        let mut parser = Parser::new(tokens.into_iter().peekable());
+        let (mut program, errors) = parser.parse_program();
+        assert!(errors.is_empty(), "{:#?}", errors);
+        let mut checker = Checker::new(parser.symtab);
+        let errors = checker.check_program(&mut program);
+        assert!(errors.is_empty(), "{:#?}", errors);
+
+        let mut interpreter = Interpreter::new(checker.into_symbols(), Vec::new());
+        interpreter.load(&program).unwrap();
+        let status = interpreter.run_main().unwrap();
+        (
+            status,
+            String::from_utf8(interpreter.output().clone()).unwrap(),
+        )
+    }
+
+    #[test]
+    fn functions_recurse_and_loops_run() {
+        let (status, output) = run("extern println(n: i32) -> i32;
+def fib(n: i32) -> i32 do
+    if n < 2: return n;
+    return fib(n - 1) + fib(n - 2);
+end
+def main() -> i32 do
+    let i: i32 = 0;
+    while i < 3 do
+        let x: i32 = fib(i + 5);
+        println(x);
+        i += 1;
+    end;
+    let x: i32 = 1;
+    do let x: i32 = 2; println(x); end;
+    println(x);
+    return fib(10);
+end");
+        assert_eq!(status, 55);
+        assert_eq!(output, "5\n8\n13\n2\n1\n");
+    }
+
+    #[test]
+    fn structs_and_wrapping_arithmetic() {
+        let (status, output) = run("extern println(n: i32) -> i32;
+struct Point { x: i32 y: i64 }
+def main() -> i32 do
+    let p: Point = Point(2147483647, 3);
+    println(p.x + 1);
+    let y: i64 = p.y * 3;
+    if y == 9: println(1) else: println(0);
+    return 0 - 1;
+end");
+        assert_eq!(status, -1);
+        assert_eq!(output, "-2147483648\n1\n");
+    }
+}
@@ -0,0 +1,408 @@
+mod expression;
+
+use crate::diagnostic::{Diagnostic, ErrorCode};
+use crate::parser::{AstNode, Class, Function, NodePosition, StructMembers};
+use crate::source::Span;
+use crate::symbol_table::{ScopeId, ScopeKind, SymbolId, SymbolTable};
+use crate::Result;
+
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::convert::TryFrom;
+use std::fmt;
+use std::io::Write;
+use std::rc::Rc;
+
+/// A value computed by the [`Interpreter`].
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    I32(i32),
+    I64(i64),
+    Bool(bool),
+    Str(Rc<str>),
+    /// The elements of an `intarr`, shared by its copies like the fields of a struct.
+    Array(Elements),
+    /// A struct's name and its fields in declaration order.
+    ///
+    /// Copies share the fields, like the pointers to a heap object that compiled code passes
+    /// around, so a field assigned through one copy changes in all of them.
+    Struct(String, Fields),
+    /// A struct that was never given a value, like the null pointer compiled code stores.
+    Null(String),
+    Void,
+}
+
+/// The fields of a struct value, shared by its copies.
+pub type Fields = Rc<RefCell<Vec<Value>>>;
+
+/// The elements of an array value, shared by its copies.
+pub type Elements = Rc<RefCell<Vec<Value>>>;
+
+impl Value {
+    /// The value a variable of type `type_` starts with, which the generator stores too.
+    fn zero(type_: &str) -> Value {
+        match type_ {
+            "i32" => Value::I32(0),
+            "i64" => Value::I64(0),
+            "bool" => Value::Bool(false),
+            "str" => Value::Str(Rc::from("")),
+            "intarr" => Value::Array(Rc::default()),
+            "void" => Value::Void,
+            name => Value::Null(name.to_string()),
+        }
+    }
+
+    /// Convert an integer to the declared type `type_`.
+    ///
+    /// Integer literals are parsed as `i32` whatever their context, so they are widened when they
+    /// are stored in an `i64`.
+    fn coerce(self, type_: &str) -> Value {
+        match (self, type_) {
+            (Value::I32(i), "i64") => Value::I64(i as i64),
+            (Value::I64(i), "i32") => Value::I32(i as i32),
+            (value, _) => value,
+        }
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Value::I32(i) => write!(f, "{}", i),
+            Value::I64(i) => write!(f, "{}", i),
+            Value::Bool(b) => write!(f, "{}", b),
+            Value::Str(s) => write!(f, "{}", s),
+            Value::Array(elements) => {
+                let elements = elements
+                    .borrow()
+                    .iter()
+                    .map(Value::to_string)
+                    .collect::<Vec<_>>();
+                write!(f, "[{}]", elements.join(", "))
+            }
+            Value::Struct(name, fields) => {
+                let fields = fields
+                    .borrow()
+                    .iter()
+                    .map(Value::to_string)
+                    .collect::<Vec<_>>();
+                write!(f, "{}({})", name, fields.join(", "))
+            }
+            Value::Null(_) => write!(f, "null"),
+            Value::Void => write!(f, "void"),
+        }
+    }
+}
+
+/// Why evaluation stopped before reaching the end of an expression.
+enum Unwind {
+    /// A `return`, carrying the function's result up to the call.
+    Return(Value),
+    Error(Diagnostic),
+}
+
+impl From<Diagnostic> for Unwind {
+    fn from(diagnostic: Diagnostic) -> Self {
+        Unwind::Error(diagnostic)
+    }
+}
+
+type Eval<T> = std::result::Result<T, Unwind>;
+
+/// Runs a checked program by walking its AST, without LLVM.
+///
+/// It is meant as the reference semantics for the [`Generator`]: integers wrap at their width,
+/// comparisons produce `bool`s and externs are mapped to built-in Rust functions. Everything the
+/// program prints goes to the writer it was created with.
+///
+/// [`Generator`]: crate::generator::Generator
+pub struct Interpreter<W: Write> {
+    /// The scopes built by the parser, walked again while running.
+    symbols: SymbolTable,
+    /// Functions by name, with the scope of their parameters and body.
+    functions: HashMap<String, (Rc<Function>, ScopeId)>,
+    /// Externs declared by the program, with their return types.
+    externs: HashMap<String, String>,
+    /// Field names and types of each struct and class, in declaration order.
+    structs: HashMap<String, Vec<(String, String)>>,
+    /// Classes declared by the program. Their methods are in `functions` under their mangled
+    /// names.
+    classes: HashSet<String>,
+    /// Variables of every active call, innermost last. Top-level expressions use the first one.
+    frames: Vec<HashMap<SymbolId, Value>>,
+    out: W,
+}
+
+impl<W: Write> Interpreter<W> {
+    pub fn new(symbols: SymbolTable, out: W) -> Self {
+        Interpreter {
+            symbols,
+            functions: HashMap::new(),
+            externs: HashMap::new(),
+            structs: HashMap::new(),
+            classes: HashSet::new(),
+            frames: vec![HashMap::new()],
+            out,
+        }
+    }
+
+    /// Everything the program printed so far.
+    pub fn output(&self) -> &W {
+        &self.out
+    }
+
+    /// Define the program's items and evaluate its top-level expressions in order.
+    pub fn load(&mut self, program: &[(AstNode, NodePosition)]) -> Result<()> {
+        for (node, pos) in program {
+            match node {
+                AstNode::FunctionDef(f) => {
+                    let scope = self
+                        .symbols
+                        .enter_scope(ScopeKind::Function, pos.span.start);
+                    self.symbols.exit_scope();
+                    self.functions
+                        .insert(f.name.clone(), (Rc::new(f.clone()), scope));
+                }
+                AstNode::Extern(e) => {
+                    self.externs.insert(e.name.clone(), e.return_type.clone());
+                }
+                AstNode::Struct(name, members, _) => self.define_struct(name, members),
+                AstNode::Expression(e) => {
+                    self.eval_expression(e, pos.span)
+                        .map_err(|unwind| match unwind {
+                            Unwind::Return(_) => Diagnostic::error(
+                                ErrorCode::Runtime,
+                                "Cannot return outside of a function",
+                            )
+                            .with_span(pos.span),
+                            Unwind::Error(e) => e,
+                        })?;
+                }
+                AstNode::Class(class) => {
+                    self.define_struct(&class.name, &class.fields);
+                    self.classes.insert(class.name.clone());
+                    self.symbols.enter_scope(ScopeKind::Class, pos.span.start);
+                    for (method, pos) in &class.fns {
+                        let scope = self
+                            .symbols
+                            .enter_scope(ScopeKind::Function, pos.span.start);
+                        self.symbols.exit_scope();
+                        self.functions.insert(
+                            Class::mangle(&class.name, &method.name),
+                            (Rc::new(method.clone()), scope),
+                        );
+                    }
+                    self.symbols.exit_scope();
+                }
+                AstNode::Error => {
+                    return Err(Diagnostic::error(
+                        ErrorCode::Runtime,
+                        "Cannot run an item that failed to parse",
+                    )
+                    .with_span(pos.span))
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Make the fields of the struct or class `name` known.
+    fn define_struct(&mut self, name: &str, members: &StructMembers) {
+        let fields = members
+            .iter()
+            .map(|member| (member.name.clone(), member.type_.clone()))
+            .collect();
+        self.structs.insert(name.to_string(), fields);
+    }
+
+    /// Call `main` and return its result.
+    pub fn run_main(&mut self) -> Result<i32> {
+        match self.functions.get("main") {
+            Some((main, _)) if main.args.name.is_empty() && main.return_type == "i32" => {}
+            Some(_) => {
+                return Err(Diagnostic::error(
+                    ErrorCode::Runtime,
+                    "`main` must take no arguments and return `i32`",
+                ))
+            }
+            None => {
+                return Err(Diagnostic::error(
+                    ErrorCode::Runtime,
+                    "There is no `main` function to run",
+                ))
+            }
+        }
+        match self.call("main", Vec::new(), None)? {
+            Value::I32(status) => Ok(status),
+            other => Err(Diagnostic::error(
+                ErrorCode::Runtime,
+                format!("`main` returned `{}` instead of an `i32`", other),
+            )),
+        }
+    }
+
+    /// Call the function or extern `name` with already evaluated arguments.
+    fn call(&mut self, name: &str, args: Vec<Value>, span: Option<Span>) -> Result<Value> {
+        let (function, scope) = match self.functions.get(name) {
+            Some((function, scope)) => (Rc::clone(function), *scope),
+            None => return self.call_extern(name, args, span),
+        };
+        // Parameters and the body live in the function's own scope, wherever it is called from.
+        let caller = self.symbols.current_scope();
+        self.symbols.restore(scope);
+        let mut frame = HashMap::new();
+        for ((name, type_), (value, span)) in function
+            .args
+            .name
+            .iter()
+            .zip(&function.args.type_)
+            .zip(args.into_iter().zip(&function.args.span))
+        {
+            if let Some(id) = self.symbols.declared_at(name, *span) {
+                frame.insert(id, value.coerce(type_));
+            }
+        }
+        self.frames.push(frame);
+
+        let mut result = Ok(Value::zero(&function.return_type));
+        for expr in &function.expressions {
+            match self.eval(expr) {
+                Ok(_) => {}
+                Err(Unwind::Return(value)) => {
+                    result = Ok(value);
+                    break;
+                }
+                Err(Unwind::Error(e)) => {
+                    result = Err(e);
+                    break;
+                }
+            }
+        }
+
+        self.frames.pop();
+        self.symbols.restore(caller);
+        result.map(|value| value.coerce(&function.return_type))
+    }
+
+    /// Call an extern through its built-in implementation.
+    fn call_extern(&mut self, name: &str, args: Vec<Value>, span: Option<Span>) -> Result<Value> {
+        let error = |message: String| {
+            let diagnostic = Diagnostic::error(ErrorCode::Runtime, message);
+            match span {
+                Some(span) => diagnostic.with_span(span),
+                None => diagnostic,
+            }
+        };
+        // What the runtime reports with its `panic`.
+        let panic = |message: String| Diagnostic {
+            code: ErrorCode::Panic,
+            ..error(message)
+        };
+        let return_type = match self.externs.get(name) {
+            Some(return_type) => return_type.clone(),
+            None => return Err(error(format!("Function `{}` doesn't exist", name))),
+        };
+        let index = |elements: &Elements, i: i32| match usize::try_from(i) {
+            Ok(index) if index < elements.borrow().len() => Ok(index),
+            Ok(_) => Err(error(format!(
+                "Index {} is out of bounds for an array of length {}",
+                i,
+                elements.borrow().len()
+            ))),
+            Err(_) => Err(panic(format!("The index {} is negative", i))),
+        };
+        match (name, args.as_slice()) {
+            ("println", [Value::I32(n)]) => {
+                writeln!(self.out, "{}", n)
+                    .map_err(|e| error(format!("Unable to print: {}", e)))?;
+                Ok(Value::I32(*n))
+            }
+            ("print_intarr" | "println_intarr", [Value::Array(elements), Value::I32(length)]) => {
+                let length = usize::try_from(*length)
+                    .map_err(|_| panic(format!("The array length {} is negative", length)))?;
+                let shown = elements.borrow().iter().take(length).cloned().collect();
+                let shown = Value::Array(Rc::new(RefCell::new(shown)));
+                self.print(&shown, name.starts_with("println"))
+                    .map_err(|e| error(format!("Unable to print: {}", e)))?;
+                Ok(Value::Void)
+            }
+            (
+                "print_i32" | "println_i32" | "print_i64" | "println_i64" | "print_bool"
+                | "println_bool" | "print_str" | "println_str",
+                [value],
+            ) => {
+                self.print(value, name.starts_with("println"))
+                    .map_err(|e| error(format!("Unable to print: {}", e)))?;
+                Ok(Value::Void)
+            }
+            ("str_len", [Value::Str(s)]) => Ok(Value::I32(s.len() as i32)),
+            ("str_eq", [Value::Str(a), Value::Str(b)]) => Ok(Value::Bool(a == b)),
+            ("str_concat", [Value::Str(a), Value::Str(b)]) => {
+                Ok(Value::Str(Rc::from(format!("{}{}", a, b))))
+            }
+            ("str_from_i32", [Value::I32(n)]) => Ok(Value::Str(Rc::from(n.to_string()))),
+            ("str_from_i64", [Value::I64(n)]) => Ok(Value::Str(Rc::from(n.to_string()))),
+            ("panic", [Value::Str(message)]) => Err(panic(message.to_string())),
+            ("intarr_new", [Value::I32(length)]) => match usize::try_from(*length) {
+                Ok(length) => Ok(Value::Array(Rc::new(RefCell::new(vec![
+                    Value::I32(0);
+                    length
+                ])))),
+                Err(_) => Err(panic(format!("The array length {} is negative", length))),
+            },
+            ("index_arr", [Value::Array(elements), Value::I32(i)]) => {
+                let i = index(elements, *i)?;
+                let element = elements.borrow()[i].clone();
+                Ok(element)
+            }
+            ("set_arr", [Value::Array(elements), Value::I32(i), Value::I32(value)]) => {
+                let i = index(elements, *i)?;
+                elements.borrow_mut()[i] = Value::I32(*value);
+                Ok(Value::I32(*value))
+            }
+            // Memory is only managed for the values the program makes, so an allocation is
+            // the zero value of what the extern returns, e.g. `size / 4` zeros for an `intarr`.
+            ("alloc", [size @ (Value::I32(_) | Value::I64(_))]) => {
+                let size = match size {
+                    Value::I32(size) => *size as i64,
+                    Value::I64(size) => *size,
+                    _ => unreachable!(),
+                };
+                match (usize::try_from(size), return_type.as_str()) {
+                    (Err(_), _) => Err(panic(format!("Unable to allocate {} bytes", size))),
+                    (Ok(size), "intarr") => Ok(Value::Array(Rc::new(RefCell::new(vec![
+                        Value::I32(0);
+                        size / 4
+                    ])))),
+                    (Ok(_), type_) => Ok(Value::zero(type_)),
+                }
+            }
+            // Values are freed as soon as they are unreachable, so there is no heap to show
+            // or collect.
+            ("show_umap", []) => {
+                eprintln!("heap: not tracked by the interpreter");
+                Ok(Value::Void)
+            }
+            ("GC_gcollect", []) => Ok(Value::Void),
+            _ => Err(error(format!(
+                "The interpreter has no built-in implementation of `{}`",
+                name
+            ))
+            .with_note("only the runtime's functions can be interpreted; compile the program to call others")),
+        }
+    }
+
+    /// Print `value` the way the runtime's `print_*` functions do.
+    fn print(&mut self, value: &Value, newline: bool) -> std::io::Result<()> {
+        if newline {
+            writeln!(self.out, "{}", value)
+        } else {
+            write!(self.out, "{}", value)
+        }
+    }
+
+    /// The variables of the innermost call.
+    fn frame(&mut self) -> &mut HashMap<SymbolId, Value> {
+        self.frames.last_mut().unwrap()
+    }
+}
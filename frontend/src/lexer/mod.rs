@@ -1,11 +1,12 @@
 pub mod tokens;
 
+use crate::diagnostic::{Diagnostic, ErrorCode};
 use crate::lexer::tokens::{Token, TokenType};
+use crate::source::{FileId, SourceFile, SourceMap, Span};
 use crate::Result;
 
 use std::iter::Peekable;
 use std::vec::IntoIter;
-use std::{fs, io};
 
 /// A lexical analyzer that splits the program into [`Token`]s.
 ///
@@ -15,29 +16,49 @@ pub struct Lexer {
     raw_data: Peekable<IntoIter<char>>,
     pos: i32,
     line_no: i32,
-    file: String,
+    file_id: FileId,
+    /// Byte offset of the next character.
+    offset: usize,
 }
 
 impl Lexer {
+    /// Create a lexer over a file of the source map.
+    pub fn new(file: &SourceFile) -> Self {
+        Lexer {
+            raw_data: file.text.chars().collect::<Vec<_>>().into_iter().peekable(),
+            pos: -1,
+            line_no: 1,
+            file_id: file.id,
+            offset: 0,
+        }
+    }
+
     /// Create a lexer from a program file given the path to the file.
     ///
     /// # Arguments
+    /// * `sources` - The source map to load the file into.
     /// * `file_path` - The path to the program file.
-    pub fn from_file(file_path: &str) -> io::Result<Self> {
-        Ok(Self::from_text(&fs::read_to_string(file_path)?, file_path))
+    pub fn from_file(sources: &SourceMap, file_path: &str) -> Result<Self> {
+        let id = sources.load(file_path)?;
+        Ok(Self::new(&sources.get(id)))
     }
 
     /// Create a lexer with the program data in plain text.
     ///
     /// # Arguments
+    /// * `sources` - The source map to add the text to.
     /// * `text` - The raw program.
-    pub fn from_text(text: &str, file_path: &str) -> Self {
-        Lexer {
-            raw_data: text.chars().collect::<Vec<_>>().into_iter().peekable(),
-            pos: -1,
-            line_no: 1,
-            file: file_path.to_string(),
-        }
+    /// * `name` - The name to show for the program in diagnostics.
+    pub fn from_text(sources: &SourceMap, text: &str, name: &str) -> Self {
+        let id = sources.add(name, text);
+        Self::new(&sources.get(id))
+    }
+
+    /// Consume the next character, keeping track of its byte offset.
+    fn bump(&mut self) -> Option<char> {
+        let c = self.raw_data.next()?;
+        self.offset += c.len_utf8();
+        Some(c)
     }
 
     /// Create a token by eating characters while a condition is met.
@@ -56,7 +77,7 @@ impl Lexer {
                         self.pos = 0;
                     };
                     raw_token.push(*c);
-                    self.raw_data.next();
+                    self.bump();
                 }
                 _ => {
                     break;
@@ -69,8 +90,6 @@ impl Lexer {
     ///
     /// Identifiers must start with an alphabetic character or underscore, and then can have
     /// alphanumeric characters and underscores.
-    ///
-
     fn is_in_identifier(c: char) -> bool {
         c.is_ascii_alphanumeric() || c == '_'
     }
@@ -83,9 +102,10 @@ impl Iterator for Lexer {
     fn next(&mut self) -> Option<Self::Item> {
         let token: Result<TokenType>;
         let current_char: char;
+        let start: usize;
         // Find first non-whitespace character
         loop {
-            match self.raw_data.next() {
+            match self.bump() {
                 Some(' ') | Some('\t') => {
                     self.pos += 1;
                     continue;
@@ -105,6 +125,7 @@ impl Iterator for Lexer {
                 Some(c) => {
                     current_char = c;
                     self.pos += 1;
+                    start = self.offset - c.len_utf8();
                     break;
                 }
                 None => return None,
@@ -143,7 +164,11 @@ impl Iterator for Lexer {
 
             token = match value.parse() {
                 Ok(i) => Ok(TokenType::Integer(i)),
-                Err(_) => Err(format!("Integer literal {} is invalid", value)),
+                Err(_) => Err(Diagnostic::error(
+                    ErrorCode::InvalidToken,
+                    format!("Integer literal {} is invalid", value),
+                )
+                .with_span(Span::new(self.file_id, start, self.offset))),
             }
         }
         // String Literal
@@ -151,21 +176,21 @@ impl Iterator for Lexer {
             let mut value = String::new();
 
             self.get_next_char_while(&mut value, |c| c != '"');
-            self.raw_data.next(); // Eat trailing "
+            self.bump(); // Eat trailing "
 
             token = Ok(TokenType::Str(value));
         } else if current_char == '\'' {
             let mut value = String::new();
 
             self.get_next_char_while(&mut value, |c| c != '\'');
-            self.raw_data.next(); // Eat trailing "
+            self.bump(); // Eat trailing "
 
             token = Ok(TokenType::Str(value));
         } else if current_char == '`' {
             let mut value = String::new();
 
             self.get_next_char_while(&mut value, |c| c != '`');
-            self.raw_data.next(); // Eat trailing "
+            self.bump(); // Eat trailing "
 
             token = Ok(TokenType::Str(value));
         }
@@ -212,7 +237,7 @@ impl Iterator for Lexer {
         // Plus and PlusEq
         else if current_char == '+' {
             if self.raw_data.peek() == Some(&'=') {
-                self.raw_data.next(); // Eat =
+                self.bump(); // Eat =
                 token = Ok(TokenType::PlusEq);
             } else {
                 token = Ok(TokenType::Plus);
@@ -221,10 +246,10 @@ impl Iterator for Lexer {
         // Minus, Arrow and MinusEq
         else if current_char == '-' {
             if self.raw_data.peek() == Some(&'=') {
-                self.raw_data.next(); // Eat =
+                self.bump(); // Eat =
                 token = Ok(TokenType::MinusEq);
             } else if self.raw_data.peek() == Some(&'>') {
-                self.raw_data.next();
+                self.bump();
                 token = Ok(TokenType::Arrow);
             } else {
                 token = Ok(TokenType::Minus);
@@ -233,7 +258,7 @@ impl Iterator for Lexer {
         // Mul and MulEq
         else if current_char == '*' {
             if self.raw_data.peek() == Some(&'=') {
-                self.raw_data.next(); // Eat =
+                self.bump(); // Eat =
                 token = Ok(TokenType::MulEq);
             } else {
                 token = Ok(TokenType::Mul);
@@ -242,7 +267,7 @@ impl Iterator for Lexer {
         // Div and DivEq
         else if current_char == '/' {
             if self.raw_data.peek() == Some(&'=') {
-                self.raw_data.next(); // Eat =
+                self.bump(); // Eat =
                 token = Ok(TokenType::DivEq);
             } else {
                 token = Ok(TokenType::Div);
@@ -251,7 +276,7 @@ impl Iterator for Lexer {
         // Less and LessEq
         else if current_char == '<' {
             if self.raw_data.peek() == Some(&'=') {
-                self.raw_data.next(); // Eat =
+                self.bump(); // Eat =
                 token = Ok(TokenType::LessEq);
             } else {
                 token = Ok(TokenType::Less);
@@ -260,19 +285,19 @@ impl Iterator for Lexer {
         // Greater and GreaterEq
         else if current_char == '>' {
             if self.raw_data.peek() == Some(&'=') {
-                self.raw_data.next(); // Eat =
+                self.bump(); // Eat =
                 token = Ok(TokenType::GreaterEq);
             } else {
-                token = Ok(TokenType::GreaterEq);
+                token = Ok(TokenType::Greater);
             }
         }
         // Assign and Equal
         else if current_char == '=' {
             if self.raw_data.peek() == Some(&'=') {
-                self.raw_data.next(); // Eat =
+                self.bump(); // Eat =
                 token = Ok(TokenType::Equal);
             } else if self.raw_data.peek() == Some(&':') {
-                self.raw_data.next();
+                self.bump();
                 token = Ok(TokenType::Walrus);
             } else {
                 token = Ok(TokenType::Assign);
@@ -281,7 +306,7 @@ impl Iterator for Lexer {
         // Not and NotEq
         else if current_char == '!' {
             if self.raw_data.peek() == Some(&'=') {
-                self.raw_data.next(); // Eat =
+                self.bump(); // Eat =
                 token = Ok(TokenType::NotEq);
             } else {
                 token = Ok(TokenType::Not);
@@ -290,11 +315,11 @@ impl Iterator for Lexer {
             token = Ok(TokenType::Unknown)
         }
 
-        Some(Ok(Token {
-            type_: token.unwrap(),
+        Some(token.map(|type_| Token {
+            type_,
             pos: self.pos,
             line_no: self.line_no,
-            file: self.file.clone(),
+            span: Span::new(self.file_id, start, self.offset),
         }))
     }
 }
@@ -1,3 +1,5 @@
+use crate::source::Span;
+
 /// A token that is parsed by the [`Lexer`].
 ///
 /// [`Lexer`]: ../struct.Lexer.html
@@ -63,10 +65,50 @@ pub enum TokenType {
     Unknown,
 }
 
+/// Binding powers of the operator levels, from loosest to tightest.
+///
+/// Every binary operator is left-associative: `a - b - c` is `(a - b) - c`.
+pub mod precedence {
+    /// `==`, `!=`, `<`, `>`, `<=`, `>=`
+    pub const COMPARISON: i32 = 10;
+    /// `+`, `-`
+    pub const ADDITIVE: i32 = 20;
+    /// `*`, `/`
+    pub const MULTIPLICATIVE: i32 = 30;
+    /// Prefix `+`, `-` and `!`
+    pub const UNARY: i32 = 40;
+    /// `.`
+    pub const MEMBER: i32 = 50;
+}
+
+impl TokenType {
+    /// Binding power of the token when it appears between two operands, or `None` if it is not
+    /// a binary operator.
+    pub fn binary_precedence(&self) -> Option<i32> {
+        match self {
+            TokenType::Equal
+            | TokenType::NotEq
+            | TokenType::Less
+            | TokenType::Greater
+            | TokenType::LessEq
+            | TokenType::GreaterEq => Some(precedence::COMPARISON),
+            TokenType::Plus | TokenType::Minus => Some(precedence::ADDITIVE),
+            TokenType::Mul | TokenType::Div => Some(precedence::MULTIPLICATIVE),
+            TokenType::Dot => Some(precedence::MEMBER),
+            _ => None,
+        }
+    }
+
+    /// Whether the token can start a prefix (unary) expression.
+    pub fn is_unary_operator(&self) -> bool {
+        matches!(self, TokenType::Plus | TokenType::Minus | TokenType::Not)
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct Token {
     pub type_: TokenType,
     pub pos: i32,
     pub line_no: i32,
-    pub file: String,
+    pub span: Span,
 }
@@ -1,10 +1,23 @@
+#![allow(clippy::missing_safety_doc)]
+
+pub mod checker;
+pub mod diagnostic;
 pub mod generator;
+pub mod interpreter;
 pub mod lexer;
+pub mod linker;
 pub mod parser;
+pub mod repl;
+pub mod runtime;
+pub mod source;
+pub mod symbol_table;
 
-use clap::{App, Arg};
+use clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
+use generator::{CodeModel, RelocationModel, TargetOptions};
+use linker::LinkOptions;
 use log::LevelFilter;
-use std::collections::HashMap;
+use std::collections::BTreeMap;
+use std::io::{self, IsTerminal};
 use std::path;
 
 #[macro_export]
@@ -12,49 +25,90 @@ macro_rules! unwrap_some {
     ($val:expr) => {
         match $val {
             Some(s) => s,
-            None => return Err("EOF".to_string()),
+            None => return Err($crate::diagnostic::Diagnostic::unexpected_eof()),
         }
     };
 }
 
-pub type Result<T> = std::result::Result<T, String>;
+pub use symbol_table::{Symbol, SymbolTable};
 
-#[derive(Debug)]
-pub struct SymbolTable {
-    symbols: HashMap<String, Symbol>,
-}
+pub type Result<T> = std::result::Result<T, diagnostic::Diagnostic>;
 
-impl SymbolTable {
-    fn new() -> Self {
-        SymbolTable {
-            symbols: HashMap::new(),
-        }
-    }
-
-    fn insert(&mut self, name: String, symbol: Symbol) -> i32 {
-        self.symbols.insert(name, symbol);
-        0
-    }
+/// What the compiler was asked to do with the input.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    /// Compile the input to an executable.
+    Compile,
+    /// Compile the input in memory and run it, without producing any files.
+    Run,
+    /// Read and evaluate input interactively.
+    Repl,
+}
 
-    fn lookup(&mut self, name: String) -> Option<Symbol> {
-        self.symbols.get(&name).cloned()
-    }
+/// What runs or compiles the checked program.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Backend {
+    /// Generate code with LLVM.
+    Llvm,
+    /// Run the AST directly with the tree-walking interpreter.
+    Interp,
 }
 
-#[derive(Clone, Debug)]
-pub struct Symbol {
-    type_: String,
-    scope: String,
+/// An artifact the compiler can write, in the order they are produced.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Emit {
+    /// The raw tokens from the lexer.
+    Tokens,
+    /// The checked abstract syntax tree.
+    Ast,
+    /// Textual LLVM IR.
+    Ir,
+    /// LLVM bitcode.
+    Bc,
+    /// Target assembly.
+    Asm,
+    /// An object file.
+    Obj,
+    /// A linked executable.
+    Exe,
 }
 
-impl Symbol {
-    fn new(type_: String, scope: String) -> Self {
-        Symbol { type_, scope }
+impl Emit {
+    const NAMES: [&'static str; 7] = ["tokens", "ast", "ir", "bc", "asm", "obj", "exe"];
+
+    fn from_name(name: &str) -> Option<Emit> {
+        match name {
+            "tokens" => Some(Emit::Tokens),
+            "ast" => Some(Emit::Ast),
+            "ir" => Some(Emit::Ir),
+            "bc" => Some(Emit::Bc),
+            "asm" => Some(Emit::Asm),
+            "obj" => Some(Emit::Obj),
+            "exe" => Some(Emit::Exe),
+            _ => None,
+        }
+    }
+
+    /// Extension of the file the artifact is written to by default.
+    fn extension(self) -> &'static str {
+        match self {
+            Emit::Tokens => "tokens",
+            Emit::Ast => "ast",
+            Emit::Ir => "ir",
+            Emit::Bc => "bc",
+            Emit::Asm => "s",
+            Emit::Obj => "o",
+            Emit::Exe => "out",
+        }
     }
 }
 
 /// CLI input configuration and parameters.
 pub struct CLIInput {
+    /// What to do with the input.
+    pub mode: Mode,
+    /// What runs or compiles the program.
+    pub backend: Backend,
     /// Path to input file.
     pub input_path: String,
     /// `input_path` file name without file extension.
@@ -65,10 +119,73 @@ pub struct CLIInput {
     pub print_ast: bool,
     /// Whether to filter logs or not.
     pub verbose: u32,
-    /// output path
-    pub output_path: String,
-    /// Optimization level (0-3)
+    /// Whether to color diagnostics.
+    pub color: bool,
+    /// The artifacts to write when compiling, and where.
+    pub emit: BTreeMap<Emit, String>,
+    /// Optimization level (0-3) of both the IR passes and code generation.
     pub optimization: u32,
+    /// A custom LLVM pass pipeline, run instead of the one `optimization` selects.
+    pub passes: Option<String>,
+    /// Whether to generate DWARF debug information.
+    pub debug_info: bool,
+    /// The machine to compile for.
+    pub target: TargetOptions,
+    /// How to link the executable.
+    pub link: LinkOptions,
+}
+
+/// Arguments shared by compiling and running.
+fn input_args<'a, 'b>() -> Vec<Arg<'a, 'b>> {
+    vec![
+        Arg::with_name("input")
+            .help("Path to the yot file")
+            .required(true)
+            .index(1),
+        Arg::with_name("optimization")
+            .help("Level of optimization")
+            .takes_value(true)
+            .use_delimiter(false)
+            .possible_values(&["0", "1", "2", "3"])
+            .default_value("2")
+            .short("O")
+            .long("optimization"),
+        Arg::with_name("passes")
+            .help("LLVM pass pipeline to run instead of `default<On>`, as in `opt -passes=...`")
+            .takes_value(true)
+            .use_delimiter(false)
+            .long("passes"),
+        Arg::with_name("debug info")
+            .help("Generate DWARF debug information")
+            .short("g"),
+        Arg::with_name("backend")
+            .help("Generate code with LLVM, or run the program with the interpreter")
+            .takes_value(true)
+            .possible_values(&["llvm", "interp"])
+            .default_value("llvm")
+            .long("backend"),
+        Arg::with_name("print tokens")
+            .help("Print raw tokens from the lexer")
+            .long("print-tokens"),
+        Arg::with_name("print AST")
+            .help("Print the raw abstract syntax tree")
+            .long("print-ast"),
+        Arg::with_name("verbose")
+            .help("Level of logging (0-2)")
+            .short("v")
+            .multiple(true),
+        color_arg(),
+    ]
+}
+
+/// Whether to color diagnostics.
+fn color_arg<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("color")
+        .help("Color diagnostics, by default only when stderr is a terminal")
+        .takes_value(true)
+        .possible_values(&["auto", "always", "never"])
+        .default_value("auto")
+        .long("color")
 }
 
 /// Initialize command line application to parse arguments.
@@ -76,67 +193,218 @@ pub fn init_cli() -> CLIInput {
     let matches = App::new("skippc")
         .version(env!("CARGO_PKG_VERSION"))
         .about("Compiler for Skipp - a toy language")
-        .arg(
-            Arg::with_name("input")
-                .help("Path to the yot file")
-                .required(true)
-                .index(1),
-        )
+        .setting(AppSettings::SubcommandsNegateReqs)
+        .setting(AppSettings::ArgsNegateSubcommands)
+        .args(&input_args())
         .arg(
             Arg::with_name("output")
-                .help("Path to generated output")
+                .help("Path to the executable, or to the only artifact emitted")
                 .takes_value(true)
                 .short("o")
                 .long("output"),
         )
         .arg(
-            Arg::with_name("optimization")
-                .help("Level of optimization")
+            Arg::with_name("target")
+                .help("Target triple to compile for, e.g. `aarch64-unknown-linux-gnu`")
+                .takes_value(true)
+                .long("target"),
+        )
+        .arg(
+            Arg::with_name("target cpu")
+                .help("CPU to compile for [default: the target's generic CPU]")
+                .takes_value(true)
+                .long("target-cpu"),
+        )
+        .arg(
+            Arg::with_name("target features")
+                .help("Target features to enable or disable, e.g. `+neon,-fp-armv8`")
+                .takes_value(true)
+                .allow_hyphen_values(true)
+                .long("target-features"),
+        )
+        .arg(
+            Arg::with_name("relocation model")
+                .help("Relocation model of the generated code")
+                .takes_value(true)
+                .possible_values(&["static", "pic"])
+                .long("relocation-model"),
+        )
+        .arg(
+            Arg::with_name("code model")
+                .help("Code model of the generated code")
+                .takes_value(true)
+                .possible_values(&["small", "kernel", "medium", "large"])
+                .long("code-model"),
+        )
+        .arg(
+            Arg::with_name("linker")
+                .help("Linker to run [default: the first of cc, clang and ld.lld found]")
+                .takes_value(true)
+                .long("linker"),
+        )
+        .arg(
+            Arg::with_name("library")
+                .help("Native library to link")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .short("l"),
+        )
+        .arg(
+            Arg::with_name("library path")
+                .help("Directory to search for native libraries")
                 .takes_value(true)
-                .use_delimiter(false)
-                .possible_values(&["0", "1", "2", "3"])
-                .default_value("2")
-                .short("O")
-                .long("optimization"),
+                .multiple(true)
+                .number_of_values(1)
+                .short("L"),
         )
         .arg(
-            Arg::with_name("print tokens")
-                .help("Print raw tokens from the lexer")
-                .long("print-tokens"),
+            Arg::with_name("link arg")
+                .help("Argument to pass to the linker as it is")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .allow_hyphen_values(true)
+                .long("link-arg"),
         )
         .arg(
-            Arg::with_name("print AST")
-                .help("Print the raw abstract syntax tree")
-                .long("print-ast"),
+            Arg::with_name("runtime")
+                .help("Runtime archive to link instead of the bundled one")
+                .takes_value(true)
+                .long("runtime"),
         )
         .arg(
-            Arg::with_name("verbose")
-                .help("Level of logging (0-2)")
-                .short("v")
-                .multiple(true),
+            Arg::with_name("emit")
+                .help(
+                    "Artifacts to write, each optionally followed by `=<path>` \
+                     [possible values: tokens, ast, ir, bc, asm, obj, exe]",
+                )
+                .takes_value(true)
+                .use_delimiter(true)
+                .multiple(true)
+                .number_of_values(1)
+                .validator(|value| match value.split('=').next().and_then(Emit::from_name) {
+                    Some(_) => Ok(()),
+                    None => Err(format!(
+                        "`{}` isn't an artifact, expected one of {}",
+                        value,
+                        Emit::NAMES.join(", ")
+                    )),
+                })
+                .default_value("exe")
+                .long("emit"),
+        )
+        .subcommand(
+            SubCommand::with_name("run")
+                .about("Compile the program in memory and run it without producing any files")
+                .args(&input_args()),
+        )
+        .subcommand(
+            SubCommand::with_name("repl")
+                .about("Evaluate definitions and expressions interactively")
+                .arg(
+                    Arg::with_name("verbose")
+                        .help("Level of logging (0-2)")
+                        .short("v")
+                        .multiple(true),
+                )
+                .arg(color_arg()),
         )
         .get_matches();
 
-    let input_path = matches.value_of("input").unwrap();
+    let (mode, matches) = match matches.subcommand() {
+        ("run", Some(run)) => (Mode::Run, run),
+        ("repl", Some(repl)) => (Mode::Repl, repl),
+        _ => (Mode::Compile, &matches),
+    };
+
+    // The REPL has no input file.
+    let input_path = matches.value_of("input").unwrap_or_default();
     let input_name = path::Path::new(input_path)
         .file_stem()
-        .unwrap()
-        .to_str()
-        .unwrap();
+        .map_or("", |stem| stem.to_str().unwrap());
 
-    let default_output_path = format!("{}.{}", input_name, "out");
+    let mut emit = BTreeMap::new();
+    for value in matches.values_of("emit").into_iter().flatten() {
+        let (kind, path) = match value.find('=') {
+            Some(i) => (&value[..i], Some(String::from(&value[i + 1..]))),
+            None => (value, None),
+        };
+        emit.insert(Emit::from_name(kind).unwrap(), path);
+    }
+    // Like rustc, `-o` names the executable, or the artifact if there is only one.
+    let output_kind = match emit.keys().next() {
+        Some(&only) if emit.len() == 1 => only,
+        _ => Emit::Exe,
+    };
+    let emit = emit
+        .into_iter()
+        .map(|(kind, path)| {
+            let output = matches.value_of("output").filter(|_| kind == output_kind);
+            let path = path
+                .or_else(|| output.map(String::from))
+                .unwrap_or_else(|| format!("{}.{}", input_name, kind.extension()));
+            (kind, path)
+        })
+        .collect();
 
     CLIInput {
+        mode,
+        backend: match matches.value_of("backend") {
+            Some("interp") => Backend::Interp,
+            _ => Backend::Llvm,
+        },
         input_path: String::from(input_path),
         input_name: String::from(input_name),
-        output_path: String::from(matches.value_of("output").unwrap_or(&default_output_path)),
-        optimization: matches.value_of("optimization").unwrap().parse().unwrap(),
+        emit,
+        optimization: matches
+            .value_of("optimization")
+            .map_or(2, |level| level.parse().unwrap()),
+        passes: matches.value_of("passes").map(String::from),
+        debug_info: matches.is_present("debug info"),
+        target: TargetOptions {
+            triple: matches.value_of("target").map(String::from),
+            cpu: String::from(matches.value_of("target cpu").unwrap_or_default()),
+            features: String::from(matches.value_of("target features").unwrap_or_default()),
+            relocation_model: match matches.value_of("relocation model") {
+                Some("static") => RelocationModel::Static,
+                Some("pic") => RelocationModel::Pic,
+                _ => TargetOptions::default().relocation_model,
+            },
+            code_model: match matches.value_of("code model") {
+                Some("small") => CodeModel::Small,
+                Some("kernel") => CodeModel::Kernel,
+                Some("medium") => CodeModel::Medium,
+                Some("large") => CodeModel::Large,
+                _ => CodeModel::Default,
+            },
+        },
+        link: LinkOptions {
+            linker: matches.value_of("linker").map(String::from),
+            runtime: matches.value_of("runtime").map(String::from),
+            libraries: values(matches, "library"),
+            library_paths: values(matches, "library path"),
+            args: values(matches, "link arg"),
+            target: matches.value_of("target").map(String::from),
+        },
         print_tokens: matches.is_present("print tokens"),
         print_ast: matches.is_present("print AST"),
         verbose: matches.occurrences_of("verbose") as u32,
+        color: match matches.value_of("color") {
+            Some("always") => true,
+            Some("never") => false,
+            _ => io::stderr().is_terminal(),
+        },
     }
 }
 
+/// Every value given for the argument `name`.
+fn values(matches: &ArgMatches, name: &str) -> Vec<String> {
+    matches
+        .values_of(name)
+        .map_or_else(Vec::new, |values| values.map(String::from).collect())
+}
+
 /// Initialize logger with verbosity filter.
 pub fn init_logger(verbose: u32) {
     env_logger::builder()
@@ -0,0 +1,338 @@
+//! Linking object files into executables with the system's linker.
+
+use crate::diagnostic::{Diagnostic, ErrorCode};
+use crate::Result;
+
+use log::{debug, info};
+use std::env;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// Linkers tried in order when none is given.
+const DEFAULT_LINKERS: [&str; 3] = ["cc", "clang", "ld.lld"];
+
+/// File name of the runtime archive.
+const RUNTIME_ARCHIVE: &str = "libskipp_rt.a";
+
+/// Native libraries the runtime needs on `target`, as printed by
+/// `rustc --print native-static-libs`.
+fn runtime_libraries(target: &str) -> &'static [&'static str] {
+    if target.contains("linux-gnu") {
+        &["gcc_s", "util", "rt", "pthread", "m", "dl", "c"]
+    } else if target.contains("linux-musl") {
+        &["c"]
+    } else if target.contains("apple") {
+        &["System", "c", "m"]
+    } else if target.contains("freebsd") {
+        &["execinfo", "pthread", "gcc_s", "c", "m", "rt", "util"]
+    } else {
+        &[]
+    }
+}
+
+/// Where the runtime archive is looked for when none is given, in order.
+///
+/// An installed skippc finds it next to itself or in the `lib` directory beside its own, and a
+/// skippc run from its build directory falls back to the one its build script built.
+fn runtime_candidates() -> Vec<PathBuf> {
+    let mut candidates = Vec::new();
+    if let Some(path) = env::var_os("SKIPP_RUNTIME") {
+        candidates.push(PathBuf::from(path));
+    }
+    if let Some(dir) = env::current_exe()
+        .ok()
+        .and_then(|exe| exe.parent().map(Path::to_path_buf))
+    {
+        candidates.push(dir.join(RUNTIME_ARCHIVE));
+        candidates.push(dir.join("../lib/skipp").join(RUNTIME_ARCHIVE));
+    }
+    candidates.push(PathBuf::from(env!("SKIPP_RUNTIME")));
+    candidates
+}
+
+/// The target of `options` if it isn't the machine skippc runs on, which the bundled runtime
+/// archive was built for.
+fn cross_target(options: &LinkOptions) -> Option<&str> {
+    options
+        .target
+        .as_deref()
+        .filter(|target| *target != env!("SKIPP_HOST"))
+}
+
+/// How to link an executable.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LinkOptions {
+    /// Linker to run, by name or path, or `None` to use the first of [`DEFAULT_LINKERS`] found.
+    pub linker: Option<String>,
+    /// Runtime archive to link instead of the bundled one.
+    pub runtime: Option<String>,
+    /// Extra native libraries, as passed to `-l`.
+    pub libraries: Vec<String>,
+    /// Directories searched for libraries, as passed to `-L`.
+    pub library_paths: Vec<String>,
+    /// Arguments passed to the linker as they are.
+    pub args: Vec<String>,
+    /// Target triple of the objects, or `None` for the machine skippc runs on.
+    pub target: Option<String>,
+}
+
+/// How a linker expects to be called.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Flavor {
+    /// A C compiler driver such as `cc`, `gcc` or `clang`, which adds the C runtime and libc.
+    Cc,
+    /// A bare linker such as `ld` or `ld.lld`, which links only what it is given.
+    Ld,
+}
+
+impl Flavor {
+    fn of(program: &Path) -> Flavor {
+        let name = program
+            .file_name()
+            .map_or_else(String::new, |name| name.to_string_lossy().into_owned());
+        if name == "ld" || name.starts_with("ld.") || name.ends_with("-ld") || name.contains("lld")
+        {
+            Flavor::Ld
+        } else {
+            Flavor::Cc
+        }
+    }
+}
+
+/// A linker found on the system, and what to link with it.
+#[derive(Debug)]
+pub struct Linker {
+    program: PathBuf,
+    flavor: Flavor,
+    runtime: PathBuf,
+    options: LinkOptions,
+}
+
+impl Linker {
+    /// Find the linker `options` asks for.
+    pub fn new(options: LinkOptions) -> Result<Linker> {
+        let program = match &options.linker {
+            Some(linker) => find_program(linker).ok_or_else(|| {
+                Diagnostic::error(
+                    ErrorCode::Link,
+                    format!("Unable to find the linker `{}`", linker),
+                )
+            })?,
+            None => DEFAULT_LINKERS
+                .iter()
+                .find_map(|linker| find_program(linker))
+                .ok_or_else(|| {
+                    Diagnostic::error(ErrorCode::Link, "Unable to find a linker")
+                        .with_note(format!("tried {}", DEFAULT_LINKERS.join(", ")))
+                        .with_note("choose one with `--linker <path>`")
+                })?,
+        };
+        let runtime = match (&options.runtime, cross_target(&options)) {
+            (Some(runtime), _) => PathBuf::from(runtime),
+            // None of the candidates but `SKIPP_RUNTIME` can be for another target.
+            (None, Some(target)) => match env::var_os("SKIPP_RUNTIME") {
+                Some(runtime) => PathBuf::from(runtime),
+                None => {
+                    return Err(Diagnostic::error(
+                        ErrorCode::Link,
+                        format!("No runtime archive for the target `{}`", target),
+                    )
+                    .with_note(format!(
+                        "the bundled runtime archive is built for `{}`",
+                        env!("SKIPP_HOST")
+                    ))
+                    .with_note(
+                        "build skipp-rt for the target and choose it with `--runtime <path>` \
+                         or `SKIPP_RUNTIME`",
+                    ));
+                }
+            },
+            (None, None) => {
+                let candidates = runtime_candidates();
+                match candidates.iter().find(|path| path.is_file()) {
+                    Some(runtime) => runtime.clone(),
+                    None => {
+                        let tried = candidates.iter().map(|path| path.display().to_string());
+                        return Err(Diagnostic::error(
+                            ErrorCode::Link,
+                            "Unable to find the runtime archive",
+                        )
+                        .with_note(format!("tried {}", tried.collect::<Vec<_>>().join(", ")))
+                        .with_note("choose one with `--runtime <path>`"));
+                    }
+                }
+            }
+        };
+        if !runtime.is_file() {
+            return Err(Diagnostic::error(
+                ErrorCode::Link,
+                format!("The runtime archive `{}` doesn't exist", runtime.display()),
+            ));
+        }
+        info!("Linker: {}", program.display());
+        Ok(Linker {
+            flavor: Flavor::of(&program),
+            program,
+            runtime,
+            options,
+        })
+    }
+
+    /// The command that links `objects` into the executable `output`.
+    ///
+    /// The runtime comes after everything the user gave, so it resolves what their objects and
+    /// libraries use from it.
+    pub fn command(&self, objects: &[&Path], output: &Path) -> Command {
+        let mut command = Command::new(&self.program);
+        // A bare linker takes the target from the objects.
+        if let (Flavor::Cc, Some(target)) = (self.flavor, cross_target(&self.options)) {
+            command.arg(format!("--target={}", target));
+        }
+        command.args(objects);
+        for path in &self.options.library_paths {
+            command.arg(format!("-L{}", path));
+        }
+        for library in &self.options.libraries {
+            command.arg(format!("-l{}", library));
+        }
+        command.args(&self.options.args).arg(&self.runtime);
+        // A bare linker doesn't know where the system libraries are, so they are left to `-l` and `-L`.
+        if self.flavor == Flavor::Cc {
+            let target = self.options.target.as_deref().unwrap_or(env!("SKIPP_HOST"));
+            for library in runtime_libraries(target) {
+                command.arg(format!("-l{}", library));
+            }
+        }
+        command.arg("-o").arg(output);
+        command
+    }
+
+    /// Link `objects` into the executable `output`, waiting for the linker to finish.
+    pub fn link(&self, objects: &[&Path], output: &Path) -> Result<()> {
+        let mut command = self.command(objects, output);
+        debug!("Linking: {:?}", command);
+        let result = command.output().map_err(|e| {
+            Diagnostic::error(
+                ErrorCode::Link,
+                format!("Unable to run the linker `{}`: {}", self.program.display(), e),
+            )
+        })?;
+        if !result.status.success() {
+            let stderr = String::from_utf8_lossy(&result.stderr);
+            let mut diagnostic = Diagnostic::error(
+                ErrorCode::Link,
+                format!(
+                    "Linking with `{}` failed: {}",
+                    self.program.display(),
+                    result.status
+                ),
+            )
+            .with_note(format!("the linker was run as {:?}", command));
+            if !stderr.trim().is_empty() {
+                diagnostic = diagnostic.with_note(format!("it reported:\n{}", stderr.trim_end()));
+            }
+            return Err(diagnostic);
+        }
+        debug!("Successfully linked {}", output.display());
+        Ok(())
+    }
+}
+
+/// The path of `program`, looked up in `PATH` unless it names a file already.
+fn find_program(program: &str) -> Option<PathBuf> {
+    let path = Path::new(program);
+    if path.components().count() > 1 {
+        return Some(path.to_path_buf()).filter(|path| path.is_file());
+    }
+    env::split_paths(&env::var_os("PATH")?)
+        .map(|dir| dir.join(program))
+        .find(|candidate| candidate.is_file())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{runtime_libraries, Flavor, LinkOptions, Linker};
+    use crate::diagnostic::ErrorCode;
+    use std::path::Path;
+
+    #[test]
+    fn flavor_follows_the_linker_name() {
+        assert_eq!(Flavor::of(Path::new("/usr/bin/cc")), Flavor::Cc);
+        assert_eq!(Flavor::of(Path::new("clang-14")), Flavor::Cc);
+        assert_eq!(Flavor::of(Path::new("aarch64-linux-gnu-gcc")), Flavor::Cc);
+        assert_eq!(Flavor::of(Path::new("ld.lld")), Flavor::Ld);
+        assert_eq!(Flavor::of(Path::new("/usr/bin/ld")), Flavor::Ld);
+        assert_eq!(Flavor::of(Path::new("riscv64-linux-gnu-ld")), Flavor::Ld);
+    }
+
+    #[test]
+    fn libraries_and_search_paths_are_passed_on() {
+        // Any program will do, as it isn't run.
+        let program = std::env::current_exe().unwrap();
+        let linker = Linker::new(LinkOptions {
+            linker: Some(program.to_string_lossy().into_owned()),
+            libraries: vec![String::from("m")],
+            library_paths: vec![String::from("/opt/lib")],
+            ..LinkOptions::default()
+        })
+        .unwrap();
+        let command = format!(
+            "{:?}",
+            linker.command(&[Path::new("a.o")], Path::new("a.out"))
+        );
+        assert!(command.contains("\"-L/opt/lib\" \"-lm\""), "{}", command);
+        assert!(command.ends_with("\"-o\" \"a.out\""), "{}", command);
+        // The runtime goes after the libraries that may use it.
+        let runtime = command.find("libskipp_rt.a").unwrap();
+        assert!(command.find("-lm").unwrap() < runtime, "{}", command);
+
+        let missing = Linker::new(LinkOptions {
+            linker: Some(String::from("no-such-linker")),
+            ..LinkOptions::default()
+        });
+        assert_eq!(missing.unwrap_err().code, ErrorCode::Link);
+    }
+
+    #[test]
+    fn other_targets_need_their_own_runtime() {
+        let program = std::env::current_exe().unwrap();
+        let options = LinkOptions {
+            linker: Some(program.to_string_lossy().into_owned()),
+            target: Some(String::from("riscv64gc-unknown-linux-gnu")),
+            ..LinkOptions::default()
+        };
+        if std::env::var_os("SKIPP_RUNTIME").is_none() {
+            let missing = Linker::new(options.clone());
+            assert_eq!(missing.unwrap_err().code, ErrorCode::Link);
+        }
+
+        let linker = Linker::new(LinkOptions {
+            runtime: Some(env!("SKIPP_RUNTIME").to_string()),
+            ..options
+        })
+        .unwrap();
+        let command = format!(
+            "{:?}",
+            linker.command(&[Path::new("a.o")], Path::new("a.out"))
+        );
+        let target = "\"--target=riscv64gc-unknown-linux-gnu\" \"a.o\"";
+        assert!(command.contains(target), "{}", command);
+
+        // The host's own triple needs nothing more, and not every `cc` knows `--target`.
+        let linker = Linker::new(LinkOptions {
+            target: Some(env!("SKIPP_HOST").to_string()),
+            ..linker.options
+        })
+        .unwrap();
+        let command = format!("{:?}", linker.command(&[], Path::new("a.out")));
+        assert!(!command.contains("--target"), "{}", command);
+    }
+
+    #[test]
+    fn runtime_libraries_follow_the_target() {
+        assert!(runtime_libraries("x86_64-unknown-linux-gnu").contains(&"gcc_s"));
+        assert_eq!(runtime_libraries("x86_64-unknown-linux-musl"), ["c"]);
+        assert!(runtime_libraries("aarch64-apple-darwin").contains(&"System"));
+        assert!(runtime_libraries("wasm32-unknown-unknown").is_empty());
+    }
+}
@@ -1,67 +1,201 @@
+use frontend::checker::Checker;
+use frontend::diagnostic::{self, Diagnostic, ErrorCode};
 use frontend::generator::Generator;
+use frontend::interpreter::Interpreter;
 use frontend::lexer::Lexer;
+use frontend::linker::Linker;
 use frontend::parser::Parser;
-use frontend::{init_cli, init_logger};
-use log::error;
+use frontend::repl::Repl;
+use frontend::source::SourceMap;
+use frontend::{init_cli, init_logger, Backend, Emit, Mode};
+use std::collections::hash_map::RandomState;
+use std::hash::BuildHasher;
+use std::os::unix::fs::DirBuilderExt;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+use std::{env, fs, io, process};
+use std::rc::Rc;
 use log::warn;
-use std::fs;
-use std::process;
 
-/// Unwrap and return result, or log and exit if Err.
+/// Unwrap and return result, or render the diagnostic against `$sources` and exit if Err.
 macro_rules! unwrap_or_exit {
-    ($f:expr, $origin:tt) => {
+    ($sources:expr, $f:expr) => {
         match $f {
             Ok(a) => a,
             Err(e) => {
-                error!("{}: {}", $origin, e);
+                eprintln!("{}", e.render(&$sources));
                 process::exit(1);
             }
         }
     };
 }
 
+/// A directory of skippc's own for temporary files, removed with everything in it when dropped.
+struct TempDir(PathBuf);
+
+impl TempDir {
+    /// Create a new directory in the system's temporary directory, that only this user can
+    /// enter, under a name no other file has.
+    fn new() -> io::Result<TempDir> {
+        let random = RandomState::new();
+        let mut attempt = 0u32;
+        loop {
+            let suffix = random.hash_one((process::id(), attempt, SystemTime::now()));
+            let path = env::temp_dir().join(format!("skippc-{:016x}", suffix));
+            // Creating rather than reusing the directory keeps anyone else from having put files
+            // in it.
+            match fs::DirBuilder::new().mode(0o700).create(&path) {
+                Ok(()) => return Ok(TempDir(path)),
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists && attempt < 100 => attempt += 1,
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        fs::remove_dir_all(&self.0).unwrap_or_else(|e| {
+            warn!("Unable to delete `{}`: {}", self.0.display(), e);
+        });
+    }
+}
+
+/// Write a textual artifact requested with `--emit`.
+fn write_artifact(path: &str, contents: &str) -> frontend::Result<()> {
+    fs::write(path, contents).map_err(|e| {
+        Diagnostic::error(ErrorCode::Io, format!("Unable to write `{}`: {}", path, e))
+    })
+}
+
 pub fn main() {
     let cli_input = init_cli();
     init_logger(cli_input.verbose);
+    diagnostic::set_color(cli_input.color);
+
+    if cli_input.mode == Mode::Repl {
+        unsafe { Repl::new().run() };
+        return;
+    }
+
+    let sources = Rc::new(SourceMap::new());
 
     // Lexer
-    let lexer = unwrap_or_exit!(Lexer::from_file(&cli_input.input_path), "IO");
+    let lexer = unwrap_or_exit!(sources, Lexer::from_file(&sources, &cli_input.input_path));
     let tokens = lexer
-        .map(|t| unwrap_or_exit!(t, "Lexing"))
+        .map(|t| unwrap_or_exit!(sources, t))
         .collect::<Vec<_>>();
 
     if cli_input.print_tokens {
         println!("***TOKENS***");
         tokens.iter().for_each(|t| println!("{:?}", t));
     }
+    if let Some(path) = cli_input.emit.get(&Emit::Tokens) {
+        let dump = tokens.iter().map(|t| format!("{:?}\n", t)).collect::<String>();
+        unwrap_or_exit!(sources, write_artifact(path, &dump));
+    }
 
     // Parser
-    let mut parser = Parser::new(tokens.into_iter().peekable(), &cli_input.input_path);
-    let program = unwrap_or_exit!(parser.parse_program(), "Parsing");
-    if !true {
+    let mut parser = Parser::new(tokens.into_iter().peekable());
+    let (mut program, errors) = parser.parse_program();
+    if !errors.is_empty() {
+        errors.iter().for_each(|e| eprintln!("{}\n", e.render(&sources)));
+        process::exit(1);
+    }
+
+    // Type checker
+    let mut checker = Checker::new(parser.symtab);
+    let errors = checker.check_program(&mut program);
+    if !errors.is_empty() {
+        errors.iter().for_each(|e| eprintln!("{}\n", e.render(&sources)));
+        process::exit(1);
+    }
+    if cli_input.print_ast {
         println!("***AST***\n{:#?}", program);
     }
+    if let Some(path) = cli_input.emit.get(&Emit::Ast) {
+        unwrap_or_exit!(sources, write_artifact(path, &format!("{:#?}\n", program)));
+    }
 
-    let generator = unsafe { Generator::new(program, &cli_input.input_name) };
-    unsafe {
-        generator.init();
-        unwrap_or_exit!(generator.generate(), "Code Generation");
-        // unwrap_or_exit!(generator.verify(), "LLVM");
-        // generator.optimize();
+    if cli_input.backend == Backend::Interp {
+        let mut interpreter = Interpreter::new(checker.into_symbols(), io::stdout());
+        unwrap_or_exit!(sources, interpreter.load(&program));
+        match interpreter.run_main() {
+            Ok(status) => process::exit(status),
+            Err(e) => {
+                eprintln!("{}", e.render(&sources));
+                // Exit the way a compiled program that panicked would.
+                let panicked = e.code == ErrorCode::Panic;
+                process::exit(if panicked { skipp_rt::panic::EXIT_CODE } else { 1 });
+            }
+        }
+    }
 
-        let object_file = format!("{}.o", cli_input.input_name);
+    // Nothing left to produce without generating code.
+    if cli_input.mode == Mode::Compile && cli_input.emit.keys().all(|&kind| kind <= Emit::Ast) {
+        return;
+    }
 
+    let mut generator = unsafe { Generator::new(
+        program,
+        &cli_input.input_name,
+        Rc::clone(&sources),
+        checker.into_symbols(),
+    ) };
+    unsafe {
+        unwrap_or_exit!(sources, generator.set_target(cli_input.target.clone()));
+        if cli_input.debug_info {
+            generator.init_debug_info(cli_input.optimization > 0);
+        }
+        generator.init();
+        unwrap_or_exit!(sources, generator.generate());
+        unwrap_or_exit!(sources, generator.verify());
         unwrap_or_exit!(
-            generator.generate_ir(format!("{}.ir", cli_input.input_name).as_str()),
-            "LLVM"
-        );
-        unwrap_or_exit!(generator.generate_object_file(3, &object_file), "LLVM");
-        unwrap_or_exit!(
-            generator.generate_executable(&object_file, &cli_input.output_path),
-            "Linker"
+            sources,
+            generator.optimize(cli_input.optimization, cli_input.passes.as_deref())
         );
-        // fs::remove_file(object_file).unwrap_or_else(|e| {
-        //     warn!("Unable to delete object file:\n{}", e);
-        // });
+
+        if cli_input.mode == Mode::Run {
+            let status = unwrap_or_exit!(sources, generator.run_main(cli_input.optimization));
+            drop(generator);
+            process::exit(status);
+        }
+
+        if let Some(path) = cli_input.emit.get(&Emit::Ir) {
+            unwrap_or_exit!(sources, generator.generate_ir(path));
+        }
+        if let Some(path) = cli_input.emit.get(&Emit::Bc) {
+            unwrap_or_exit!(sources, generator.generate_bitcode(path));
+        }
+        if let Some(path) = cli_input.emit.get(&Emit::Asm) {
+            unwrap_or_exit!(sources, generator.generate_assembly(cli_input.optimization, path));
+        }
+
+        let executable = cli_input.emit.get(&Emit::Exe);
+        // The object file only needed for linking goes to a temporary directory.
+        let (object_file, temp_dir) = match (cli_input.emit.get(&Emit::Obj), executable) {
+            (Some(path), _) => (path.clone(), None),
+            (None, Some(_)) => {
+                let temp_dir = unwrap_or_exit!(sources, TempDir::new().map_err(|e| {
+                    Diagnostic::error(
+                        ErrorCode::Io,
+                        format!("Unable to create a temporary directory: {}", e),
+                    )
+                }));
+                let path = temp_dir.0.join(format!("{}.o", cli_input.input_name));
+                (path.to_string_lossy().into_owned(), Some(temp_dir))
+            }
+            (None, None) => return,
+        };
+        let result = generator
+            .generate_object_file(cli_input.optimization, &object_file)
+            .and_then(|_| match executable {
+                Some(path) => Linker::new(cli_input.link.clone())
+                    .and_then(|linker| linker.link(&[Path::new(&object_file)], Path::new(path))),
+                None => Ok(()),
+            });
+        // Exiting skips destructors.
+        drop(temp_dir);
+        unwrap_or_exit!(sources, result);
     }
 }
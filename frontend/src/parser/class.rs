@@ -1,27 +1,20 @@
+use crate::diagnostic::{Diagnostic, ErrorCode};
 use crate::lexer::tokens::TokenType;
-use crate::parser::{Class, Function, NodePosition, Parser};
+use crate::parser::{Class, Function, NodePosition, Parser, Repr, StructMember, StructMembers};
+use crate::symbol_table::{ScopeKind, Symbol, SymbolKind};
 use crate::{unwrap_some, Result};
 
-use std::collections::HashMap;
-
 impl Parser {
     pub fn parse_class(&mut self) -> Result<(Class, NodePosition)> {
+        let mut fields: StructMembers = Vec::new();
         let mut fns: Vec<(Function, NodePosition)> = Vec::new();
 
-        // println!("{:#?}", self.tokens.peek());
-
         self.advance();
         let nx = unwrap_some!(self.tokens.next()); // Eat class
-        let start = NodePosition {
-            pos: nx.pos,
-            line_no: nx.line_no,
-            file: nx.file.to_string(),
-        };
-        // println!("{:#?}", self.tokens.peek());
 
         let name = match &unwrap_some!(self.tokens.peek()).type_ {
             TokenType::Identifier(i) => i.clone(),
-            _ => return Err("Syntax Error: expected Identifier after keyword 'class'".to_string()),
+            _ => return Err(self.parser_error("Expected Identifier after keyword 'class'")),
         };
         self.advance();
         self.tokens.next(); // eat the identifier
@@ -29,69 +22,64 @@ impl Parser {
         self.advance();
         match unwrap_some!(self.tokens.next()).type_ {
             TokenType::LBrace => {}
-            _ => return Err("Expected '{' in class".to_string()),
+            _ => return Err(self.parser_error("Expected '{' in class")),
         }
+        self.symtab.enter_scope(ScopeKind::Class, nx.span.start);
 
         while unwrap_some!(self.tokens.peek()).type_ != TokenType::RBrace {
-            // println!("{:#?}", self.tokens.peek());
             match unwrap_some!(self.tokens.peek()).type_ {
-                TokenType::Def => {}
-                _ => return Err(self.parser_error("SyntaxError: expected Function")),
-            }
-            match self.parse_function() {
-                Ok((f, p)) => fns.insert(fns.len(), (f, p)),
-                Err(e) => {
-                    println!("oops");
-                    return Err(e);
+                TokenType::Def => fns.push(self.parse_method(&name)?),
+                TokenType::Identifier(_) => {
+                    let (name, type_, span) = self.parse_type_annot()?;
+                    self.add_member(&mut fields, StructMember { name, type_, span });
                 }
+                _ => return Err(self.parser_error("Expected a field or a method in class")),
             }
         }
         self.advance();
         self.tokens.next(); // eat '}'
-        Ok((Class { name, fns }, start))
+        self.symtab.exit_scope();
+        let pos = self.position_from(&nx);
+        self.declare(Symbol::new(&name, SymbolKind::Class, &name, pos.span));
+        Ok((Class { name, fields, fns }, pos))
     }
 
-    pub fn parse_struct(
-        &mut self,
-    ) -> Result<((String, HashMap<String, (String, i32)>), NodePosition)> {
-        let mut members: HashMap<String, (String, i32)> = HashMap::new();
+    pub fn parse_struct(&mut self) -> Result<((String, StructMembers, Repr), NodePosition)> {
+        let mut members: StructMembers = Vec::new();
 
         // println!("{:#?}", self.tokens.peek());
 
         self.advance();
         let nx = unwrap_some!(self.tokens.next()); // Eat struct
-        let start = NodePosition {
-            pos: nx.pos,
-            line_no: nx.line_no,
-            file: nx.file.to_string(),
-        };
 
         let name = match &unwrap_some!(self.tokens.peek()).type_ {
             TokenType::Identifier(i) => i.clone(),
-            _ => return Err("Syntax Error: expected Identifier after keyword 'struct'".to_string()),
+            _ => return Err(self.parser_error("Expected Identifier after keyword 'struct'")),
         };
         self.advance();
         self.tokens.next(); // eat the identifier
 
+        let repr = match &unwrap_some!(self.tokens.peek()).type_ {
+            TokenType::Identifier(i) if i == "repr" => self.parse_repr()?,
+            _ => Repr::default(),
+        };
+
         self.advance();
         match unwrap_some!(self.tokens.next()).type_ {
             TokenType::LBrace => {}
-            _ => return Err("Expected '{' in struct".to_string()),
+            _ => return Err(self.parser_error("Expected '{' in struct")),
         }
 
-        let mut index = 0;
-
         while unwrap_some!(self.tokens.peek()).type_ != TokenType::RBrace {
             // println!("{:#?}", self.tokens.peek());
-            let mut name = "".to_string();
-            match &unwrap_some!(self.tokens.peek()).type_ {
-                TokenType::Identifier(n) => {
-                    name = n.clone();
-                    self.advance();
-                    self.tokens.next();
-                }
+            let member = unwrap_some!(self.tokens.peek());
+            let start = member.span;
+            let name = match &member.type_ {
+                TokenType::Identifier(n) => n.clone(),
                 _ => return Err(self.parser_error("SyntaxError: expected Identifier")),
-            }
+            };
+            self.advance();
+            self.tokens.next();
             self.advance();
             if let TokenType::Colon = unwrap_some!(self.tokens.next()).type_ {
             } else {
@@ -100,14 +88,90 @@ impl Parser {
 
             self.advance();
             if let TokenType::Identifier(type_) = unwrap_some!(self.tokens.next()).type_ {
-                members.insert(name.clone(), (type_, index));
+                let span = start.to(self.tokens.last);
+                self.add_member(&mut members, StructMember { name, type_, span });
             } else {
                 return Err(self.parser_error("SyntaxError: expected type"));
             }
-            index += 1;
         }
         self.advance();
         self.tokens.next(); // eat '}'
-        Ok(((name, members), start))
+        let pos = self.position_from(&nx);
+        self.declare(Symbol::new(&name, SymbolKind::Struct, &name, pos.span));
+        Ok(((name, members, repr), pos))
+    }
+
+    /// Add `member` to `members`, reporting a clash with a member of the same name.
+    fn add_member(&mut self, members: &mut StructMembers, member: StructMember) {
+        match members.iter().find(|m| m.name == member.name) {
+            Some(previous) => self.errors.push(
+                Diagnostic::error(
+                    ErrorCode::Redefinition,
+                    format!("Field `{}` is already declared", member.name),
+                )
+                .with_span(member.span)
+                .with_label(previous.span, "previously declared here"),
+            ),
+            None => members.push(member),
+        }
+    }
+
+    /// Parse `repr(option, ...)`, where an option is `C`, `packed` or `align(n)`.
+    fn parse_repr(&mut self) -> Result<Repr> {
+        self.advance();
+        self.tokens.next(); // eat `repr`
+        self.advance();
+        match unwrap_some!(self.tokens.next()).type_ {
+            TokenType::LParen => {}
+            _ => return Err(self.parser_error("Expected '(' after 'repr'")),
+        }
+
+        let mut repr = Repr::default();
+        loop {
+            self.advance();
+            match unwrap_some!(self.tokens.next()).type_ {
+                TokenType::Identifier(option) if option == "C" => repr.c = true,
+                TokenType::Identifier(option) if option == "packed" => repr.packed = true,
+                TokenType::Identifier(option) if option == "align" => {
+                    repr.align = Some(self.parse_align()?)
+                }
+                _ => {
+                    return Err(self.parser_error("Expected 'C', 'packed' or 'align(n)' in 'repr'"))
+                }
+            }
+            self.advance();
+            match unwrap_some!(self.tokens.next()).type_ {
+                TokenType::Comma => {}
+                TokenType::RParen => break,
+                _ => return Err(self.parser_error("Expected ',' or ')' in 'repr'")),
+            }
+        }
+        if repr.packed && repr.align.is_some() {
+            return Err(self.parser_error("A struct can't be both packed and aligned"));
+        }
+        Ok(repr)
+    }
+
+    /// Parse the `(n)` of `align(n)`.
+    fn parse_align(&mut self) -> Result<u32> {
+        self.advance();
+        match unwrap_some!(self.tokens.next()).type_ {
+            TokenType::LParen => {}
+            _ => return Err(self.parser_error("Expected '(' after 'align'")),
+        }
+        self.advance();
+        let align = match unwrap_some!(self.tokens.next()).type_ {
+            TokenType::Integer(n) if n > 0 && (n as u32).is_power_of_two() => n as u32,
+            _ => return Err(self.parser_error("The alignment must be a power of two")),
+        };
+        // The heap starts every object at a multiple of 16 bytes, and promises nothing more.
+        if align > 16 {
+            return Err(self.parser_error("Structs can't be aligned to more than 16 bytes"));
+        }
+        self.advance();
+        match unwrap_some!(self.tokens.next()).type_ {
+            TokenType::RParen => Ok(align),
+            _ => Err(self.parser_error("Expected ')' after the alignment")),
+        }
     }
 }
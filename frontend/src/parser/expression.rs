@@ -1,108 +1,209 @@
-use crate::lexer::tokens::TokenType;
-use crate::parser::{ExprValue, NodePosition, Parser};
-use crate::{unwrap_some, Result, Symbol};
+use crate::diagnostic::{Diagnostic, ErrorCode};
+use crate::lexer::tokens::{precedence, TokenType};
+use crate::parser::{Expr, ExprValue, NodePosition, Parser};
+use crate::symbol_table::{ScopeKind, Symbol, SymbolKind};
+use crate::{unwrap_some, Result};
 use log::trace;
 
 impl Parser {
     pub fn parse_expression(&mut self) -> Result<(ExprValue, NodePosition)> {
         trace!("Parsing expression");
-        let l_value: Result<(ExprValue, NodePosition)> =
-            match unwrap_some!(self.tokens.peek()).type_ {
-                TokenType::LParen => {
-                    self.advance();
-                    self.tokens.next();
-                    self.parse_paren_expression()
-                }
-                TokenType::LBrack => self.parse_array(),
-                // Unary
-                TokenType::Plus | TokenType::Minus | TokenType::Not => self.parse_unop(),
+        let lhs = self.parse_primary()?;
+        let expression = self.parse_binop_rhs(0, lhs)?;
+        self.parse_field_assignment(expression)
+    }
 
-                TokenType::If => self.parse_if_else(),
+    /// Parse the `= value` or `op= value` following `target`, if there is one.
+    ///
+    /// Assignments to variables are parsed with the variable's name, but a field is only known to
+    /// be assigned once the whole `a.b.c` before the `=` has been parsed.
+    fn parse_field_assignment(
+        &mut self,
+        target: (ExprValue, NodePosition),
+    ) -> Result<(ExprValue, NodePosition)> {
+        let op = match unwrap_some!(self.tokens.peek()).type_ {
+            TokenType::Assign => None,
+            TokenType::PlusEq | TokenType::MinusEq | TokenType::MulEq | TokenType::DivEq => {
+                Some(Box::new(unwrap_some!(self.tokens.peek()).type_.clone()))
+            }
+            _ => return Ok(target),
+        };
+        let (object, field) = match target.0 {
+            ExprValue::FieldAccess { object, field, .. } => (object, field),
+            _ => return Err(self.parser_error("Only variables and fields can be assigned to")),
+        };
+        self.advance();
+        self.tokens.next(); // Eat the '=' or 'op='
+        let value = Box::new(Expr::from(self.parse_expression()?));
+        let mut pos = target.1;
+        pos.span = pos.span.to(value.span);
+        let assignment = match op {
+            None => ExprValue::FieldAssign {
+                object,
+                field,
+                value,
+                struct_: String::new(),
+            },
+            Some(op) => ExprValue::FieldAugAssign {
+                object,
+                field,
+                op,
+                value,
+                struct_: String::new(),
+            },
+        };
+        Ok((assignment, pos))
+    }
 
-                TokenType::While => self.parse_while(),
+    /// Parse a single operand: a literal, identifier, call, block, prefix operation or any other
+    /// expression that doesn't start with a binary operator.
+    pub fn parse_primary(&mut self) -> Result<(ExprValue, NodePosition)> {
+        match unwrap_some!(self.tokens.peek()).type_ {
+            TokenType::LParen => self.parse_paren_expression(),
+            TokenType::LBrack => self.parse_array(),
+            ref op if op.is_unary_operator() => self.parse_unop(),
 
-                TokenType::Let => self.parse_declaration(),
+            TokenType::If => self.parse_if_else(),
 
-                TokenType::True => self.parse_true(),
+            TokenType::While => self.parse_while(),
 
-                TokenType::False => self.parse_false(),
+            TokenType::Let => self.parse_declaration(),
 
-                TokenType::Identifier(_) => self.parse_identifier(), // Parses identifiers, assignments and function calls as well
+            TokenType::True => self.parse_true(),
 
-                TokenType::Return => self.parse_return(),
+            TokenType::False => self.parse_false(),
 
-                TokenType::Use => self.parse_use(),
+            TokenType::Identifier(_) => self.parse_identifier(), // Parses identifiers, assignments and function calls as well
 
-                TokenType::Do => self.parse_do(),
+            TokenType::Return => self.parse_return(),
 
-                TokenType::Integer(i) => {
-                    self.advance();
-                    let nx = unwrap_some!(self.tokens.next());
-                    Ok((
-                        ExprValue::Integer(i),
-                        NodePosition {
-                            pos: nx.pos,
-                            line_no: nx.line_no,
-                            file: nx.file,
-                        },
-                    ))
-                }
+            TokenType::Use => self.parse_use(),
 
-                TokenType::Str(_) => self.parse_string(),
+            TokenType::Do => self.parse_do(),
 
-                _ => return Err(self.parser_error("Invalid expression")),
-            };
-        // The functions above will eat the value, then we can proceed to check for a bin op.
+            TokenType::Integer(i) => {
+                self.advance();
+                let nx = unwrap_some!(self.tokens.next());
+                Ok((ExprValue::Integer(i), self.position_from(&nx)))
+            }
+
+            TokenType::Str(_) => self.parse_string(),
+
+            _ => Err(
+                Diagnostic::error(ErrorCode::ExpectedExpression, "Invalid expression")
+                    .with_span(self.span),
+            ),
+        }
+    }
+
+    /// Precedence climbing over the binary operators following `lhs`.
+    ///
+    /// Only operators binding at least as tightly as `min_prec` are consumed, so the caller gets
+    /// back control as soon as a looser operator (or the end of the expression) shows up.
+    pub fn parse_binop_rhs(
+        &mut self,
+        min_prec: i32,
+        mut lhs: (ExprValue, NodePosition),
+    ) -> Result<(ExprValue, NodePosition)> {
         loop {
-            if unwrap_some!(self.tokens.peek()).type_ == TokenType::LBrack {
+            let op = unwrap_some!(self.tokens.peek()).type_.clone();
+            if op == TokenType::LBrack {
                 return self.parse_index();
             }
 
-            let op: TokenType = match unwrap_some!(self.tokens.peek()).type_ {
-                TokenType::Plus
-                | TokenType::Minus
-                | TokenType::Div
-                | TokenType::Mul
-                | TokenType::Less
-                | TokenType::Dot
-                | TokenType::LessEq
-                | TokenType::Greater
-                | TokenType::GreaterEq
-                | TokenType::Equal
-                | TokenType::NotEq => {
-                    self.advance();
-                    unwrap_some!(self.tokens.next()).type_
+            let prec = self.get_tok_precedence(&op);
+            if prec < 0 || prec < min_prec {
+                return Ok(lhs);
+            }
+            self.advance();
+            self.tokens.next(); // Eat the operator
+
+            let mut rhs = if op == TokenType::Dot {
+                self.parse_field_name()?
+            } else {
+                self.parse_primary()?
+            };
+            // Let tighter operators take the rhs. One of the same level is left for this loop, so
+            // operators group to the left.
+            loop {
+                let next = unwrap_some!(self.tokens.peek()).type_.clone();
+                if self.get_tok_precedence(&next) <= prec {
+                    break;
                 }
-                _ => return l_value,
+                rhs = self.parse_binop_rhs(prec + 1, rhs)?;
+            }
+
+            let mut pos = lhs.1.clone();
+            pos.span = pos.span.to(rhs.1.span);
+            lhs = match (op, rhs) {
+                (TokenType::Dot, (ExprValue::FnCall(method, args), _)) => (
+                    ExprValue::MethodCall {
+                        object: Box::new(Expr::from(lhs)),
+                        method,
+                        args,
+                        class: String::new(),
+                    },
+                    pos,
+                ),
+                (TokenType::Dot, (ExprValue::Identifier(field), _)) => (
+                    ExprValue::FieldAccess {
+                        object: Box::new(Expr::from(lhs)),
+                        field,
+                        struct_: String::new(),
+                    },
+                    pos,
+                ),
+                (op, rhs) => (
+                    ExprValue::BinOp(
+                        Box::new(Expr::from(lhs)),
+                        Box::new(op),
+                        Box::new(Expr::from(rhs)),
+                    ),
+                    pos,
+                ),
             };
-            let r_value = self.parse_expression();
+        }
+    }
+
+    /// Parse the field or method call after a `.`, which is never an assignment by itself.
+    fn parse_field_name(&mut self) -> Result<(ExprValue, NodePosition)> {
+        self.advance();
+        let nx = unwrap_some!(self.tokens.next());
+        let name = match &nx.type_ {
+            TokenType::Identifier(name) => name.clone(),
+            _ => return Err(self.parser_error("Expected a field name after '.'")),
+        };
+        if unwrap_some!(self.tokens.peek()).type_ == TokenType::LParen {
+            let args = self.parse_call_args()?;
+            return Ok((ExprValue::FnCall(name, args), self.position_from(&nx)));
+        }
+        Ok((ExprValue::Identifier(name), self.position_from(&nx)))
+    }
+
+    /// Parse the parenthesized arguments of a call.
+    fn parse_call_args(&mut self) -> Result<Vec<Expr>> {
+        self.advance();
+        self.tokens.next(); // Eat '('
+        let mut values = Vec::new();
+        if unwrap_some!(self.tokens.peek()).type_ == TokenType::RParen {
+            self.advance();
+            self.tokens.next(); // Eat ')'
+            return Ok(values);
+        }
+        loop {
+            values.push(Expr::from(self.parse_expression()?));
             match unwrap_some!(self.tokens.peek()).type_ {
-                TokenType::Plus
-                | TokenType::Minus
-                | TokenType::Div
-                | TokenType::Mul
-                | TokenType::Less
-                | TokenType::LessEq
-                | TokenType::Greater
-                | TokenType::GreaterEq
-                | TokenType::Equal
-                | TokenType::NotEq => continue, // Leave it at this stage, let the loop start with binop search again.
-                _ => {
-                    // println!("{:#?} {:?} {:#?}",l_value,op, r_value );
-                    return Ok((
-                        ExprValue::BinOp(
-                            Box::new(l_value.unwrap().0),
-                            Box::new(op),
-                            Box::new(r_value.unwrap().0),
-                        ),
-                        NodePosition {
-                            pos: self.pos,
-                            line_no: self.line_no,
-                            file: self.file.clone(),
-                        },
-                    ));
+                TokenType::Comma => {
+                    self.advance();
+                    self.tokens.next(); // Eat ','
                 }
-            };
+                TokenType::RParen => {
+                    self.advance();
+                    self.tokens.next(); // Eat ')'
+                    return Ok(values);
+                }
+                _ => return Err(self.parser_error("Expected ',' or ')' after argument")),
+            }
         }
     }
 
@@ -111,45 +212,37 @@ impl Parser {
         // Eat the operator while working.
         self.advance();
         let nx = unwrap_some!(self.tokens.next());
-        let start = NodePosition {
-            pos: nx.pos,
-            line_no: nx.line_no,
-            file: nx.file,
-        };
         self.advance();
-        let t = nx.type_;
-        let op = Box::new(t);
-        let expr = Box::new(self.parse_expression().unwrap().0);
-        Ok((ExprValue::UnOp(op, expr), start))
+        let op = Box::new(nx.type_.clone());
+        // The operand binds tighter than any binary operator except member access, so `-a * b`
+        // is `(-a) * b` while `-p.x` is `-(p.x)`.
+        let operand = self.parse_primary()?;
+        let expr = Box::new(Expr::from(
+            self.parse_binop_rhs(precedence::UNARY + 1, operand)?,
+        ));
+        Ok((ExprValue::UnOp(op, expr), self.position_from(&nx)))
     }
 
     pub fn parse_index(&mut self) -> Result<(ExprValue, NodePosition)> {
         self.advance();
         self.tokens.next(); // eat '['
-        let expr = self.parse_expression();
-        println!("expr {:?}", expr);
+        self.parse_expression()?;
         if unwrap_some!(self.tokens.next()).type_ != TokenType::RBrack {
             Err(self.parser_error("Missing closing ']'"))
         } else {
-            todo!()
+            Err(self.parser_error("Indexing is not supported yet"))
         }
     }
 
     pub fn parse_paren_expression(&mut self) -> Result<(ExprValue, NodePosition)> {
         trace!("Parsing paren expr");
-        let expr = self.parse_expression();
-        let expr = expr.unwrap().0;
+        self.advance();
+        let nx = unwrap_some!(self.tokens.next()); // Eat '('
+        let expr = self.parse_expression()?.0;
         if unwrap_some!(self.tokens.peek()).type_ == TokenType::RParen {
             self.advance();
-            let nx = unwrap_some!(self.tokens.next()); // Eat ')'
-            Ok((
-                expr,
-                NodePosition {
-                    pos: nx.pos,
-                    line_no: nx.line_no,
-                    file: nx.file,
-                },
-            ))
+            self.tokens.next(); // Eat ')'
+            Ok((expr, self.position_from(&nx)))
         } else {
             Err(self.parser_error("Missing closing ')'"))
         }
@@ -157,12 +250,7 @@ impl Parser {
 
     pub fn parse_array(&mut self) -> Result<(ExprValue, NodePosition)> {
         self.advance();
-        let pos = unwrap_some!(self.tokens.next());
-        let mut pos = NodePosition {
-            pos: pos.pos,
-            line_no: pos.line_no,
-            file: pos.file,
-        };
+        let nx = unwrap_some!(self.tokens.next()); // Eat '['
         let mut expressions = vec![];
 
         self.advance();
@@ -190,27 +278,17 @@ impl Parser {
                     self.tokens.next(); // Eat ']'
                     break;
                 }
-                let expr = self.parse_expression();
-                match expr {
-                    Ok((expr, p)) => {
-                        expressions.insert(expressions.len(), expr);
-                        pos = p;
-                    }
-                    Err(e) => {
-                        return Err(e);
-                    }
-                };
+                expressions.push(Expr::from(self.parse_expression()?));
             }
         }
-        Ok((ExprValue::Array(expressions, type_), pos))
+        Ok((ExprValue::Array(expressions, type_), self.position_from(&nx)))
     }
 
     pub fn parse_if_else(&mut self) -> Result<(ExprValue, NodePosition)> {
         trace!("Parsing if else");
         self.advance();
         let nx = unwrap_some!(self.tokens.next()); // Eat 'if'
-        let mut type_ = String::from("void");
-        let mut hastype = !true;
+        let type_ = String::from("void");
 
         // if unwrap_some!(self.tokens.peek()).type_ == TokenType::Colon {
         //     self.advance();
@@ -227,44 +305,36 @@ impl Parser {
         // }
 
 
-        let cond = Box::new(self.parse_expression().unwrap().0);
-        
-        if unwrap_some!(self.tokens.peek()).type_ == TokenType::Colon {
-            self.advance();
-            self.tokens.next(); // Eat ':'
-        }else {
-            return Err("expected ':'".to_string())
-        }
+        let cond = Box::new(Expr::from(self.parse_expression()?));
+        self.parse_branch_start()?;
 
-        let mut expression_if = Box::new(self.parse_expression().unwrap().0);
+        let expression_if = Box::new(Expr::from(self.parse_expression()?));
 
         if unwrap_some!(self.tokens.peek()).type_ == TokenType::Else {
             self.advance();
             self.tokens.next(); // Eat 'else'
         } else {
+            let else_ = Box::new(Expr {
+                value: ExprValue::Integer(0),
+                span: expression_if.span.shrink_to_end(),
+            });
             return Ok((
                 ExprValue::IfElse {
                     cond,
                     if_: expression_if,
-                    else_: Box::new(ExprValue::Integer(0)),
+                    else_,
                     type_,
                 },
-                NodePosition {
-                    pos: nx.pos,
-                    line_no: nx.line_no,
-                    file: nx.file,
-                },
+                self.position_from(&nx),
             ));
         }
 
-        if unwrap_some!(self.tokens.peek()).type_ == TokenType::Colon {
-            self.advance();
-            self.tokens.next(); // Eat ':'
-        }else{
-            return Err("expected ':'".to_string())
+        // `else if ...` chains without a `:`.
+        if unwrap_some!(self.tokens.peek()).type_ != TokenType::If {
+            self.parse_branch_start()?;
         }
 
-        let mut expression_else = Box::new(self.parse_expression().unwrap().0);
+        let expression_else = Box::new(Expr::from(self.parse_expression()?));
 
 
         Ok((
@@ -274,70 +344,101 @@ impl Parser {
                 else_: expression_else,
                 type_,
             },
-            NodePosition {
-                pos: nx.pos,
-                line_no: nx.line_no,
-                file: nx.file,
-            },
+            self.position_from(&nx),
         ))
     }
 
-    pub fn parse_do(&mut self) -> Result<(ExprValue, NodePosition)>{
-       self.advance();
-       let nx = unwrap_some!(self.tokens.next()); // Eat 'do'
-       let mut expressions = vec![];
-       loop {
-           match self.parse_expression() {
-               Ok((expr, _)) => expressions.insert(expressions.len(), expr),
-               Err(e) if e == self.parser_error("Invalid expression") => {
-                   if unwrap_some!(self.tokens.peek()).type_ == TokenType::End
-                       || unwrap_some!(self.tokens.peek()).type_ == TokenType::Semicolon
-                   {
-                       break;
-                   } else {
-                       return Err(e);
-                   }
-               }
-               Err(e) => return Err(e),
-           }
-           // Eat the semicolons
-           match unwrap_some!(self.tokens.peek()).type_ {
-               TokenType::Semicolon => {
-                   self.advance();
-                   self.tokens.next();
-                   continue;
-               }
-               TokenType::End => break,
-               _ => continue,
-           }
-       }
-
-       if unwrap_some!(self.tokens.peek()).type_ == TokenType::End {
-           self.advance();
-           self.tokens.next(); // Eat 'end'
-        } // No other case
-        return Ok((
-            ExprValue::Do(expressions),
-            NodePosition {
-                pos: nx.pos,
-                line_no: nx.line_no,
-                file: nx.file,
-            },
-        ))
+    /// Eat the `:` introducing a branch of an `if`. It may be left out before a `do` block.
+    fn parse_branch_start(&mut self) -> Result<()> {
+        match unwrap_some!(self.tokens.peek()).type_ {
+            TokenType::Colon => {
+                self.advance();
+                self.tokens.next(); // Eat ':'
+                Ok(())
+            }
+            TokenType::Do => Ok(()),
+            _ => Err(self.parser_error("expected ':' or 'do'")),
+        }
+    }
+
+    pub fn parse_do(&mut self) -> Result<(ExprValue, NodePosition)> {
+        self.advance();
+        let nx = unwrap_some!(self.tokens.next()); // Eat 'do'
+        self.symtab.enter_scope(ScopeKind::Block, nx.span.start);
+        let expressions = self.parse_block_body()?;
+        self.advance();
+        self.tokens.next(); // Eat 'end'
+        self.symtab.exit_scope();
+        Ok((ExprValue::Do(expressions), self.position_from(&nx)))
+    }
+
+    /// Parse the expressions of a `do ... end` block up to, but not including, the `end`.
+    ///
+    /// An expression that fails to parse is recorded as an error, replaced by
+    /// [`ExprValue::Error`] and skipped, so the rest of the block is still checked.
+    pub fn parse_block_body(&mut self) -> Result<Vec<Expr>> {
+        let mut expressions = vec![];
+        loop {
+            match unwrap_some!(self.tokens.peek()).type_ {
+                TokenType::End => break,
+                TokenType::Semicolon => {
+                    self.advance();
+                    self.tokens.next(); // Eat the stray ';'
+                    continue;
+                }
+                ref t if Self::is_item_start(t) => {
+                    return Err(self.parser_error("expected 'end'"));
+                }
+                _ => {}
+            }
+            let start = unwrap_some!(self.tokens.peek()).span;
+            let scope = self.symtab.current_scope();
+            match self.parse_expression() {
+                Ok(expr) => expressions.push(Expr::from(expr)),
+                Err(e) if e.is_unexpected_eof() => return Err(e),
+                Err(e) => {
+                    // The expression may have failed inside a nested block.
+                    self.symtab.restore(scope);
+                    self.recover(e);
+                    expressions.push(Expr {
+                        value: ExprValue::Error,
+                        span: start.to(self.tokens.last),
+                    });
+                    continue;
+                }
+            }
+            // Eat the semicolons
+            let after_end = self.tokens.after_end;
+            match unwrap_some!(self.tokens.peek()).type_ {
+                TokenType::Semicolon => {
+                    self.advance();
+                    self.tokens.next();
+                }
+                TokenType::End => break,
+                // Like `do ... end`, a block ends its expression clearly enough.
+                _ if after_end => {}
+                // Nothing is skipped, as the next expression most likely starts right here.
+                _ => self.errors.push(
+                    Diagnostic::error(ErrorCode::Syntax, "Expected ';' after expression")
+                        .with_span(self.tokens.last),
+                ),
+            }
+        }
+        Ok(expressions)
     }
 
     pub fn parse_while(&mut self) -> Result<(ExprValue, NodePosition)> {
         self.advance();
         let nx = unwrap_some!(self.tokens.next()); // Eat 'while'
-        let condition = self.parse_expression().unwrap().0;
+        let condition = Expr::from(self.parse_expression()?);
+        self.symtab
+            .enter_scope(ScopeKind::Loop, unwrap_some!(self.tokens.peek()).span.start);
+        let body = Expr::from(self.parse_expression()?);
+        self.symtab.exit_scope();
 
         Ok((
-            ExprValue::While(Box::new(condition), Box::new(self.parse_expression().unwrap().0)),
-            NodePosition {
-                pos: nx.pos,
-                line_no: nx.line_no,
-                file: nx.file,
-            },
+            ExprValue::While(Box::new(condition), Box::new(body)),
+            self.position_from(&nx),
         ))
     }
 
@@ -363,36 +464,27 @@ impl Parser {
         if unwrap_some!(self.tokens.peek()).type_ == TokenType::Assign {
             self.advance();
             self.tokens.next(); // Eat '='
-            let val = self.parse_expression();
-            match val {
-                Ok((v, pos)) => {
-                    return Ok((
-                        ExprValue::VarDecl {
-                            name,
-                            type_,
-                            value: Some(Box::new(v)),
-                        },
-                        NodePosition {
-                            pos: nx.pos,
-                            line_no: nx.line_no,
-                            file: nx.file,
-                        },
-                    ))
-                }
-                Err(e) => return Err(e),
-            }
+            let value = Expr::from(self.parse_expression()?);
+            let pos = self.position_from(&nx);
+            self.declare(Symbol::new(&name, SymbolKind::Variable, &type_, pos.span));
+            Ok((
+                ExprValue::VarDecl {
+                    name,
+                    type_,
+                    value: Some(Box::new(value)),
+                },
+                pos,
+            ))
         } else {
+            let pos = self.position_from(&nx);
+            self.declare(Symbol::new(&name, SymbolKind::Variable, &type_, pos.span));
             Ok((
                 ExprValue::VarDecl {
                     name,
                     type_,
                     value: None,
                 },
-                NodePosition {
-                    pos: nx.pos,
-                    line_no: nx.line_no,
-                    file: nx.file,
-                },
+                pos,
             ))
         }
     }
@@ -400,40 +492,21 @@ impl Parser {
     pub fn parse_true(&mut self) -> Result<(ExprValue, NodePosition)> {
         self.advance();
         let nx = unwrap_some!(self.tokens.next()); // Eat `true`
-        Ok((
-            ExprValue::Boolean(true),
-            NodePosition {
-                pos: nx.pos,
-                line_no: nx.line_no,
-                file: nx.file,
-            },
-        ))
+        Ok((ExprValue::Boolean(true), self.position_from(&nx)))
     }
 
     pub fn parse_false(&mut self) -> Result<(ExprValue, NodePosition)> {
         self.advance();
         let nx = unwrap_some!(self.tokens.next()); // Eat `false`
-        Ok((
-            ExprValue::Boolean(false),
-            NodePosition {
-                pos: nx.pos,
-                line_no: nx.line_no,
-                file: nx.file,
-            },
-        ))
+        Ok((ExprValue::Boolean(false), self.position_from(&nx)))
     }
 
     pub fn parse_identifier(&mut self) -> Result<(ExprValue, NodePosition)> {
         self.advance();
         // Eat the identifier and work.
         let nx = unwrap_some!(self.tokens.next());
-        let start = NodePosition {
-            pos: nx.pos,
-            line_no: nx.line_no,
-            file: nx.file,
-        };
-        let name = match nx.type_ {
-            TokenType::Identifier(n) => n,
+        let name = match &nx.type_ {
+            TokenType::Identifier(n) => n.clone(),
             _ => unreachable!(),
         };
         // Check for assignment
@@ -441,90 +514,55 @@ impl Parser {
             TokenType::Assign => {
                 self.advance();
                 self.tokens.next(); // Eat '='
-                let value = Box::new(self.parse_expression().unwrap().0);
-                return Ok((ExprValue::Assign { name, value }, start));
+                let value = Box::new(Expr::from(self.parse_expression()?));
+                return Ok((ExprValue::Assign { name, value }, self.position_from(&nx)));
             }
             TokenType::PlusEq => {
                 self.advance();
                 let op = Box::new(unwrap_some!(self.tokens.next()).type_); // Eat '+='
-                let value = Box::new(self.parse_expression().unwrap().0);
-                return Ok((ExprValue::AugAssign { name, op, value }, start));
+                let value = Box::new(Expr::from(self.parse_expression()?));
+                return Ok((ExprValue::AugAssign { name, op, value }, self.position_from(&nx)));
             }
             TokenType::MinusEq => {
                 self.advance();
                 let op = Box::new(unwrap_some!(self.tokens.next()).type_); // Eat '-='
-                let value = Box::new(self.parse_expression().unwrap().0);
-                return Ok((ExprValue::AugAssign { name, op, value }, start));
+                let value = Box::new(Expr::from(self.parse_expression()?));
+                return Ok((ExprValue::AugAssign { name, op, value }, self.position_from(&nx)));
             }
             TokenType::DivEq => {
                 self.advance();
                 let op = Box::new(unwrap_some!(self.tokens.next()).type_); // Eat '/='
-                let value = Box::new(self.parse_expression().unwrap().0);
-                return Ok((ExprValue::AugAssign { name, op, value }, start));
+                let value = Box::new(Expr::from(self.parse_expression()?));
+                return Ok((ExprValue::AugAssign { name, op, value }, self.position_from(&nx)));
             }
             TokenType::MulEq => {
                 self.advance();
                 let op = Box::new(unwrap_some!(self.tokens.next()).type_); // Eat '*='
-                let value = Box::new(self.parse_expression().unwrap().0);
-                return Ok((ExprValue::AugAssign { name, op, value }, start));
+                let value = Box::new(Expr::from(self.parse_expression()?));
+                return Ok((ExprValue::AugAssign { name, op, value }, self.position_from(&nx)));
             }
             _ => {}
         }
         // Check for function call
         if unwrap_some!(self.tokens.peek()).type_ == TokenType::LParen {
-            self.advance();
-            self.tokens.next(); // Eat '('
-            let mut values = Vec::new();
-            loop {
-                match self.parse_expression() {
-                    Ok((expr, _)) => values.insert(values.len(), expr),
-                    Err(e) => {
-                        if unwrap_some!(self.tokens.peek()).type_ == TokenType::Comma {
-                            break;
-                        } else if unwrap_some!(self.tokens.peek()).type_ == TokenType::RParen {
-                            self.advance();
-                            self.tokens.next(); // Eat ')'
-                            return Ok((ExprValue::FnCall(name, values), start));
-                        } else {
-                            return Err(e);
-                        }
-                    }
-                }
-                if unwrap_some!(self.tokens.peek()).type_ == TokenType::Comma {
-                    self.advance();
-                    self.tokens.next(); // Eat ','
-                }
-            }
+            let values = self.parse_call_args()?;
+            return Ok((ExprValue::FnCall(name, values), self.position_from(&nx)));
         }
-        Ok((ExprValue::Identifier(name), start))
+        Ok((ExprValue::Identifier(name), self.position_from(&nx)))
     }
 
     pub fn parse_return(&mut self) -> Result<(ExprValue, NodePosition)> {
         self.advance();
         let nx = unwrap_some!(self.tokens.next()); // Eat `return`
-        let expr = self.parse_expression().unwrap().0;
-        Ok((
-            ExprValue::Return(Box::new(expr)),
-            NodePosition {
-                pos: nx.pos,
-                line_no: nx.line_no,
-                file: nx.file,
-            },
-        ))
+        let expr = Expr::from(self.parse_expression()?);
+        Ok((ExprValue::Return(Box::new(expr)), self.position_from(&nx)))
     }
 
     pub fn parse_string(&mut self) -> Result<(ExprValue, NodePosition)> {
         self.advance();
         let nx = unwrap_some!(self.tokens.next());
-        match nx.type_ {
-            TokenType::Str(s) => Ok((
-                ExprValue::Str(s),
-                NodePosition {
-                    pos: nx.pos,
-                    line_no: nx.line_no,
-                    file: nx.file,
-                },
-            )),
+        match &nx.type_ {
+            TokenType::Str(s) => Ok((ExprValue::Str(s.clone()), self.position_from(&nx))),
             _ => unreachable!(),
         }
     }
@@ -534,15 +572,141 @@ impl Parser {
         let nx = unwrap_some!(self.tokens.next()); // Eat `use`
         self.advance();
         match unwrap_some!(self.tokens.next()).type_ {
-            TokenType::Str(s) => Ok((
-                ExprValue::Use(s.to_string()),
-                NodePosition {
-                    pos: nx.pos,
-                    line_no: nx.line_no,
-                    file: nx.file,
-                },
-            )),
+            TokenType::Str(s) => Ok((ExprValue::Use(s), self.position_from(&nx))),
             _ => Err(self.parser_error("Invalid 'use' expression")),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::lexer::Lexer;
+    use crate::parser::{ExprValue, Parser};
+    use crate::source::SourceMap;
+
+    /// Parse `text` as a single expression and print it fully parenthesized.
+    fn parenthesize(text: &str) -> String {
+        fn show(expr: &ExprValue) -> String {
+            match expr {
+                ExprValue::BinOp(l, op, r) => {
+                    format!("({} {:?} {})", show(&l.value), op, show(&r.value))
+                }
+                ExprValue::FieldAccess { object, field, .. } => {
+                    format!("({} Dot {})", show(&object.value), field)
+                }
+                ExprValue::UnOp(op, e) => format!("({:?} {})", op, show(&e.value)),
+                ExprValue::Integer(i) => i.to_string(),
+                ExprValue::Identifier(n) => n.clone(),
+                ExprValue::While(cond, body) => {
+                    format!("(while {} {})", show(&cond.value), show(&body.value))
+                }
+                ExprValue::IfElse { cond, if_, else_, .. } => format!(
+                    "(if {} {} {})",
+                    show(&cond.value),
+                    show(&if_.value),
+                    show(&else_.value)
+                ),
+                ExprValue::Do(exprs) => format!("do {}", exprs.len()),
+                other => format!("{:?}", other),
+            }
+        }
+        let tokens = Lexer::from_text(&SourceMap::new(), text, "<test>")
+            .map(|t| t.unwrap())
+            .collect::<Vec<_>>();
+        let mut parser = Parser::new(tokens.into_iter().peekable());
+        show(&parser.parse_expression().unwrap().0)
+    }
+
+    #[test]
+    fn binary_operators_are_left_associative() {
+        assert_eq!(parenthesize("1 - 2 - 3;"), "((1 Minus 2) Minus 3)");
+        assert_eq!(parenthesize("8 / 4 / 2;"), "((8 Div 4) Div 2)");
+    }
+
+    #[test]
+    fn binary_operators_respect_precedence() {
+        assert_eq!(parenthesize("a * b + c;"), "((a Mul b) Plus c)");
+        assert_eq!(parenthesize("a + b * c;"), "(a Plus (b Mul c))");
+        assert_eq!(
+            parenthesize("a + 1 <= b * 2;"),
+            "((a Plus 1) LessEq (b Mul 2))"
+        );
+        assert_eq!(parenthesize("a > b;"), "(a Greater b)");
+    }
+
+    #[test]
+    fn unary_binds_tighter_than_binary_but_looser_than_member() {
+        assert_eq!(parenthesize("-a * b;"), "((Minus a) Mul b)");
+        assert_eq!(parenthesize("-p.x;"), "(Minus (p Dot x))");
+        assert_eq!(parenthesize("a.b.c + 1;"), "(((a Dot b) Dot c) Plus 1)");
+    }
+
+    #[test]
+    fn fields_are_assigned_to() {
+        let show = |text: &str| {
+            let tokens = Lexer::from_text(&SourceMap::new(), text, "<test>")
+                .map(|t| t.unwrap())
+                .collect::<Vec<_>>();
+            let mut parser = Parser::new(tokens.into_iter().peekable());
+            match parser.parse_expression().map(|(value, _)| value) {
+                Ok(ExprValue::FieldAssign { object, field, .. }) => {
+                    format!("{:?}.{} =", object.value, field)
+                }
+                Ok(ExprValue::FieldAugAssign {
+                    object, field, op, ..
+                }) => format!("{:?}.{} {:?}", object.value, field, op),
+                Ok(other) => format!("{:?}", other),
+                Err(e) => e.message,
+            }
+        };
+        assert_eq!(show("p.x = 5;"), "Identifier(\"p\").x =");
+        assert_eq!(show("p.x += 1;"), "Identifier(\"p\").x PlusEq");
+        assert!(show("a.b.c = 1 + 2;").ends_with(".c ="));
+        assert_eq!(
+            show("f() = 1;"),
+            "Only variables and fields can be assigned to"
+        );
+    }
+
+    #[test]
+    fn nodes_span_everything_they_were_parsed_from() {
+        let text = "foo(1, -x) + (2 * y);";
+        let tokens = Lexer::from_text(&SourceMap::new(), text, "<test>")
+            .map(|t| t.unwrap())
+            .collect::<Vec<_>>();
+        let mut parser = Parser::new(tokens.into_iter().peekable());
+        let (value, pos) = parser.parse_expression().unwrap();
+        assert_eq!(&text[pos.span.start..pos.span.end], "foo(1, -x) + (2 * y)");
+        match value {
+            ExprValue::BinOp(lhs, _, rhs) => {
+                assert_eq!(&text[lhs.span.start..lhs.span.end], "foo(1, -x)");
+                assert_eq!(&text[rhs.span.start..rhs.span.end], "(2 * y)");
+                match lhs.value {
+                    ExprValue::FnCall(_, args) => {
+                        assert_eq!(&text[args[1].span.start..args[1].span.end], "-x")
+                    }
+                    other => panic!("expected a call, got {:?}", other),
+                }
+            }
+            other => panic!("expected a binary operation, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn while_takes_a_condition_and_a_body() {
+        assert_eq!(
+            parenthesize("while i < n do i = i + 1; end;"),
+            "(while (i Less n) do 1)"
+        );
+    }
+
+    #[test]
+    fn if_branches_are_introduced_by_a_colon_or_a_block() {
+        assert_eq!(parenthesize("if a: 1 else: 2;"), "(if a 1 2)");
+        assert_eq!(
+            parenthesize("if a do 1 end else if b do 2; 3 end else do end;"),
+            "(if a do 1 (if b do 2 do 0))"
+        );
+        assert_eq!(parenthesize("if a: 1;"), "(if a 1 0)");
+    }
+}
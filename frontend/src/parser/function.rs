@@ -1,16 +1,17 @@
 use crate::lexer::tokens::{Token, TokenType};
-use crate::parser::{Args, ExprValue, External, Function, NodePosition, Parser};
-use crate::{unwrap_some, Result, Symbol};
+use crate::parser::{Args, Expr, External, Function, NodePosition, Parser};
+use crate::source::Span;
+use crate::symbol_table::{ScopeKind, Symbol, SymbolKind};
+use crate::{unwrap_some, Result};
 
 impl Parser {
-    fn parse_type_annot(&mut self) -> Result<(String, String)> {
+    /// Parse `name: type`, returning both and the span they cover.
+    pub(super) fn parse_type_annot(&mut self) -> Result<(String, String, Span)> {
         // Check if Identifier exists, else return Err
         match unwrap_some!(self.tokens.peek()) {
             Token {
                 type_: TokenType::Identifier(_),
-                pos: _,
-                line_no: _,
-                file: _,
+                ..
             } => {}
             _ => {
                 println!("{:?}", self.tokens.peek());
@@ -19,7 +20,9 @@ impl Parser {
         }
         // Store identifier.
         self.advance();
-        let name = match unwrap_some!(self.tokens.next()).type_ {
+        let nx = unwrap_some!(self.tokens.next());
+        let start = nx.span;
+        let name = match nx.type_ {
             TokenType::Identifier(s) => s,
             _ => unreachable!(),
         };
@@ -27,11 +30,9 @@ impl Parser {
         match unwrap_some!(self.tokens.peek()) {
             Token {
                 type_: TokenType::Colon,
-                pos: _,
-                line_no: _,
-                file: _,
+                ..
             } => {}
-            _ => return Err("expected ':' .".to_string()),
+            _ => return Err(self.parser_error("expected ':'")),
         }
         self.advance();
         self.tokens.next(); // Eat ':'
@@ -39,11 +40,9 @@ impl Parser {
         match unwrap_some!(self.tokens.peek()) {
             Token {
                 type_: TokenType::Identifier(_),
-                pos: _,
-                line_no: _,
-                file: _,
+                ..
             } => {}
-            _ => return Err("expected Identifier.".to_string()),
+            _ => return Err(self.parser_error("expected Identifier")),
         }
         self.advance();
         // Store type
@@ -51,29 +50,23 @@ impl Parser {
             TokenType::Identifier(s) => s,
             _ => unreachable!(),
         };
-        Ok((name, type_))
+        Ok((name, type_, start.to(self.tokens.last)))
     }
 
     pub fn parse_extern(&mut self) -> Result<(External, NodePosition)> {
         let mut args = Args {
             name: vec![],
             type_: vec![],
+            span: vec![],
         };
 
         self.advance();
         let nx = unwrap_some!(self.tokens.next()); // Eat extern
-        let start = NodePosition {
-            pos: nx.pos,
-            line_no: nx.line_no,
-            file: nx.file.to_string(),
-        };
 
         match unwrap_some!(self.tokens.peek()) {
             Token {
                 type_: TokenType::Identifier(_),
-                pos: _,
-                line_no: _,
-                file: _,
+                ..
             } => {}
             _ => return Err(self.parser_error("Expected Identifier after keyword 'extern'")),
         }
@@ -107,9 +100,10 @@ impl Parser {
                 }
                 let type_annot = self.parse_type_annot();
                 match type_annot {
-                    Ok((n, t)) => {
+                    Ok((n, t, span)) => {
                         args.name.insert(args.name.len(), n);
                         args.type_.insert(args.type_.len(), t);
+                        args.span.insert(args.span.len(), span);
                     }
                     Err(e) => {
                         return Err(e);
@@ -137,49 +131,48 @@ impl Parser {
         } else {
             return Err(self.parser_error("Semicolon after extern is mandatory."));
         }
-        self.symtab.insert(
-            name.clone(),
-            Symbol::new(return_type.clone(), self.current_scope.clone()),
-        );
+        let pos = self.position_from(&nx);
+        self.declare(Symbol::new(&name, SymbolKind::Extern, &return_type, pos.span));
         Ok((
             External {
                 name,
                 args,
                 return_type,
             },
-            start,
+            pos,
         ))
     } // end of parse_extern
 
     pub fn parse_function(&mut self) -> Result<(Function, NodePosition)> {
+        self.parse_def(None)
+    }
+
+    /// Parse a method of `class`, which gets the implicit parameter `self` before its own.
+    pub fn parse_method(&mut self, class: &str) -> Result<(Function, NodePosition)> {
+        self.parse_def(Some(class))
+    }
+
+    fn parse_def(&mut self, class: Option<&str>) -> Result<(Function, NodePosition)> {
         let name: String;
         let return_type: String;
         let mut args = Args {
             name: vec![],
             type_: vec![],
+            span: vec![],
         };
-        let mut expressions: Vec<ExprValue> = Vec::new();
+        let expressions: Vec<Expr>;
         match self.tokens.peek() {
             Some(Token {
                 type_: TokenType::Def,
-                pos,
-                line_no,
-                file,
+                ..
             }) => {
-                let start = NodePosition {
-                    pos: *pos,
-                    line_no: *line_no,
-                    file: file.to_string(),
-                };
                 self.advance();
-                self.tokens.next(); // Eat Def
+                let nx = unwrap_some!(self.tokens.next()); // Eat Def
 
                 match unwrap_some!(self.tokens.peek()) {
                     Token {
                         type_: TokenType::Identifier(_),
-                        pos: _,
-                        line_no: _,
-                        file: _,
+                        ..
                     } => {}
                     _ => return Err(self.parser_error("Expected Identifier after keyword 'def'")),
                 }
@@ -189,7 +182,16 @@ impl Parser {
                     TokenType::Identifier(n) => name = n, // Always matches
                     _ => unreachable!(),                  // never happens
                 }
-                self.current_scope = format!("{}.{}", self.current_scope, name.clone());
+                self.symtab.enter_scope(ScopeKind::Function, nx.span.start);
+
+                if let Some(class) = class {
+                    // Declared at the method's name, as it isn't written anywhere.
+                    let span = self.tokens.last;
+                    self.declare(Symbol::new("self", SymbolKind::Parameter, class, span));
+                    args.name.push(String::from("self"));
+                    args.type_.push(class.to_string());
+                    args.span.push(span);
+                }
 
                 if unwrap_some!(self.tokens.peek()).type_ != TokenType::LParen {
                     return Err(self.parser_error("Expected '(' after Identifier"));
@@ -213,9 +215,13 @@ impl Parser {
                         }
                         let type_annot = self.parse_type_annot();
                         match type_annot {
-                            Ok((n, t)) => {
+                            Ok((n, t, span)) => {
+                                if n != "_" {
+                                    self.declare(Symbol::new(&n, SymbolKind::Parameter, &t, span));
+                                }
                                 args.name.push(n);
                                 args.type_.push(t);
+                                args.span.push(span);
                             }
                             Err(e) => {
                                 return Err(e);
@@ -243,38 +249,9 @@ impl Parser {
                 self.advance();
                 self.tokens.next(); // Eat 'do'
 
-                loop {
-                    match self.parse_expression() {
-                        Ok(expr) => expressions.insert(expressions.len(), expr.0),
-                        Err(e) if e == self.parser_error("Invalid expression") => {
-                            if unwrap_some!(self.tokens.peek()).type_ == TokenType::End
-                                || unwrap_some!(self.tokens.peek()).type_ == TokenType::Semicolon
-                            {
-                                break;
-                            } else {
-                                return Err(e);
-                            }
-                        }
-                        Err(e) => return Err(e),
-                    }
-                    // Eat the semicolons
-                    match unwrap_some!(self.tokens.peek()).type_ {
-                        TokenType::Semicolon => {
-                            self.advance();
-                            self.tokens.next();
-                            continue;
-                        }
-                        TokenType::End => break,
-                        _ => {
-                            continue;
-                            // print!("{:?}", self.tokens.peek());
-                            // return Err(self.parser_error("Expected semicolon or 'end'"));
-                        }
-                    }
-                }
+                expressions = self.parse_block_body()?;
 
                 if unwrap_some!(self.tokens.peek()).type_ != TokenType::End {
-                    print!("{:?}", unwrap_some!(self.tokens.peek()).type_);
                     return Err(self.parser_error("expected 'end'"));
                 }
                 self.advance();
@@ -287,11 +264,9 @@ impl Parser {
                     }
                     _ => {}
                 }
-                self.current_scope = "global".to_string();
-                self.symtab.insert(
-                    name.clone(),
-                    Symbol::new(return_type.clone(), self.current_scope.clone()),
-                );
+                self.symtab.exit_scope();
+                let pos = self.position_from(&nx);
+                self.declare(Symbol::new(&name, SymbolKind::Function, &return_type, pos.span));
                 Ok((
                     Function {
                         name,
@@ -299,10 +274,10 @@ impl Parser {
                         expressions,
                         return_type,
                     },
-                    start,
+                    pos,
                 ))
             }
-            _ => Err("PASS".to_string()), // never happens
+            _ => Err(self.parser_error("Expected keyword 'def'")), // never happens
         }
     }
 }
@@ -1,15 +1,11 @@
+use crate::diagnostic::{Diagnostic, ErrorCode};
 use crate::lexer::tokens::{Token, TokenType};
-use crate::SymbolTable;
+use crate::source::Span;
+use crate::symbol_table::{Symbol, SymbolTable};
 
-use std::collections::HashMap;
-use std::fs::read_to_string;
-use std::io::BufRead;
 use std::iter::Peekable;
-
 use std::vec::IntoIter;
 
-use owo_colors::OwoColorize;
-
 pub mod class;
 pub mod expression;
 pub mod function;
@@ -17,6 +13,34 @@ pub mod program;
 
 type TokenIter = Peekable<IntoIter<Token>>;
 
+/// The parser's view of the token stream, remembering where the last consumed token ended so
+/// nodes can be given a span covering everything they were parsed from.
+struct TokenStream {
+    tokens: TokenIter,
+    /// Span of the most recently consumed token.
+    last: Span,
+    /// Whether the most recently consumed token was an `end`, after which an expression in a
+    /// block needs no `;`.
+    after_end: bool,
+}
+
+impl TokenStream {
+    fn peek(&mut self) -> Option<&Token> {
+        self.tokens.peek()
+    }
+}
+
+impl Iterator for TokenStream {
+    type Item = Token;
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.next()?;
+        self.last = token.span;
+        self.after_end = token.type_ == TokenType::End;
+        Some(token)
+    }
+}
+
 // #[derive(Debug)]
 // pub struct Node{
 //     pub ast_node: AstNode,
@@ -29,7 +53,35 @@ type TokenIter = Peekable<IntoIter<Token>>;
 pub struct NodePosition {
     pub pos: i32,
     pub line_no: i32,
-    pub file: String,
+    /// Everything the node was parsed from.
+    pub span: Span,
+}
+
+/// A struct member's name and type.
+#[derive(Debug, Clone)]
+pub struct StructMember {
+    pub name: String,
+    pub type_: String,
+    pub span: Span,
+}
+
+/// Struct members in the order they are declared, which is also the order they are laid out in.
+pub type StructMembers = Vec<StructMember>;
+
+/// How a struct's fields are laid out, as written in `struct Name repr(...) { ... }`.
+///
+/// Fields are always laid out in the order they are declared, each at the next offset its type's
+/// alignment allows.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Repr {
+    /// `repr(C)`: the struct is laid out like the same fields in C, so `extern` functions may
+    /// take and return it.
+    pub c: bool,
+    /// `repr(packed)`: there is no padding between fields, which may then be misaligned.
+    pub packed: bool,
+    /// `repr(align(n))`: the struct is aligned to at least `n` bytes, and its size is a multiple
+    /// of `n`.
+    pub align: Option<u32>,
 }
 
 //the top-level
@@ -38,15 +90,33 @@ pub enum AstNode {
     Extern(External),
     FunctionDef(Function),
     Class(Class),
-    Struct(String, HashMap<String, (String, i32)>),
+    Struct(String, StructMembers, Repr),
     Expression(ExprValue),
+    /// A top-level item that failed to parse. The error itself is in the parser's diagnostics.
+    Error,
+}
+
+/// An expression together with the source it was parsed from.
+#[derive(Debug, Clone)]
+pub struct Expr {
+    pub value: ExprValue,
+    pub span: Span,
+}
+
+impl From<(ExprValue, NodePosition)> for Expr {
+    fn from((value, pos): (ExprValue, NodePosition)) -> Self {
+        Expr {
+            value,
+            span: pos.span,
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
 pub enum ExprValue {
-    FnCall(String, Vec<ExprValue>),
-    UnOp(Box<TokenType>, Box<ExprValue>),
-    BinOp(Box<ExprValue>, Box<TokenType>, Box<ExprValue>),
+    FnCall(String, Vec<Expr>),
+    UnOp(Box<TokenType>, Box<Expr>),
+    BinOp(Box<Expr>, Box<TokenType>, Box<Expr>),
     Boolean(bool),
     Integer(i32),
     Str(String),
@@ -54,29 +124,63 @@ pub enum ExprValue {
     VarDecl {
         name: String,
         type_: String,
-        value: Option<Box<ExprValue>>,
+        value: Option<Box<Expr>>,
     },
     IfElse {
-        cond: Box<ExprValue>,
-        if_: Box<ExprValue>,
-        else_: Box<ExprValue>,
+        cond: Box<Expr>,
+        if_: Box<Expr>,
+        else_: Box<Expr>,
         type_: String,
     },
     Assign {
         name: String,
-        value: Box<ExprValue>,
+        value: Box<Expr>,
     },
     AugAssign {
         name: String,
         op: Box<TokenType>,
-        value: Box<ExprValue>,
+        value: Box<Expr>,
+    },
+    /// `object.field`
+    FieldAccess {
+        object: Box<Expr>,
+        field: String,
+        /// The struct or class `object` is an instance of, filled in by the checker.
+        struct_: String,
+    },
+    /// `object.field = value`
+    FieldAssign {
+        object: Box<Expr>,
+        field: String,
+        value: Box<Expr>,
+        /// The struct or class `object` is an instance of, filled in by the checker.
+        struct_: String,
+    },
+    /// `object.field op= value`
+    FieldAugAssign {
+        object: Box<Expr>,
+        field: String,
+        op: Box<TokenType>,
+        value: Box<Expr>,
+        /// The struct or class `object` is an instance of, filled in by the checker.
+        struct_: String,
+    },
+    /// `object.method(args)`
+    MethodCall {
+        object: Box<Expr>,
+        method: String,
+        args: Vec<Expr>,
+        /// The class `object` is an instance of, filled in by the checker.
+        class: String,
     },
-    Return(Box<ExprValue>),
+    Return(Box<Expr>),
     Use(String),
     // Walrus(Box<ExprValue>, String, Box<ExprValue>),
-    While(Box<ExprValue>, Box<ExprValue>),
-    Array(Vec<ExprValue>, String),
-    Do(Vec<ExprValue>),
+    While(Box<Expr>, Box<Expr>),
+    Array(Vec<Expr>, String),
+    Do(Vec<Expr>),
+    /// An expression that failed to parse. The error itself is in the parser's diagnostics.
+    Error,
 }
 
 // 'extern' name (args) '->' return_type
@@ -92,17 +196,30 @@ pub struct External {
 pub struct Function {
     pub name: String,
     pub args: Args,
-    pub expressions: Vec<ExprValue>,
+    pub expressions: Vec<Expr>,
     pub return_type: String,
 }
 
-// 'class' name {functions}
+// 'class' name {fields functions}
 #[derive(Debug)]
 pub struct Class {
     pub name: String,
+    /// Fields, laid out like the members of a struct.
+    pub fields: StructMembers,
+    /// Methods, whose first parameter is the implicit `self`.
     pub fns: Vec<(Function, NodePosition)>,
 }
 
+impl Class {
+    /// The name `method` of the class `class` is generated under.
+    ///
+    /// It can't be written in skipp, so it never clashes with a function or another class's
+    /// method.
+    pub fn mangle(class: &str, method: &str) -> String {
+        format!("{}.{}", class, method)
+    }
+}
+
 #[derive(Debug)]
 pub struct Module {
     pub name: String,
@@ -112,81 +229,115 @@ pub struct Module {
 
 /// A parser that generates an abstract syntax tree.
 pub struct Parser {
-    tokens: TokenIter,
+    tokens: TokenStream,
     pub symtab: SymbolTable,
-    current_scope: String,
+    /// Syntax errors that were recovered from so far.
+    errors: Vec<Diagnostic>,
     pos: i32,
     line_no: i32,
-    file: String,
+    /// Span of the token most recently looked at.
+    span: Span,
 }
 
 #[derive(Debug, Clone)]
 pub struct Args {
     pub name: Vec<String>,
     pub type_: Vec<String>,
+    pub span: Vec<Span>,
 } // I will improve this later.
 
 impl Parser {
-    pub fn new(tokens: TokenIter, file_path: &str) -> Self {
+    pub fn new(tokens: TokenIter) -> Self {
         Parser {
-            tokens,
+            tokens: TokenStream {
+                tokens,
+                last: Span::default(),
+                after_end: false,
+            },
             symtab: SymbolTable::new(),
-            current_scope: "global".to_string(),
+            errors: Vec::new(),
             pos: -1,
             line_no: 1,
-            file: file_path.to_string(),
+            span: Span::default(),
         }
     }
 
-    pub fn get_tok_precedence(&mut self, tok: TokenType) -> i32 {
-        match tok {
-            TokenType::Equal
-            | TokenType::NotEq
-            | TokenType::Greater
-            | TokenType::GreaterEq
-            | TokenType::Less
-            | TokenType::LessEq => 0,
-            TokenType::Minus | TokenType::Plus => 1,
-            TokenType::DivEq | TokenType::Mul => 2,
-            any => panic!("Bad operator! Unknown {:?}", any),
-        }
+    /// Binding power of `tok` as a binary operator, or `-1` if it doesn't continue an expression.
+    pub fn get_tok_precedence(&self, tok: &TokenType) -> i32 {
+        tok.binary_precedence().unwrap_or(-1)
     }
 
     fn advance(&mut self) {
-        self.pos = match self.tokens.peek() {
-            Some(t) => t,
-            None => panic!("Dunno"),
+        // At the end of input keep pointing at the last token.
+        if let Some(t) = self.tokens.peek() {
+            self.pos = t.pos;
+            self.line_no = t.line_no;
+            self.span = t.span;
         }
-        .pos;
-        self.line_no = match self.tokens.peek() {
-            Some(t) => t,
-            None => panic!("Dunno"),
+    }
+
+    /// Position of a node that started at `start` and ends with the last consumed token.
+    fn position_from(&self, start: &Token) -> NodePosition {
+        NodePosition {
+            pos: start.pos,
+            line_no: start.line_no,
+            span: start.span.to(self.tokens.last),
         }
-        .line_no;
-        // self.file = match self.tokens.peek(){
-        //     Some(t)=>t,
-        //     None=> panic!("Dunno")
-        // }.file.to_string();
     }
 
-    fn parser_error(&self, cause: &str) -> String {
-        format!(
-            "
-{text}
-{pointy}
-{cause}
-
-    at {line}:{pos} in file `{file}`.",
-            text = read_to_string(self.file.clone())
-                .unwrap()
-                .lines()
-                .collect::<Vec<_>>()[(self.line_no - 1) as usize],
-            pointy = ("~".repeat(self.pos as usize) + "^").red(),
-            cause = cause.yellow(),
-            line = self.line_no.green(),
-            pos = self.pos.green(),
-            file = self.file.green()
+    /// Whether `tok` can only start a top-level item.
+    fn is_item_start(tok: &TokenType) -> bool {
+        matches!(
+            tok,
+            TokenType::Def | TokenType::Class | TokenType::Extern | TokenType::Struct
         )
-        .to_string()
+    }
+
+    /// Record a syntax error and skip ahead to a point where parsing can resume.
+    ///
+    /// Tokens are discarded up to and including the next `;`, or up to (but not including) an
+    /// `end` or `}` closing the enclosing block, or the start of the next top-level item. Blocks
+    /// opened while skipping are skipped as a whole.
+    fn recover(&mut self, error: Diagnostic) {
+        self.errors.push(error);
+        let mut depth = 0;
+        while let Some(tok) = self.tokens.peek() {
+            match tok.type_ {
+                TokenType::Do | TokenType::LBrace => depth += 1,
+                TokenType::End | TokenType::RBrace if depth == 0 => return,
+                TokenType::End | TokenType::RBrace => depth -= 1,
+                TokenType::Semicolon if depth == 0 => {
+                    self.advance();
+                    self.tokens.next(); // Eat ';'
+                    return;
+                }
+                ref t if Self::is_item_start(t) => return,
+                _ => {}
+            }
+            self.advance();
+            self.tokens.next();
+        }
+    }
+
+    /// Declare `symbol` in the current scope, reporting a clash with an existing declaration.
+    fn declare(&mut self, symbol: Symbol) {
+        let span = symbol.span;
+        let name = symbol.name.clone();
+        if let Err(previous) = self.symtab.insert(symbol) {
+            let previous = self.symtab.symbol(previous).span;
+            self.errors.push(
+                Diagnostic::error(
+                    ErrorCode::Redefinition,
+                    format!("`{}` is already declared in this scope", name),
+                )
+                .with_span(span)
+                .with_label(previous, "previously declared here"),
+            );
+        }
+    }
+
+    /// A syntax error at the current position.
+    fn parser_error(&self, cause: &str) -> Diagnostic {
+        Diagnostic::error(ErrorCode::Syntax, cause).with_span(self.span)
     }
 }
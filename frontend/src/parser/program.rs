@@ -1,82 +1,320 @@
+use crate::diagnostic::Diagnostic;
 use crate::lexer::tokens::TokenType;
 use crate::parser::{AstNode, NodePosition, Parser};
-use crate::{unwrap_some, Result};
+use crate::Result;
 
 impl Parser {
-    pub fn parse_program(&mut self) -> Result<Vec<(AstNode, NodePosition)>> {
+    /// Parse the whole token stream.
+    ///
+    /// Parsing doesn't stop at the first syntax error: every error is collected, the offending
+    /// item or expression is replaced by an error node, and parsing resumes after it. The AST is
+    /// only meaningful if the returned diagnostics are empty.
+    pub fn parse_program(&mut self) -> (Vec<(AstNode, NodePosition)>, Vec<Diagnostic>) {
         let mut ast: Vec<(AstNode, NodePosition)> = Vec::new();
-        loop {
-            match self.tokens.peek() {
-                Some(s) => match s.type_ {
-                    TokenType::Extern => match self.parse_extern() {
-                        Ok((result, pos)) => {
-                            ast.insert(ast.len(), (AstNode::Extern(result), pos));
-                        }
-                        Err(e) if e == *"EOF".to_string() => return Ok(ast),
-                        Err(e) => return Err(e),
-                    },
-
-                    TokenType::Def => match self.parse_function() {
-                        Ok((result, pos)) => {
-                            ast.insert(ast.len(), (AstNode::FunctionDef(result), pos));
-                        }
-                        Err(e) if e == *"EOF".to_string() => return Ok(ast),
-                        Err(e) => return Err(e),
-                    },
-
-                    TokenType::Class => match self.parse_class() {
-                        Ok((result, pos)) => {
-                            ast.insert(ast.len(), (AstNode::Class(result), pos));
-                        }
-                        Err(e) if e == *"EOF".to_string() => return Ok(ast),
-                        Err(e) => return Err(e),
-                    },
-
-                    TokenType::Struct => match self.parse_struct() {
-                        Ok(((name, result), pos)) => {
-                            ast.insert(ast.len(), (AstNode::Struct(name, result), pos));
-                        }
-                        Err(e) if e == *"EOF".to_string() => return Ok(ast),
-                        Err(e) => return Err(e),
-                    },
-
-                    // TokenType::Module=>{
-                    // 	match self.parse_module(){
-                    // 		Ok((result, pos)) => {
-                    // 			ast.insert(ast.len(), (AstNode::Module(result), pos));
-                    // 		},
-                    // 		Err(e) if e == "EOF".to_string() => return Ok(ast),
-                    // 		Err(e) => return Err(e),
-                    // 	}
-                    // 	unimplemented!(),
-                    // }
-                    _ => {
-                        match self.parse_expression() {
-                            Ok((result, pos)) => {
-                                match self.tokens.peek() {
-                                    Some(t) if t.type_ == TokenType::Semicolon => {
-                                        self.tokens.next()
-                                    } // eat ';'
-                                    Some(_) => {
-                                        let _pos = unwrap_some!(self.tokens.peek()).pos;
-                                        let _line = unwrap_some!(self.tokens.peek()).line_no;
-                                        return Err(self
-                                            .parser_error("Expected semicolon after expression"));
-                                    }
-                                    None => return Err("EOF".to_string()),
-                                };
-                                ast.insert(ast.len(), (AstNode::Expression(result), pos));
-                            }
-                            Err(e) if e == *"EOF".to_string() => return Ok(ast),
-                            Err(e) => return Err(e),
-                        }
-                    } // {
-                      // 	println!("{:?}", self.tokens.peek());
-                      // 	return Err("Only functions or expressions allowed at top-level.".to_string())
-                      // }
-                },
-                None => return Ok(ast),
+        while let Some(s) = self.tokens.peek() {
+            let start = NodePosition {
+                pos: s.pos,
+                line_no: s.line_no,
+                span: s.span,
+            };
+            let scope = self.symtab.current_scope();
+            let item = match s.type_ {
+                TokenType::Extern => self
+                    .parse_extern()
+                    .map(|(result, pos)| (AstNode::Extern(result), pos)),
+
+                TokenType::Def => self
+                    .parse_function()
+                    .map(|(result, pos)| (AstNode::FunctionDef(result), pos)),
+
+                TokenType::Class => self
+                    .parse_class()
+                    .map(|(result, pos)| (AstNode::Class(result), pos)),
+
+                TokenType::Struct => self
+                    .parse_struct()
+                    .map(|((name, result, repr), pos)| (AstNode::Struct(name, result, repr), pos)),
+
+                // TokenType::Module=>{
+                // 	match self.parse_module(){
+                // 		Ok((result, pos)) => {
+                // 			ast.insert(ast.len(), (AstNode::Module(result), pos));
+                // 		},
+                // 		Err(e) if e == "EOF".to_string() => return Ok(ast),
+                // 		Err(e) => return Err(e),
+                // 	}
+                // 	unimplemented!(),
+                // }
+                _ => self.parse_top_level_expression(),
+            };
+            match item {
+                Ok(item) => ast.push(item),
+                Err(e) if e.is_unexpected_eof() => {
+                    self.errors.push(self.locate(e));
+                    let span = start.span.to(self.tokens.last);
+                    ast.push((AstNode::Error, NodePosition { span, ..start }));
+                    break;
+                }
+                Err(e) => {
+                    // Leave whatever scopes the item opened before failing.
+                    self.symtab.restore(scope);
+                    self.recover(e);
+                    // A stray closer most likely belonged to the item that just failed.
+                    if let Some(TokenType::End) | Some(TokenType::RBrace) =
+                        self.tokens.peek().map(|t| &t.type_)
+                    {
+                        self.advance();
+                        self.tokens.next();
+                    }
+                    let span = start.span.to(self.tokens.last);
+                    ast.push((AstNode::Error, NodePosition { span, ..start }));
+                }
+            }
+        }
+        (ast, std::mem::take(&mut self.errors))
+    }
+
+    /// Parse an expression at the top level, which must be terminated by a semicolon.
+    fn parse_top_level_expression(&mut self) -> Result<(AstNode, NodePosition)> {
+        let (result, pos) = self.parse_expression()?;
+        match self.tokens.peek() {
+            Some(t) if t.type_ == TokenType::Semicolon => {
+                self.advance();
+                self.tokens.next(); // eat ';'
+            }
+            Some(_) => return Err(self.parser_error("Expected semicolon after expression")),
+            None => return Err(Diagnostic::unexpected_eof()),
+        };
+        Ok((AstNode::Expression(result), pos))
+    }
+
+    /// Point an end-of-input diagnostic at the last token that was read.
+    fn locate(&self, diagnostic: Diagnostic) -> Diagnostic {
+        if diagnostic.span.is_none() && diagnostic.is_unexpected_eof() {
+            diagnostic.with_span(self.span)
+        } else {
+            diagnostic
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::diagnostic::{Diagnostic, ErrorCode};
+    use crate::lexer::Lexer;
+    use crate::parser::{AstNode, ExprValue, NodePosition, Parser, Repr};
+    use crate::source::SourceMap;
+
+    fn parse(text: &str) -> (Vec<(AstNode, NodePosition)>, Vec<Diagnostic>) {
+        let tokens = Lexer::from_text(&SourceMap::new(), text, "<test>")
+            .map(|t| t.unwrap())
+            .collect::<Vec<_>>();
+        let mut parser = Parser::new(tokens.into_iter().peekable());
+        parser.parse_program()
+    }
+
+    fn parse_error(text: &str) -> ErrorCode {
+        parse(text).1[0].code
+    }
+
+    #[test]
+    fn truncated_input_is_reported_as_unexpected_eof() {
+        assert_eq!(
+            parse_error("def main() -> i32 do\n  println(1"),
+            ErrorCode::UnexpectedEof
+        );
+        assert_eq!(parse_error("extern println(a: i32)"), ErrorCode::UnexpectedEof);
+    }
+
+    #[test]
+    fn malformed_input_is_reported_as_syntax_error() {
+        assert_eq!(parse_error("extern println(a: i32) i32;"), ErrorCode::Syntax);
+        assert_eq!(parse_error("1 + 2 3"), ErrorCode::Syntax);
+    }
+
+    #[test]
+    fn every_syntax_error_is_reported() {
+        let (ast, errors) = parse(
+            "extern println(a: i32) i32;
+def broken(a i32) -> i32 do
+    a
+end
+def main() -> i32 do
+    println(1 +);
+    let x: = 2;
+    println(3);
+end
+1 + ;
+",
+        );
+        assert_eq!(errors.len(), 5, "{:#?}", errors);
+        assert!(errors.iter().all(|e| e.code != ErrorCode::UnexpectedEof));
+        assert_eq!(ast.len(), 4);
+        assert!(matches!(ast[0].0, AstNode::Error));
+        assert!(matches!(ast[1].0, AstNode::Error));
+        assert!(matches!(ast[3].0, AstNode::Error));
+        match &ast[2].0 {
+            AstNode::FunctionDef(f) => {
+                assert!(matches!(f.expressions[0].value, ExprValue::Error));
+                assert!(matches!(f.expressions[1].value, ExprValue::Error));
+                assert!(matches!(f.expressions[2].value, ExprValue::FnCall(..)));
+            }
+            other => panic!("expected `main` to be parsed, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn missing_semicolons_are_reported() {
+        let (ast, errors) = parse(
+            "def main() -> i32 do
+    let a: i32 = 1 return a;
+    while a > 0 do a -= 1; end
+    return 0;
+end",
+        );
+        assert_eq!(errors.len(), 1, "{:#?}", errors);
+        assert_eq!(errors[0].message, "Expected ';' after expression");
+        match &ast[0].0 {
+            AstNode::FunctionDef(f) => assert_eq!(f.expressions.len(), 4),
+            other => panic!("expected `main` to be parsed, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn nested_blocks_are_skipped_while_recovering() {
+        let (ast, errors) = parse(
+            "def f() -> i32 do
+    let = do 1; 2 end;
+    3
+end
+def g() -> i32 do 4 end",
+        );
+        assert_eq!(errors.len(), 1, "{:#?}", errors);
+        assert!(matches!(ast[0].0, AstNode::FunctionDef(_)));
+        assert!(matches!(ast[1].0, AstNode::FunctionDef(_)));
+    }
+
+    #[test]
+    fn names_may_be_shadowed_but_not_redeclared() {
+        let (_, errors) = parse(
+            "def f(a: i32) -> i32 do
+    let a: i32 = 1;
+    do let a: i32 = 2; end;
+    let b: i32 = 3;
+    let b: bool = true;
+    0
+end
+def f() -> i32 do 0 end",
+        );
+        let codes = errors.iter().map(|e| e.code).collect::<Vec<_>>();
+        assert_eq!(codes, vec![ErrorCode::Redefinition; 3], "{:#?}", errors);
+        assert!(errors.iter().all(|e| e.labels.len() == 1));
+    }
+
+    #[test]
+    fn fields_are_declared_once() {
+        let (ast, errors) = parse(
+            "struct S { a: i32 b: i32 a: bool }
+class C {
+    x: i32
+    x: i32
+}",
+        );
+        let codes = errors.iter().map(|e| e.code).collect::<Vec<_>>();
+        assert_eq!(codes, vec![ErrorCode::Redefinition; 2], "{:#?}", errors);
+        assert!(errors.iter().all(|e| e.labels.len() == 1));
+        match &ast[0].0 {
+            AstNode::Struct(_, members, _) => {
+                let fields = members.iter().map(|m| (&*m.name, &*m.type_));
+                assert_eq!(fields.collect::<Vec<_>>(), [("a", "i32"), ("b", "i32")]);
             }
+            other => panic!("expected a struct, got {:?}", other),
         }
     }
+
+    #[test]
+    fn classes_hold_fields_and_methods() {
+        let (ast, errors) = parse(
+            "class A {
+    x: i32
+    def init(x: i32) -> void do self.x = x; end
+    def get() -> i32 do self.x end
+}
+A(1).get();",
+        );
+        assert!(errors.is_empty(), "{:#?}", errors);
+        match &ast[0].0 {
+            AstNode::Class(class) => {
+                let fields = class.fields.iter().map(|f| &f.name).collect::<Vec<_>>();
+                assert_eq!(fields, ["x"]);
+                let names = class.fns.iter().map(|(f, _)| &f.name).collect::<Vec<_>>();
+                assert_eq!(names, ["init", "get"]);
+                // `self` comes first, before the declared parameters.
+                assert_eq!(class.fns[0].0.args.name, ["self", "x"]);
+                assert_eq!(class.fns[0].0.args.type_, ["A", "i32"]);
+            }
+            other => panic!("expected a class, got {:?}", other),
+        }
+        match &ast[1].0 {
+            AstNode::Expression(ExprValue::MethodCall {
+                object,
+                method,
+                args,
+                ..
+            }) => {
+                assert!(matches!(object.value, ExprValue::FnCall(..)));
+                assert_eq!(method, "get");
+                assert!(args.is_empty());
+            }
+            other => panic!("expected a method call, got {:?}", other),
+        }
+        assert_eq!(
+            parse("class A { 1 }").1[0].message,
+            "Expected a field or a method in class"
+        );
+    }
+
+    #[test]
+    fn struct_layouts_are_parsed() {
+        let repr = |text: &str| match parse(text) {
+            (ast, errors) if errors.is_empty() => match &ast[0].0 {
+                AstNode::Struct(_, _, repr) => Ok(*repr),
+                other => panic!("expected a struct, got {:?}", other),
+            },
+            (_, errors) => Err(errors[0].message.clone()),
+        };
+        assert_eq!(repr("struct P { x: i32 }"), Ok(Repr::default()));
+        assert_eq!(
+            repr("struct P repr(C, packed) { x: i32 }"),
+            Ok(Repr {
+                c: true,
+                packed: true,
+                align: None
+            })
+        );
+        assert_eq!(
+            repr("struct P repr(align(8)) { x: i32 }").map(|r| r.align),
+            Ok(Some(8))
+        );
+        assert_eq!(
+            repr("struct P repr(align(6)) { x: i32 }"),
+            Err(String::from("The alignment must be a power of two"))
+        );
+        assert_eq!(
+            repr("struct P repr(align(32)) { x: i32 }"),
+            Err(String::from(
+                "Structs can't be aligned to more than 16 bytes"
+            ))
+        );
+        assert_eq!(
+            repr("struct P repr(packed, align(4)) { x: i32 }"),
+            Err(String::from("A struct can't be both packed and aligned"))
+        );
+        assert_eq!(
+            repr("struct P repr(Rust) { x: i32 }"),
+            Err(String::from(
+                "Expected 'C', 'packed' or 'align(n)' in 'repr'"
+            ))
+        );
+    }
 }
@@ -0,0 +1,349 @@
+//! The read-eval-print loop of `skippc repl`.
+
+use crate::checker::{Checker, Type};
+use crate::diagnostic::{Diagnostic, ErrorCode};
+use crate::generator::Generator;
+use crate::lexer::tokens::TokenType;
+use crate::lexer::Lexer;
+use crate::parser::{AstNode, ExprValue, NodePosition, Parser};
+use crate::source::SourceMap;
+use crate::symbol_table::SymbolTable;
+
+use libc::c_char;
+use std::ffi::CStr;
+use std::io::{self, BufRead, Write};
+use std::rc::Rc;
+use std::{mem, slice};
+
+/// Name of the function each top-level expression is compiled into.
+const THUNK: &str = "$repl";
+
+const HELP: &str = "Enter definitions (`def`, `extern`, `struct`) or expressions to evaluate.
+Commands:
+  :type <expr>  Show the type of an expression without evaluating it
+  :ast <input>  Show the syntax tree of some input
+  :ir           Show the LLVM IR of everything defined so far
+  :help         Show this message
+  :quit         Exit";
+
+type Program = Vec<(AstNode, NodePosition)>;
+
+/// An interactive session.
+///
+/// Definitions accumulate in one LLVM module, and every expression is compiled into a function
+/// that is run with the JIT against everything defined before it. Input that fails to check or
+/// generate leaves the session as it was.
+pub struct Repl {
+    sources: Rc<SourceMap>,
+    checker: Checker,
+    generator: Generator,
+}
+
+impl Repl {
+    pub unsafe fn new() -> Self {
+        let sources = Rc::new(SourceMap::new());
+        let generator = Generator::new(Vec::new(), "repl", Rc::clone(&sources), SymbolTable::new());
+        generator.init();
+        Repl {
+            sources,
+            checker: Checker::new(SymbolTable::new()),
+            generator,
+        }
+    }
+
+    /// Read and evaluate input from stdin until it ends or `:quit` is entered.
+    pub unsafe fn run(&mut self) {
+        println!(
+            "skippc {} - :help for help, :quit to exit",
+            env!("CARGO_PKG_VERSION")
+        );
+        let stdin = io::stdin();
+        let mut buffer = String::new();
+        loop {
+            print!("{}", if buffer.is_empty() { ">> " } else { ".. " });
+            io::stdout().flush().ok();
+            match stdin.lock().read_line(&mut buffer) {
+                Ok(0) | Err(_) => break,
+                Ok(_) => {}
+            }
+            if buffer.trim().is_empty() {
+                buffer.clear();
+                continue;
+            }
+            if is_incomplete(&buffer) {
+                continue;
+            }
+            let input = mem::take(&mut buffer);
+            if matches!(input.trim(), ":q" | ":quit") {
+                break;
+            }
+            match self.eval(&input) {
+                Ok(output) => output.iter().for_each(|line| println!("{}", line)),
+                Err(errors) => errors
+                    .iter()
+                    .for_each(|e| eprintln!("{}\n", e.render(&self.sources))),
+            }
+        }
+    }
+
+    /// Evaluate one complete input: a meta-command, or definitions and expressions.
+    ///
+    /// Returns a line of output for every expression that has a value.
+    pub unsafe fn eval(&mut self, input: &str) -> Result<Vec<String>, Vec<Diagnostic>> {
+        let input = input.trim();
+        if let Some(command) = input.strip_prefix(':') {
+            return self.command(command);
+        }
+        let saved = self.checker.clone();
+        let result = self.eval_program(input);
+        if result.is_err() {
+            self.checker = saved;
+        }
+        result
+    }
+
+    fn command(&self, command: &str) -> Result<Vec<String>, Vec<Diagnostic>> {
+        let (name, argument) = match command.find(char::is_whitespace) {
+            Some(i) => (&command[..i], command[i..].trim()),
+            None => (command, ""),
+        };
+        match name {
+            "type" => {
+                // Checked against a copy, so nothing the expression declares is kept.
+                let mut checker = self.checker.clone();
+                let mut program = parse(&self.sources, argument, &mut checker.symbols)?;
+                program
+                    .iter_mut()
+                    .map(|(node, pos)| match node {
+                        AstNode::Expression(e) => {
+                            checker.infer(e, pos.span).map(|type_| type_.to_string())
+                        }
+                        _ => Err(vec![Diagnostic::error(
+                            ErrorCode::Syntax,
+                            "`:type` expects an expression",
+                        )
+                        .with_span(pos.span)]),
+                    })
+                    .collect()
+            }
+            "ast" => {
+                let mut symbols = self.checker.symbols.clone();
+                let program = parse(&self.sources, argument, &mut symbols)?;
+                Ok(vec![format!("{:#?}", program)])
+            }
+            "ir" => Ok(vec![unsafe { self.generator.ir() }]),
+            "help" => Ok(vec![HELP.to_string()]),
+            _ => Err(vec![Diagnostic::error(
+                ErrorCode::Syntax,
+                format!("Unknown command `:{}`, see `:help`", name),
+            )]),
+        }
+    }
+
+    unsafe fn eval_program(&mut self, input: &str) -> Result<Vec<String>, Vec<Diagnostic>> {
+        let mut program = parse(&self.sources, input, &mut self.checker.symbols)?;
+
+        // The type of each top-level expression.
+        let mut types = Vec::new();
+        let mut errors = Vec::new();
+        for item in program.iter_mut() {
+            let type_ = match item {
+                (AstNode::Expression(ExprValue::VarDecl { .. }), pos) => {
+                    errors.push(
+                        Diagnostic::error(
+                            ErrorCode::Syntax,
+                            "Variables can only be declared inside a function or a `do` block",
+                        )
+                        .with_span(pos.span),
+                    );
+                    None
+                }
+                (AstNode::Expression(e), pos) => match self.checker.infer(e, pos.span) {
+                    Ok(type_) => Some(type_),
+                    Err(e) => {
+                        errors.extend(e);
+                        None
+                    }
+                },
+                _ => {
+                    errors.extend(self.checker.check_program(slice::from_mut(item)));
+                    None
+                }
+            };
+            types.push(type_);
+        }
+        if !errors.is_empty() {
+            return Err(errors);
+        }
+
+        mem::swap(
+            &mut *self.generator.symbols.borrow_mut(),
+            &mut self.checker.symbols,
+        );
+        let result = self.generate(&program, &types);
+        mem::swap(
+            &mut *self.generator.symbols.borrow_mut(),
+            &mut self.checker.symbols,
+        );
+        if result.is_err() {
+            // Don't keep half of the input around.
+            for (node, _) in &program {
+                match node {
+                    AstNode::FunctionDef(f) => self.generator.remove_function(&f.name),
+                    AstNode::Extern(e) => self.generator.remove_function(&e.name),
+                    _ => {}
+                }
+            }
+        }
+        result.map_err(|e| vec![e])
+    }
+
+    unsafe fn generate(
+        &self,
+        program: &[(AstNode, NodePosition)],
+        types: &[Option<Type>],
+    ) -> crate::Result<Vec<String>> {
+        let mut output = Vec::new();
+        for (item, type_) in program.iter().zip(types) {
+            let (expression, pos, type_) = match (item, type_) {
+                ((AstNode::Expression(e), pos), Some(type_)) => (e, pos, type_),
+                _ => {
+                    self.generator.gen_program(slice::from_ref(item))?;
+                    self.generator.verify()?;
+                    continue;
+                }
+            };
+            self.generator
+                .gen_thunk(THUNK, expression, pos.span, &type_.to_string())?;
+            let value = self.generator.verify().and_then(|_| {
+                self.generator
+                    .run_function(THUNK, |value| show(value, type_))
+            });
+            self.generator.remove_function(THUNK);
+            output.extend(value?);
+        }
+        Ok(output)
+    }
+}
+
+/// How the REPL prints `value`, the result of an expression of type `type_`.
+///
+/// A `str` is followed to its text, which must still be alive; any other pointer is only named.
+unsafe fn show(value: Option<i64>, type_: &Type) -> Option<String> {
+    let value = value?;
+    let shown = match type_ {
+        Type::I32 | Type::I64 => value.to_string(),
+        Type::Bool => (value != 0).to_string(),
+        _ if value == 0 => String::from("null"),
+        Type::Str => CStr::from_ptr(value as usize as *const c_char)
+            .to_string_lossy()
+            .into_owned(),
+        _ => format!("<{} object>", type_),
+    };
+    Some(format!("{}: {}", shown, type_))
+}
+
+/// Parse REPL input, declaring its names in `symbols`.
+///
+/// The semicolon after a lone expression may be left out.
+fn parse(
+    sources: &SourceMap,
+    input: &str,
+    symbols: &mut SymbolTable,
+) -> Result<Program, Vec<Diagnostic>> {
+    let input = input.trim();
+    let is_item = ["def", "extern", "struct", "class"]
+        .iter()
+        .any(|keyword| input.split_whitespace().next() == Some(keyword));
+    let input = if is_item || input.ends_with(';') {
+        input.to_string()
+    } else {
+        format!("{};", input)
+    };
+    let tokens = Lexer::from_text(sources, &input, "<repl>")
+        .collect::<crate::Result<Vec<_>>>()
+        .map_err(|e| vec![e])?;
+
+    let mut parser = Parser::new(tokens.into_iter().peekable());
+    mem::swap(&mut parser.symtab, symbols);
+    let (program, errors) = parser.parse_program();
+    mem::swap(&mut parser.symtab, symbols);
+    if errors.is_empty() {
+        Ok(program)
+    } else {
+        Err(errors)
+    }
+}
+
+/// Whether `input` has an unclosed `do ... end` or `{ }`, so more lines should be read.
+fn is_incomplete(input: &str) -> bool {
+    let mut depth = 0;
+    for token in Lexer::from_text(&SourceMap::new(), input, "<repl>") {
+        match token.map(|t| t.type_) {
+            Ok(TokenType::Do) | Ok(TokenType::LBrace) => depth += 1,
+            Ok(TokenType::End) | Ok(TokenType::RBrace) => depth -= 1,
+            Ok(_) => {}
+            // Let evaluating the input report the error.
+            Err(_) => return false,
+        }
+    }
+    depth > 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{is_incomplete, Repl};
+
+    fn eval(repl: &mut Repl, input: &str) -> Vec<String> {
+        unsafe { repl.eval(input) }.unwrap_or_else(|e| panic!("{:#?}", e))
+    }
+
+    #[test]
+    fn definitions_persist_between_inputs() {
+        let mut repl = unsafe { Repl::new() };
+        assert_eq!(eval(&mut repl, "1 + 2"), vec!["3: i32"]);
+        eval(
+            &mut repl,
+            "def fact(n: i32) -> i32 do
+    if n < 2: return 1;
+    return n * fact(n - 1);
+end",
+        );
+        assert_eq!(eval(&mut repl, "fact(5) == 120"), vec!["true: bool"]);
+        assert_eq!(eval(&mut repl, ":type fact(5)"), vec!["i32"]);
+        assert!(eval(&mut repl, ":ir")[0].contains("define i32 @fact"));
+    }
+
+    #[test]
+    fn failed_input_is_forgotten() {
+        let mut repl = unsafe { Repl::new() };
+        assert!(unsafe { repl.eval("def f() -> i32 do return true; end") }.is_err());
+        eval(&mut repl, "def f() -> i32 do return 4; end");
+        assert_eq!(eval(&mut repl, "f()"), vec!["4: i32"]);
+        assert!(unsafe { repl.eval("let x: i32 = 1") }.is_err());
+    }
+
+    #[test]
+    fn results_are_shown_by_type() {
+        let mut repl = unsafe { Repl::new() };
+        eval(
+            &mut repl,
+            "def big() -> i64 do let n: i64 = 2147483647; return n * 4; end",
+        );
+        assert_eq!(eval(&mut repl, "big()"), vec!["8589934588: i64"]);
+        assert_eq!(eval(&mut repl, "\"h\u{e9}llo\""), vec!["h\u{e9}llo: str"]);
+        eval(&mut repl, "struct Point { x: i32 y: i32 }");
+        assert_eq!(
+            eval(&mut repl, "Point(1, 2)"),
+            vec!["<Point object>: Point"]
+        );
+    }
+
+    #[test]
+    fn unclosed_blocks_need_more_input() {
+        assert!(is_incomplete("def f() -> i32 do"));
+        assert!(is_incomplete("struct P {"));
+        assert!(!is_incomplete("def f() -> i32 do 1 end"));
+        assert!(!is_incomplete("1 + 2"));
+    }
+}
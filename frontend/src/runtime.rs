@@ -0,0 +1,5 @@
+//! The runtime library, `skipp-rt`, for programs run in-process by the JIT.
+//!
+//! Compiled programs are linked against the same functions, built as a static library.
+
+pub use skipp_rt::symbols;
@@ -0,0 +1,190 @@
+use crate::diagnostic::{Diagnostic, ErrorCode};
+use crate::Result;
+
+use std::cell::RefCell;
+use std::fs;
+use std::rc::Rc;
+
+/// Identifies a source file.
+pub type FileId = usize;
+
+/// A range of bytes `start..end` in a source file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Span {
+    pub file_id: FileId,
+    /// Offset of the first byte.
+    pub start: usize,
+    /// Offset one past the last byte.
+    pub end: usize,
+}
+
+impl Span {
+    pub fn new(file_id: FileId, start: usize, end: usize) -> Self {
+        Span {
+            file_id,
+            start,
+            end,
+        }
+    }
+
+    /// The smallest span covering both `self` and `other`.
+    pub fn to(self, other: Span) -> Span {
+        Span {
+            file_id: self.file_id,
+            start: self.start.min(other.start),
+            end: self.end.max(other.end),
+        }
+    }
+
+    /// An empty span right after `self`.
+    pub fn shrink_to_end(self) -> Span {
+        Span {
+            start: self.end,
+            ..self
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.end - self.start
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.end == self.start
+    }
+
+    /// Whether `offset` falls inside the span.
+    pub fn contains(&self, offset: usize) -> bool {
+        self.start <= offset && offset < self.end
+    }
+}
+
+/// A source file's name and contents, with its line starts precomputed.
+#[derive(Debug)]
+pub struct SourceFile {
+    pub id: FileId,
+    /// The path the file was loaded from, or a made-up name such as `<repl>`.
+    pub name: String,
+    pub text: String,
+    /// Byte offset at which each line starts.
+    line_starts: Vec<usize>,
+}
+
+impl SourceFile {
+    fn new(id: FileId, name: String, text: String) -> Self {
+        let line_starts = std::iter::once(0)
+            .chain(text.match_indices('\n').map(|(i, _)| i + 1))
+            .collect();
+        SourceFile {
+            id,
+            name,
+            text,
+            line_starts,
+        }
+    }
+
+    /// The 1-based line and column of `offset`, counting columns in characters.
+    ///
+    /// Offsets past the end of the file are clamped to it.
+    pub fn line_col(&self, offset: usize) -> (usize, usize) {
+        let offset = offset.min(self.text.len());
+        let line = match self.line_starts.binary_search(&offset) {
+            Ok(line) => line,
+            Err(next) => next - 1,
+        };
+        let column = self.text[self.line_starts[line]..offset].chars().count();
+        (line + 1, column + 1)
+    }
+
+    /// The text of the 1-based line `line`, without its line break.
+    pub fn line(&self, line: usize) -> Option<&str> {
+        let start = *self.line_starts.get(line.checked_sub(1)?)?;
+        let end = self
+            .line_starts
+            .get(line)
+            .map_or(self.text.len(), |&next| next - 1);
+        Some(self.text[start..end].trim_end_matches('\r'))
+    }
+
+    /// The source text covered by `span`.
+    pub fn slice(&self, span: Span) -> &str {
+        &self.text[span.start.min(self.text.len())..span.end.min(self.text.len())]
+    }
+}
+
+/// Every source file loaded during a compilation.
+///
+/// Files are never removed, so a [`FileId`] stays valid for the map's whole life. The map is
+/// shared between the compiler phases behind an `Rc`, and files can be added through a shared
+/// reference.
+#[derive(Debug, Default)]
+pub struct SourceMap {
+    files: RefCell<Vec<Rc<SourceFile>>>,
+}
+
+impl SourceMap {
+    pub fn new() -> Self {
+        SourceMap::default()
+    }
+
+    /// Add a source that is already in memory and return its id.
+    pub fn add(&self, name: &str, text: &str) -> FileId {
+        let mut files = self.files.borrow_mut();
+        let id = files.len();
+        files.push(Rc::new(SourceFile::new(
+            id,
+            name.to_string(),
+            text.to_string(),
+        )));
+        id
+    }
+
+    /// Read the file at `path` and add it to the map.
+    pub fn load(&self, path: &str) -> Result<FileId> {
+        match fs::read_to_string(path) {
+            Ok(text) => Ok(self.add(path, &text)),
+            Err(e) => Err(Diagnostic::error(
+                ErrorCode::Io,
+                format!("Unable to read `{}`: {}", path, e),
+            )),
+        }
+    }
+
+    /// The file with the given id.
+    ///
+    /// # Panics
+    /// If `id` wasn't handed out by this map.
+    pub fn get(&self, id: FileId) -> Rc<SourceFile> {
+        Rc::clone(&self.files.borrow()[id])
+    }
+
+    /// The file with the given id, if there is one.
+    pub fn try_get(&self, id: FileId) -> Option<Rc<SourceFile>> {
+        self.files.borrow().get(id).cloned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{SourceMap, Span};
+
+    #[test]
+    fn offsets_map_to_lines_and_columns() {
+        let sources = SourceMap::new();
+        sources.add("a", "first");
+        let id = sources.add("b", "ab\r\nçd\n\nx");
+        let file = sources.get(id);
+        assert_eq!(file.id, 1);
+        assert_eq!(file.line_col(0), (1, 1));
+        assert_eq!(file.line_col(4), (2, 1));
+        assert_eq!(file.line_col(6), (2, 2));
+        assert_eq!(file.line_col(9), (4, 1));
+        assert_eq!(file.line_col(100), (4, 2));
+        assert_eq!(file.line(1), Some("ab"));
+        assert_eq!(file.line(2), Some("çd"));
+        assert_eq!(file.line(3), Some(""));
+        assert_eq!(file.line(4), Some("x"));
+        assert_eq!(file.line(0), None);
+        assert_eq!(file.line(5), None);
+        assert_eq!(file.slice(Span::new(id, 4, 7)), "çd");
+    }
+}
@@ -0,0 +1,266 @@
+use crate::source::Span;
+
+use std::collections::HashMap;
+use std::fmt;
+
+/// Index of a scope in a [`SymbolTable`].
+pub type ScopeId = usize;
+
+/// Identifies a symbol: the scope it is declared in and its index there.
+pub type SymbolId = (ScopeId, usize);
+
+/// What introduced a scope.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScopeKind {
+    Global,
+    /// A function's parameters and body.
+    Function,
+    /// A `do ... end` block.
+    Block,
+    /// The body of a `while` loop.
+    Loop,
+    /// A class's methods.
+    Class,
+}
+
+/// What a symbol names.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SymbolKind {
+    Variable,
+    Parameter,
+    Function,
+    Extern,
+    Struct,
+    Class,
+}
+
+impl fmt::Display for SymbolKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SymbolKind::Variable => write!(f, "variable"),
+            SymbolKind::Parameter => write!(f, "parameter"),
+            SymbolKind::Function => write!(f, "function"),
+            SymbolKind::Extern => write!(f, "extern"),
+            SymbolKind::Struct => write!(f, "struct"),
+            SymbolKind::Class => write!(f, "class"),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Symbol {
+    pub name: String,
+    pub kind: SymbolKind,
+    /// The declared type, or the return type of a function.
+    pub type_: String,
+    /// The declaration.
+    pub span: Span,
+}
+
+impl Symbol {
+    pub fn new(name: &str, kind: SymbolKind, type_: &str, span: Span) -> Self {
+        Symbol {
+            name: name.to_string(),
+            kind,
+            type_: type_.to_string(),
+            span,
+        }
+    }
+
+    /// Whether a use at byte `offset` can refer to this symbol.
+    ///
+    /// Variables come into scope after their declaration, so `let x: i32 = x` refers to an outer
+    /// `x`. Functions and types are visible from the start of their definition, which lets a
+    /// function call itself.
+    pub fn visible_at(&self, offset: usize) -> bool {
+        match self.kind {
+            SymbolKind::Variable | SymbolKind::Parameter => self.span.end <= offset,
+            _ => self.span.start <= offset,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Scope {
+    kind: ScopeKind,
+    parent: Option<ScopeId>,
+    /// Byte offset where the construct that introduced the scope starts.
+    start: usize,
+    children: Vec<ScopeId>,
+    /// Symbols in declaration order.
+    symbols: Vec<Symbol>,
+    /// Index of each name in `symbols`.
+    names: HashMap<String, usize>,
+}
+
+/// Lexical scopes of a program and the symbols declared in them.
+///
+/// The parser builds the tree while reading the program. Later passes walk it again by entering
+/// and exiting the same scopes: [`SymbolTable::enter_scope`] steps into the scope introduced at a
+/// given offset if it exists, and creates it otherwise.
+#[derive(Debug, Clone)]
+pub struct SymbolTable {
+    scopes: Vec<Scope>,
+    current: ScopeId,
+}
+
+impl SymbolTable {
+    pub fn new() -> Self {
+        SymbolTable {
+            scopes: vec![Scope {
+                kind: ScopeKind::Global,
+                parent: None,
+                start: 0,
+                children: Vec::new(),
+                symbols: Vec::new(),
+                names: HashMap::new(),
+            }],
+            current: 0,
+        }
+    }
+
+    /// The innermost scope.
+    pub fn current_scope(&self) -> ScopeId {
+        self.current
+    }
+
+    pub fn scope_kind(&self, scope: ScopeId) -> ScopeKind {
+        self.scopes[scope].kind
+    }
+
+    /// Make `scope` the innermost scope, e.g. to get back to a known state after a syntax error.
+    pub fn restore(&mut self, scope: ScopeId) {
+        self.current = scope;
+    }
+
+    /// Enter the child scope of the current scope introduced at byte offset `start`, creating it
+    /// if it doesn't exist yet.
+    pub fn enter_scope(&mut self, kind: ScopeKind, start: usize) -> ScopeId {
+        let existing = self.scopes[self.current]
+            .children
+            .iter()
+            .copied()
+            .find(|&child| self.scopes[child].start == start && self.scopes[child].kind == kind);
+        let scope = existing.unwrap_or_else(|| {
+            let id = self.scopes.len();
+            self.scopes.push(Scope {
+                kind,
+                parent: Some(self.current),
+                start,
+                children: Vec::new(),
+                symbols: Vec::new(),
+                names: HashMap::new(),
+            });
+            self.scopes[self.current].children.push(id);
+            id
+        });
+        self.current = scope;
+        scope
+    }
+
+    /// Go back to the parent of the current scope.
+    ///
+    /// # Panics
+    /// If the current scope is the global scope.
+    pub fn exit_scope(&mut self) {
+        self.current = self.scopes[self.current]
+            .parent
+            .expect("cannot exit the global scope");
+    }
+
+    /// Declare a symbol in the current scope.
+    ///
+    /// Names may shadow names of enclosing scopes, but not other names in the same scope: then
+    /// nothing is declared and the id of the existing symbol is returned as the error.
+    pub fn insert(&mut self, symbol: Symbol) -> Result<SymbolId, SymbolId> {
+        let scope = &mut self.scopes[self.current];
+        if let Some(&index) = scope.names.get(&symbol.name) {
+            return Err((self.current, index));
+        }
+        let index = scope.symbols.len();
+        scope.names.insert(symbol.name.clone(), index);
+        scope.symbols.push(symbol);
+        Ok((self.current, index))
+    }
+
+    /// Find the symbol a use of `name` at byte `offset` refers to, searching outwards from the
+    /// current scope.
+    pub fn lookup(&self, name: &str, offset: usize) -> Option<SymbolId> {
+        let mut scope = Some(self.current);
+        while let Some(id) = scope {
+            let s = &self.scopes[id];
+            match s.names.get(name) {
+                Some(&index) if s.symbols[index].visible_at(offset) => return Some((id, index)),
+                _ => scope = s.parent,
+            }
+        }
+        None
+    }
+
+    /// The symbol declared by the declaration at `span` in the current scope.
+    pub fn declared_at(&self, name: &str, span: Span) -> Option<SymbolId> {
+        let scope = &self.scopes[self.current];
+        scope
+            .names
+            .get(name)
+            .filter(|&&index| scope.symbols[index].span == span)
+            .map(|&index| (self.current, index))
+    }
+
+    pub fn symbol(&self, (scope, index): SymbolId) -> &Symbol {
+        &self.scopes[scope].symbols[index]
+    }
+}
+
+impl Default for SymbolTable {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ScopeKind, Symbol, SymbolKind, SymbolTable};
+    use crate::source::Span;
+
+    fn var(name: &str, start: usize, end: usize) -> Symbol {
+        Symbol::new(name, SymbolKind::Variable, "i32", Span::new(0, start, end))
+    }
+
+    #[test]
+    fn lookup_walks_outwards_and_respects_declaration_order() {
+        let mut table = SymbolTable::new();
+        table
+            .insert(Symbol::new("f", SymbolKind::Function, "i32", Span::new(0, 0, 100)))
+            .unwrap();
+        table.enter_scope(ScopeKind::Function, 0);
+        let outer = table.insert(var("x", 10, 20)).unwrap();
+        table.enter_scope(ScopeKind::Block, 30);
+        assert_eq!(table.lookup("x", 35), Some(outer));
+        let inner = table.insert(var("x", 40, 50)).unwrap();
+        assert_eq!(table.lookup("x", 45), Some(outer));
+        assert_eq!(table.lookup("x", 55), Some(inner));
+        assert_eq!(table.lookup("f", 55).map(|id| table.symbol(id).kind), Some(SymbolKind::Function));
+        assert_eq!(table.insert(var("x", 60, 70)), Err(inner));
+        table.exit_scope();
+        assert_eq!(table.lookup("x", 95), Some(outer));
+        assert_eq!(table.lookup("x", 15), None);
+        table.exit_scope();
+        assert_eq!(table.lookup("x", 95), None);
+    }
+
+    #[test]
+    fn scopes_are_reentered_by_position() {
+        let mut table = SymbolTable::new();
+        let a = table.enter_scope(ScopeKind::Function, 0);
+        let b = table.enter_scope(ScopeKind::Block, 5);
+        let x = table.insert(var("x", 6, 7)).unwrap();
+        table.exit_scope();
+        table.exit_scope();
+
+        assert_eq!(table.enter_scope(ScopeKind::Function, 0), a);
+        assert_eq!(table.enter_scope(ScopeKind::Block, 5), b);
+        assert_eq!(table.declared_at("x", Span::new(0, 6, 7)), Some(x));
+        assert_eq!(table.declared_at("x", Span::new(0, 6, 8)), None);
+    }
+}
@@ -0,0 +1,173 @@
+//! Differential testing of the LLVM backend against the interpreter.
+//!
+//! Every `.spp` program in `tests/programs` (or in the directory named by `SKIPP_CORPUS`) is
+//! compiled to an executable and run, and is also run by the tree-walking interpreter. Both must
+//! print the same thing and exit with the same code. The first program where they don't is
+//! reported with the lines where their output starts to differ.
+
+use frontend::checker::Checker;
+use frontend::diagnostic::ErrorCode;
+use frontend::generator::Generator;
+use frontend::interpreter::Interpreter;
+use frontend::lexer::Lexer;
+use frontend::linker::{LinkOptions, Linker};
+use frontend::parser::{AstNode, NodePosition, Parser};
+use frontend::source::SourceMap;
+use frontend::symbol_table::SymbolTable;
+
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::rc::Rc;
+use std::{env, fs};
+
+/// What running a program produced.
+#[derive(Debug, PartialEq)]
+struct Outcome {
+    stdout: String,
+    /// The exit code, which the OS truncates to its low byte.
+    status: i32,
+}
+
+/// Lex, parse and check `path`.
+fn front_end(
+    sources: &SourceMap,
+    path: &Path,
+) -> Result<(Vec<(AstNode, NodePosition)>, SymbolTable), String> {
+    let lexer = Lexer::from_file(sources, path.to_str().unwrap()).map_err(|e| e.render(sources))?;
+    let tokens = lexer
+        .collect::<frontend::Result<Vec<_>>>()
+        .map_err(|e| e.render(sources))?;
+    let mut parser = Parser::new(tokens.into_iter().peekable());
+    let (mut program, errors) = parser.parse_program();
+    let mut checker = Checker::new(parser.symtab);
+    let errors = if errors.is_empty() {
+        checker.check_program(&mut program)
+    } else {
+        errors
+    };
+    match errors.first() {
+        Some(e) => Err(e.render(sources)),
+        None => Ok((program, checker.into_symbols())),
+    }
+}
+
+fn interpret(path: &Path) -> Result<Outcome, String> {
+    let sources = SourceMap::new();
+    let (program, symbols) = front_end(&sources, path)?;
+    let mut interpreter = Interpreter::new(symbols, Vec::new());
+    let status = match interpreter.load(&program).and_then(|_| interpreter.run_main()) {
+        Ok(status) => status,
+        Err(e) if e.code == ErrorCode::Panic => skipp_rt::panic::EXIT_CODE,
+        Err(e) => return Err(e.render(&sources)),
+    };
+    Ok(Outcome {
+        stdout: String::from_utf8_lossy(interpreter.output()).into_owned(),
+        status: status & 0xff,
+    })
+}
+
+fn compile_and_run(path: &Path, work_dir: &Path) -> Result<Outcome, String> {
+    let sources = Rc::new(SourceMap::new());
+    let (program, symbols) = front_end(&sources, path)?;
+    let name = path.file_stem().unwrap().to_str().unwrap();
+    let object = work_dir.join(format!("{}.o", name));
+    let executable = work_dir.join(format!("{}.out", name));
+    unsafe {
+        let generator = Generator::new(program, name, Rc::clone(&sources), symbols);
+        generator.init();
+        generator.generate().map_err(|e| e.render(&sources))?;
+        generator.verify().map_err(|e| e.render(&sources))?;
+        generator
+            .optimize(2, None)
+            .map_err(|e| e.render(&sources))?;
+        generator
+            .generate_object_file(2, object.to_str().unwrap())
+            .map_err(|e| e.render(&sources))?;
+    }
+
+    Linker::new(LinkOptions::default())
+        .and_then(|linker| linker.link(&[&object], &executable))
+        .map_err(|e| e.render(&sources))?;
+
+    let run = Command::new(&executable)
+        .output()
+        .map_err(|e| format!("Unable to run {}: {}", executable.display(), e))?;
+    Ok(Outcome {
+        stdout: String::from_utf8_lossy(&run.stdout).into_owned(),
+        status: run
+            .status
+            .code()
+            .ok_or("The executable was killed by a signal")?,
+    })
+}
+
+/// The first line where two outputs differ, with the line before it for context.
+fn first_difference(interpreted: &str, compiled: &str) -> Option<String> {
+    let expected = interpreted.lines().collect::<Vec<_>>();
+    let actual = compiled.lines().collect::<Vec<_>>();
+    let line = (0..expected.len().max(actual.len())).find(|&i| expected.get(i) != actual.get(i))?;
+    let show = |lines: &[&str], i: usize| {
+        lines
+            .get(i)
+            .map_or("<end of output>".into(), |l| l.to_string())
+    };
+    let mut diff = String::new();
+    if line > 0 {
+        diff += &format!("    {:>4} | {}\n", line, expected[line - 1]);
+    }
+    diff += &format!(
+        "  - {:>4} | {}    (interpreter)\n",
+        line + 1,
+        show(&expected, line)
+    );
+    diff += &format!("  + {:>4} | {}    (llvm)\n", line + 1, show(&actual, line));
+    Some(diff)
+}
+
+fn corpus() -> Vec<PathBuf> {
+    let dir = env::var_os("SKIPP_CORPUS")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/programs"));
+    let mut programs = fs::read_dir(&dir)
+        .unwrap_or_else(|e| panic!("Unable to read {}: {}", dir.display(), e))
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "spp"))
+        .collect::<Vec<_>>();
+    programs.sort();
+    programs
+}
+
+#[test]
+fn llvm_backend_agrees_with_the_interpreter() {
+    let work_dir = env::temp_dir().join(format!("skipp-differential-{}", std::process::id()));
+    fs::create_dir_all(&work_dir).unwrap();
+
+    let programs = corpus();
+    assert!(!programs.is_empty(), "The corpus is empty");
+    for path in &programs {
+        let interpreted = interpret(path)
+            .unwrap_or_else(|e| panic!("{}: interpreter failed:\n{}", path.display(), e));
+        let compiled = compile_and_run(path, &work_dir)
+            .unwrap_or_else(|e| panic!("{}: LLVM backend failed:\n{}", path.display(), e));
+        if interpreted == compiled {
+            continue;
+        }
+
+        let mut report = format!(
+            "{}: the LLVM backend disagrees with the interpreter\n",
+            path.display()
+        );
+        if let Some(diff) = first_difference(&interpreted.stdout, &compiled.stdout) {
+            report += &format!("stdout:\n{}", diff);
+        }
+        if interpreted.status != compiled.status {
+            report += &format!(
+                "exit code: {} (interpreter), {} (llvm)\n",
+                interpreted.status, compiled.status
+            );
+        }
+        fs::remove_dir_all(&work_dir).ok();
+        panic!("{}", report);
+    }
+    fs::remove_dir_all(&work_dir).ok();
+}
@@ -0,0 +1,141 @@
+//! Golden tests of the lexer, parser and generator.
+//!
+//! For every `tests/golden/<name>.spp` the tokens (as printed by `--print-tokens`), the checked
+//! AST (as printed by `--print-ast`) and the generated LLVM IR are compared against the checked-in
+//! `<name>.tokens`, `<name>.ast` and `<name>.ir`.
+//!
+//! After an intended change, re-bless the expected files with
+//!
+//! ```text
+//! SKIPP_BLESS=1 cargo test --test golden
+//! ```
+//!
+//! and review the diff like any other change.
+
+use frontend::checker::Checker;
+use frontend::generator::{Generator, TargetOptions};
+use frontend::lexer::Lexer;
+use frontend::parser::Parser;
+use frontend::source::SourceMap;
+
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::{env, fs};
+
+/// The tokens, AST and IR of the program at `path`, by file extension.
+fn outputs(path: &Path) -> Vec<(&'static str, String)> {
+    let name = path.file_name().unwrap().to_str().unwrap();
+    let text = fs::read_to_string(path).unwrap();
+    let sources = Rc::new(SourceMap::new());
+    let tokens = Lexer::from_text(&sources, &text, name)
+        .collect::<frontend::Result<Vec<_>>>()
+        .unwrap_or_else(|e| panic!("{}", e.render(&sources)));
+    let token_dump = tokens
+        .iter()
+        .map(|t| format!("{:?}\n", t))
+        .collect::<String>();
+
+    let mut parser = Parser::new(tokens.into_iter().peekable());
+    let (mut program, mut errors) = parser.parse_program();
+    let mut checker = Checker::new(parser.symtab);
+    if errors.is_empty() {
+        errors = checker.check_program(&mut program);
+    }
+    if let Some(e) = errors.first() {
+        panic!("{}", e.render(&sources));
+    }
+    let ast_dump = format!("{:#?}\n", program);
+
+    let ir = unsafe {
+        let stem = path.file_stem().unwrap().to_str().unwrap();
+        let mut generator =
+            Generator::new(program, stem, Rc::clone(&sources), checker.into_symbols());
+        // A fixed target, so the data layout doesn't depend on the host.
+        let target = TargetOptions {
+            triple: Some(String::from("x86_64-unknown-linux-gnu")),
+            ..TargetOptions::default()
+        };
+        generator
+            .set_target(target)
+            .unwrap_or_else(|e| panic!("{}", e.render(&sources)));
+        generator.init();
+        generator
+            .generate()
+            .and_then(|_| generator.verify())
+            .unwrap_or_else(|e| panic!("{}", e.render(&sources)));
+        generator.ir()
+    };
+
+    vec![("tokens", token_dump), ("ast", ast_dump), ("ir", ir)]
+}
+
+/// The first line where `expected` and `actual` differ, with a little context.
+fn diff(expected: &str, actual: &str) -> String {
+    let expected = expected.lines().collect::<Vec<_>>();
+    let actual = actual.lines().collect::<Vec<_>>();
+    let line = (0..expected.len().max(actual.len()))
+        .find(|&i| expected.get(i) != actual.get(i))
+        .unwrap_or(0);
+    let mut diff = String::new();
+    let before = line.saturating_sub(2);
+    for (i, context) in expected[before..line].iter().enumerate() {
+        diff += &format!("    {:>4} | {}\n", before + i + 1, context);
+    }
+    for i in line..(line + 3) {
+        match (expected.get(i), actual.get(i)) {
+            (Some(e), Some(a)) if e == a => diff += &format!("    {:>4} | {}\n", i + 1, e),
+            (e, a) => {
+                if let Some(e) = e {
+                    diff += &format!("  - {:>4} | {}\n", i + 1, e);
+                }
+                if let Some(a) = a {
+                    diff += &format!("  + {:>4} | {}\n", i + 1, a);
+                }
+            }
+        }
+    }
+    diff
+}
+
+fn corpus() -> Vec<PathBuf> {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/golden");
+    let mut programs = fs::read_dir(&dir)
+        .unwrap_or_else(|e| panic!("Unable to read {}: {}", dir.display(), e))
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "spp"))
+        .collect::<Vec<_>>();
+    programs.sort();
+    programs
+}
+
+#[test]
+fn outputs_match_the_golden_files() {
+    let bless = env::var_os("SKIPP_BLESS").is_some();
+    let programs = corpus();
+    assert!(!programs.is_empty(), "There are no golden tests");
+
+    let mut failures = Vec::new();
+    for path in &programs {
+        for (extension, actual) in outputs(path) {
+            let golden = path.with_extension(extension);
+            if bless {
+                fs::write(&golden, &actual).unwrap();
+                continue;
+            }
+            match fs::read_to_string(&golden) {
+                Ok(expected) if expected == actual => {}
+                Ok(expected) => failures.push(format!(
+                    "{} differs:\n{}",
+                    golden.display(),
+                    diff(&expected, &actual)
+                )),
+                Err(_) => failures.push(format!("{} is missing", golden.display())),
+            }
+        }
+    }
+    assert!(
+        failures.is_empty(),
+        "{}\nIf the changes are intended, re-bless with `SKIPP_BLESS=1 cargo test --test golden`.",
+        failures.join("\n")
+    );
+}
@@ -0,0 +1,21 @@
+#include <stdbool.h>
+#include <stdio.h>
+#include <stdlib.h>
+
+/* Matches `struct Person repr(C)` in example.spp. */
+typedef struct {
+	int age;
+	bool alive;
+} Person;
+
+Person *create_person(int age, bool alive) {
+	Person *person = malloc(sizeof(Person));
+	person->age = age;
+	person->alive = alive;
+	return person;
+}
+
+void print_person(Person *person) {
+	printf("Person { age: %d, alive: %s }\n", person->age, person->alive ? "true" : "false");
+	fflush(stdout);
+}
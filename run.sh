@@ -1,2 +1,3 @@
-target/release/frontend ../example.spp
+cc -c ../person.c -o person.o
+target/release/frontend ../example.spp --link-arg person.o
 time ./example.out
\ No newline at end of file